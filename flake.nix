@@ -116,8 +116,8 @@
         "Xenova/grok-1-tokenizer"               = "grok";
       };
 
-      # Assembled model directory for the Rust CLI: o200k_base.tiktoken +
-      # {model}/tokenizer.json for each HF model.  Shared between the
+      # Assembled model directory for the Rust CLI: the tiktoken rank files
+      # + {model}/tokenizer.json for each HF model.  Shared between the
       # dev shell (TOKEN_COUNT_MODELS env) and the release package.
       rustModelsDir = { pkgs }:
         let
@@ -126,6 +126,18 @@
             url = "https://openaipublic.blob.core.windows.net/encodings/o200k_base.tiktoken";
             hash = "sha256-RGqVOMtsNI41FhINfAiwn1fDZJXirP/+WaW/iwz7Gi0=";
           };
+          cl100kData = pkgs.fetchurl {
+            url = "https://openaipublic.blob.core.windows.net/encodings/cl100k_base.tiktoken";
+            hash = "sha256-Ijkht27pm96ZW3/3OFE+7xAPtR0YyTWXoRO8/+hlsqc=";
+          };
+          p50kData = pkgs.fetchurl {
+            url = "https://openaipublic.blob.core.windows.net/encodings/p50k_base.tiktoken";
+            hash = "sha256-lLXKff9NAHZ7wlb90bJ+Wxc2HXuKX5aFR/nyPrcNIGk=";
+          };
+          r50kData = pkgs.fetchurl {
+            url = "https://openaipublic.blob.core.windows.net/encodings/r50k_base.tiktoken";
+            hash = "sha256-MGzSfwPBpxTspxCOA9ZrfcBCq+jCWLRMGZp+2YON2TA=";
+          };
           copyHfModels = builtins.concatStringsSep "\n" (
             nixpkgs.lib.mapAttrsToList (repo: dir: ''
               mkdir -p $out/${dir}
@@ -136,6 +148,9 @@
         pkgs.runCommand "tokencount-rust-models" {} ''
           mkdir -p $out
           cp ${o200kData} $out/o200k_base.tiktoken
+          cp ${cl100kData} $out/cl100k_base.tiktoken
+          cp ${p50kData} $out/p50k_base.tiktoken
+          cp ${r50kData} $out/r50k_base.tiktoken
           ${copyHfModels}
         '';
     in
@@ -0,0 +1,143 @@
+//! Added and special tokens: strings matched in the input ahead of the
+//! model proper and emitted as a single ID each (HF `added_tokens`,
+//! tiktoken's special tokens).
+
+use crate::convert::{
+    ADDED_CHAT, ADDED_LSTRIP, ADDED_NORMALIZED, ADDED_RSTRIP, ADDED_SINGLE_WORD, ADDED_SPECIAL,
+};
+use crate::frozen;
+use crate::SpecialTokens;
+
+/// An added or special token. For a `normalized` token, `content` is stored
+/// normalized, as it is matched against normalized text.
+pub(crate) struct AddedToken {
+    pub id: u32,
+    pub content: String,
+    pub flags: u8,
+}
+
+/// Added tokens indexed by first byte, longest first, so the first hit at
+/// a position is the leftmost-longest match HF's `AddedVocabulary` finds.
+pub(crate) struct AddedMatcher {
+    tokens: Vec<AddedToken>,
+    by_first_byte: Vec<Vec<usize>>,
+}
+
+impl AddedMatcher {
+    pub fn new(mut tokens: Vec<AddedToken>) -> Self {
+        tokens.retain(|t| !t.content.is_empty());
+        tokens.sort_by_key(|t| std::cmp::Reverse(t.content.len()));
+        let mut by_first_byte = vec![Vec::new(); 256];
+        for (i, t) in tokens.iter().enumerate() {
+            by_first_byte[t.content.as_bytes()[0] as usize].push(i);
+        }
+        AddedMatcher {
+            tokens,
+            by_first_byte,
+        }
+    }
+
+    /// Non-overlapping matches in `text` as `(start, end, id)`. The range
+    /// takes in the whitespace an `lstrip`/`rstrip` token absorbs; special
+    /// tokens match only if `specials` allows them.
+    pub fn find(&self, text: &str, specials: &SpecialTokens) -> Vec<(usize, usize, u32)> {
+        self.find_tokens(text, |t| {
+            t.flags & ADDED_SPECIAL == 0 || specials.allows(&t.content)
+        })
+        .into_iter()
+        .map(|(start, end, t)| (start, end, t.id))
+        .collect()
+    }
+
+    /// Byte range of the first special token in `text` that `specials`
+    /// does not allow.
+    pub fn find_disallowed(&self, text: &str, specials: &SpecialTokens) -> Option<(usize, usize)> {
+        self.find_tokens(text, |_| true)
+            .into_iter()
+            .find(|(_, _, t)| t.flags & ADDED_SPECIAL != 0 && !specials.allows(&t.content))
+            .map(|(start, end, _)| (start, end))
+    }
+
+    fn find_tokens(
+        &self,
+        text: &str,
+        accept: impl Fn(&AddedToken) -> bool,
+    ) -> Vec<(usize, usize, &AddedToken)> {
+        let mut matches = Vec::new();
+        if self.tokens.is_empty() {
+            return matches;
+        }
+        let bytes = text.as_bytes();
+        let mut last_end = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            let hit = self.by_first_byte[bytes[i] as usize]
+                .iter()
+                .map(|&k| &self.tokens[k])
+                .find(|t| {
+                    let end = i + t.content.len();
+                    bytes[i..].starts_with(t.content.as_bytes())
+                        && accept(t)
+                        && (t.flags & ADDED_SINGLE_WORD == 0 || is_single_word(text, i, end))
+                });
+            let Some(t) = hit else {
+                i += 1;
+                continue;
+            };
+            let mut start = i;
+            let mut end = i + t.content.len();
+            if t.flags & ADDED_LSTRIP != 0 {
+                start = last_end + text[last_end..start].trim_end().len();
+            }
+            if t.flags & ADDED_RSTRIP != 0 {
+                end = text.len() - text[end..].trim_start().len();
+            }
+            matches.push((start, end, t));
+            last_end = end;
+            i = end;
+        }
+        matches
+    }
+}
+
+/// Whether `text[start..end]` is not glued to a word character on either
+/// side, for `single_word` tokens.
+fn is_single_word(text: &str, start: usize, end: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    !text[..start].chars().next_back().is_some_and(is_word)
+        && !text[end..].chars().next().is_some_and(is_word)
+}
+
+/// Added-tokens section: `[count]` then `[id u32][flags u8][content]` each.
+pub(crate) fn parse_added_tokens(section: &[u8]) -> Result<Vec<AddedToken>, String> {
+    let count = frozen::get_u32(section, 0)? as usize;
+    let mut off = 4;
+    let mut tokens = Vec::new();
+    for _ in 0..count {
+        let id = frozen::get_u32(section, off)?;
+        let flags = frozen::get_u8(section, off + 4)?;
+        let (content, len) = frozen::get_str(section, off + 5)?;
+        off += 5 + len;
+        tokens.push(AddedToken { id, content, flags });
+    }
+    if off != section.len() {
+        return Err(format!("{} unread bytes", section.len() - off));
+    }
+    Ok(tokens)
+}
+
+/// One-line summary of an added-tokens section (`tokencount inspect`).
+pub(crate) fn describe_added_tokens(section: &[u8]) -> Result<String, String> {
+    let tokens = parse_added_tokens(section)?;
+    let count = |flag: u8| tokens.iter().filter(|t| t.flags & flag != 0).count();
+    let mut summary = format!(
+        "{} tokens ({} special, {} normalized",
+        tokens.len(),
+        count(ADDED_SPECIAL),
+        count(ADDED_NORMALIZED)
+    );
+    if count(ADDED_CHAT) > 0 {
+        summary += &format!(", {} chat markers", count(ADDED_CHAT));
+    }
+    Ok(summary + ")")
+}
@@ -1,202 +1,296 @@
-use unicode_normalization::UnicodeNormalization;
+use std::borrow::Cow;
 
-use crate::byte_level;
+use crate::format::{self, Header};
 use crate::frozen;
-
-enum Normalizer {
-    None,
-    Replace { pattern: String, content: String },
-    Prepend(String),
-    Nfc,
-    Sequence(Vec<Normalizer>),
+use crate::pipeline::{Piece, Pipeline};
+use crate::template::ChatTemplate;
+use crate::{SpecialTokens, TokenSpan};
+
+/// Symbol flags: whether a symbol's text takes the continuation prefix or
+/// the end-of-word suffix. Byte-fallback and unk symbols take neither.
+const PREFIXED: u8 = 1;
+const SUFFIXED: u8 = 2;
+
+/// Characters below this code point have their vocab membership cached at
+/// load. It covers ASCII, Latin-1 and the whole byte-level alphabet.
+const KNOWN_CHARS: usize = 0x180;
+
+pub struct HfTokenizer<'a> {
+    data: Cow<'a, [u8]>,
+    pipeline: Pipeline,
+    options: Options,
+    /// Bitmap of the characters below `KNOWN_CHARS` that are in the vocab.
+    known_chars: [u64; KNOWN_CHARS / 64],
+    vocab_off: usize,
+    id_tokens_off: usize,
+    merges_off: usize,
+    merge_left_off: usize,
+    merge_right_off: usize,
 }
 
-fn apply_normalizer(norm: &Normalizer, text: &str) -> String {
-    match norm {
-        Normalizer::None => text.to_string(),
-        Normalizer::Replace { pattern, content } => text.replace(pattern.as_str(), content.as_str()),
-        Normalizer::Prepend(prefix) => format!("{}{}", prefix, text),
-        Normalizer::Nfc => text.nfc().collect(),
-        Normalizer::Sequence(norms) => {
-            let mut s = text.to_string();
-            for n in norms {
-                s = apply_normalizer(n, &s);
+impl<'a> HfTokenizer<'a> {
+    pub fn from_frozen(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, String> {
+        let data = data.into();
+        let header = Header::parse_kind(&data, format::KIND_HF_BPE)?;
+        let pipeline = Pipeline::from_frozen(&header, &data)?;
+        let options = header.section(format::SEC_BPE)?;
+        let options = Options::parse(&data[options])
+            .map_err(|e| format!("{} section: {}", format::section_name(format::SEC_BPE), e))?;
+        let vocab_off = header
+            .checked_section(format::SEC_VOCAB, frozen::check_frozen_map)?
+            .start;
+        let id_tokens_off = header
+            .checked_section(format::SEC_ID_TOKENS, frozen::check_string_array)?
+            .start;
+        let merges_off = header
+            .checked_section(format::SEC_MERGES, frozen::check_frozen_map)?
+            .start;
+        let merge_left_off = header
+            .checked_section(format::SEC_MERGE_LEFT, frozen::check_frozen_set)?
+            .start;
+        let merge_right_off = header
+            .checked_section(format::SEC_MERGE_RIGHT, frozen::check_frozen_set)?
+            .start;
+
+        let mut known_chars = [0; KNOWN_CHARS / 64];
+        for ch in (0..KNOWN_CHARS as u32).filter_map(char::from_u32) {
+            let mut buf = [0u8; 4];
+            if frozen::frozen_map_get(&data[vocab_off..], ch.encode_utf8(&mut buf).as_bytes())
+                .is_some()
+            {
+                known_chars[ch as usize / 64] |= 1 << (ch as usize % 64);
             }
-            s
         }
+
+        Ok(HfTokenizer {
+            data,
+            pipeline,
+            options,
+            known_chars,
+            vocab_off,
+            id_tokens_off,
+            merges_off,
+            merge_left_off,
+            merge_right_off,
+        })
     }
-}
 
-struct SplitPattern {
-    regex: fancy_regex::Regex,
-}
+    pub fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        self.pipeline.set_special_tokens(mode);
+    }
 
-enum PreTokenizer {
-    None,
-    Sequence(Vec<PreTokenizerStep>),
-}
+    pub fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        self.pipeline.find_special_token(text)
+    }
 
-enum PreTokenizerStep {
-    Split(SplitPattern),
-    ByteLevel { table: Box<[char; 256]> },
-}
+    pub fn set_add_special_tokens(&mut self, add: bool) {
+        self.pipeline.set_add_special_tokens(add);
+    }
 
-fn apply_pre_tokenizer(pt: &PreTokenizer, text: &str) -> Vec<String> {
-    match pt {
-        PreTokenizer::None => {
-            vec![text.to_string()]
-        }
-        PreTokenizer::Sequence(steps) => {
-            let mut chunks = vec![text.to_string()];
-            for step in steps {
-                let mut next_chunks = Vec::new();
-                for chunk in &chunks {
-                    match step {
-                        PreTokenizerStep::Split(sp) => {
-                            next_chunks.extend(split_isolated(&sp.regex, chunk));
-                        }
-                        PreTokenizerStep::ByteLevel { table } => {
-                            for c in &chunks {
-                                next_chunks.push(byte_level::encode_bytes(
-                                    c.as_bytes(),
-                                    table,
-                                ));
-                            }
-                            break;
-                        }
-                    }
-                }
-                chunks = next_chunks;
-            }
-            chunks
+    pub fn chat_template(&self) -> Option<&ChatTemplate> {
+        self.pipeline.chat_template()
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
         }
+        self.pipeline.post_count(false) + self.count_text(text)
     }
-}
 
-fn split_isolated(regex: &fancy_regex::Regex, text: &str) -> Vec<String> {
-    let mut result = Vec::new();
-    let mut last_end = 0;
+    pub fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        self.pipeline.post_count(true) + self.count_text(a) + self.count_text(b)
+    }
 
-    for m in regex.find_iter(text) {
-        let m = match m {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
-        if m.start() > last_end {
-            result.push(text[last_end..m.start()].to_string());
+    /// Tokens of `text` alone, without the post-processor's.
+    fn count_text(&self, text: &str) -> usize {
+        let mut total = 0;
+        for piece in self.pipeline.pre_tokenize(text, false) {
+            match piece {
+                Piece::Added(_) => total += 1,
+                Piece::Text(chunk) => self.bpe(&chunk.text, &mut |_, _, _| total += 1),
+            }
         }
-        result.push(m.as_str().to_string());
-        last_end = m.end();
+        total
     }
-    if last_end < text.len() {
-        result.push(text[last_end..].to_string());
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets_impl(text, false)
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
     }
-    result
-}
 
-const NORM_NONE: u8 = 0;
-const NORM_REPLACE: u8 = 1;
-const NORM_PREPEND: u8 = 2;
-const NORM_NFC: u8 = 3;
-const NORM_SEQUENCE: u8 = 4;
-
-const STEP_SPLIT: u8 = 1;
-const STEP_BYTE_LEVEL: u8 = 2;
-
-pub struct HfTokenizer {
-    data: &'static [u8],
-    byte_fallback: bool,
-    post_add: usize,
-    normalizer: Normalizer,
-    pre_tokenizer: PreTokenizer,
-    vocab_off: usize,
-    vocab_count: usize,
-    merges_off: usize,
-    merge_left_off: usize,
-    merge_right_off: usize,
-}
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        self.encode_with_offsets_impl(text, true)
+    }
 
-impl HfTokenizer {
-    pub fn from_frozen(data: &'static [u8]) -> Result<Self, String> {
-        let mut off = 0;
-        let byte_fallback = data[off] != 0;
-        off += 1;
-        let post_add = frozen::read_u32(data, off) as usize;
-        off += 4;
-        let (normalizer, norm_len) = deserialize_normalizer(data, off)?;
-        off += norm_len;
-        let (pre_tokenizer, pt_len) = deserialize_pre_tokenizer(data, off)?;
-        off += pt_len;
-        let vocab_count = frozen::read_u32(data, off) as usize;
-        off += 4;
-        let vocab_off = off;
-        off += vocab_count * 4;
-        let merges_off = off;
-        off += frozen::frozen_map_byte_len(&data[off..]);
-        let merge_left_off = off;
-        off += frozen::frozen_set_byte_len(&data[off..]);
-        let merge_right_off = off;
+    pub fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        self.pipeline.encode_pair(
+            |spans| self.encode_text(a, false, spans),
+            |spans| self.encode_text(b, false, spans),
+        )
+    }
 
-        Ok(HfTokenizer {
-            data,
-            byte_fallback,
-            post_add,
-            normalizer,
-            pre_tokenizer,
-            vocab_off,
-            vocab_count,
-            merges_off,
-            merge_left_off,
-            merge_right_off,
+    fn encode_with_offsets_impl(&self, text: &str, track_offsets: bool) -> Vec<TokenSpan> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.pipeline.encode_single(text.len(), |spans| {
+            self.encode_text(text, track_offsets, spans)
         })
     }
 
-    pub fn count_tokens(&self, text: &str) -> usize {
-        if text.is_empty() { return 0; }
-        let normalized = apply_normalizer(&self.normalizer, text);
-        let chunks = apply_pre_tokenizer(&self.pre_tokenizer, &normalized);
-        let mut total = self.post_add;
-        for chunk in &chunks {
-            if !chunk.is_empty() { total += self.bpe_count(chunk); }
+    /// Encode `text` alone into `spans`, without the post-processor's tokens.
+    fn encode_text(&self, text: &str, track_offsets: bool, spans: &mut Vec<TokenSpan>) {
+        let vocab = &self.data[self.vocab_off..];
+        for piece in self.pipeline.pre_tokenize(text, track_offsets) {
+            let chunk = match piece {
+                Piece::Added(span) => {
+                    spans.push(span);
+                    continue;
+                }
+                Piece::Text(chunk) => chunk,
+            };
+            // Like HF's BPE without an unk token, pieces missing from the
+            // vocab are dropped.
+            self.bpe(&chunk.text, &mut |tok, s, e| {
+                if let Some(id) = frozen::frozen_map_get(vocab, tok) {
+                    let (start, end) = chunk.original_range(s, e);
+                    spans.push(TokenSpan { id, start, end });
+                }
+            });
         }
-        total
     }
 
-    fn bpe_count(&self, chunk: &str) -> usize {
-        let initial: Vec<String> = if self.byte_fallback {
-            self.initial_tokens(chunk)
+    pub fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        let id_tokens = &self.data[self.id_tokens_off..];
+        let tokens = ids
+            .iter()
+            .filter_map(|&id| frozen::string_array_get(id_tokens, id as usize))
+            .map(|t| t.to_vec())
+            .collect();
+        self.pipeline.decode(tokens)
+    }
+
+    /// Run BPE over one pre-tokenized chunk, calling `emit` with the bytes
+    /// of each final token and the byte range of `chunk` it covers, in order.
+    fn bpe<F: FnMut(&[u8], usize, usize)>(&self, chunk: &str, emit: &mut F) {
+        // The raw word, without the subword prefix or suffix.
+        if self.options.ignore_merges
+            && frozen::frozen_map_get(&self.data[self.vocab_off..], chunk.as_bytes()).is_some()
+        {
+            emit(chunk.as_bytes(), 0, chunk.len());
+            return;
+        }
+        let (initial, flags, starts) = self.initial_tokens(chunk);
+        if initial.len() > 512 {
+            self.bpe_chunked(&initial, &flags, &starts, emit);
         } else {
-            chunk.chars().map(|c| c.to_string()).collect()
-        };
-        let n = initial.len();
-        if n <= 1 { return n; }
-        if n > 512 {
-            return self.bpe_count_chunked(&initial);
+            self.bpe_merge(&initial, &flags, &starts, emit);
         }
-        self.bpe_merge_count(&initial)
     }
 
-    fn initial_tokens(&self, chunk: &str) -> Vec<String> {
+    /// Split a chunk into its initial symbols, like HF's `merge_word`: one
+    /// per character, `<0xNN>` byte tokens for characters missing from a
+    /// byte-fallback vocab, and otherwise the unk token (one per run of
+    /// missing characters with `fuse_unk`), or nothing when there is none.
+    /// Returns each symbol with its flags and its start offset in `chunk`,
+    /// plus a trailing `chunk.len()`.
+    fn initial_tokens(&self, chunk: &str) -> (Vec<String>, Vec<u8>, Vec<usize>) {
+        let vocab = &self.data[self.vocab_off..];
+        let options = &self.options;
         let mut tokens = Vec::new();
-        for ch in chunk.chars() {
-            if self.vocab_contains_char(ch) {
-                tokens.push(ch.to_string());
-            } else {
-                let mut buf = [0u8; 4];
-                let bytes = ch.encode_utf8(&mut buf).as_bytes();
-                for &b in bytes {
-                    tokens.push(format!("<0x{:02X}>", b));
+        let mut flags = Vec::new();
+        let mut starts = Vec::new();
+        let mut in_unk = false;
+        for (pos, ch) in chunk.char_indices() {
+            let raw = &chunk[pos..pos + ch.len_utf8()];
+            let mut flag = 0;
+            if pos > 0 && !options.prefix.is_empty() {
+                flag |= PREFIXED;
+            }
+            if pos + raw.len() == chunk.len() && !options.suffix.is_empty() {
+                flag |= SUFFIXED;
+            }
+            let text = self.symbol_text(raw.as_bytes(), flag);
+            let known = match ch as usize {
+                c if c < KNOWN_CHARS && flag == 0 => {
+                    self.known_chars[c / 64] & (1 << (c % 64)) != 0
+                }
+                _ => frozen::frozen_map_get(vocab, &text).is_some(),
+            };
+            if known {
+                tokens.push(raw.to_string());
+                flags.push(flag);
+                starts.push(pos);
+                in_unk = false;
+                continue;
+            }
+            let byte_tokens: Vec<String> = text.iter().map(|b| format!("<0x{:02X}>", b)).collect();
+            if self.pipeline.byte_fallback
+                && byte_tokens
+                    .iter()
+                    .all(|t| frozen::frozen_map_get(vocab, t.as_bytes()).is_some())
+            {
+                // The affixes are split into bytes too; their tokens get
+                // empty ranges at the character's edges.
+                let prefix_len = if flag & PREFIXED != 0 {
+                    options.prefix.len()
+                } else {
+                    0
+                };
+                for (k, token) in byte_tokens.into_iter().enumerate() {
+                    tokens.push(token);
+                    flags.push(0);
+                    starts.push(pos + k.saturating_sub(prefix_len).min(raw.len()));
+                }
+                in_unk = false;
+                continue;
+            }
+            if let Some(unk) = &options.unk_token {
+                if !(in_unk && options.fuse_unk) {
+                    tokens.push(unk.clone());
+                    flags.push(0);
+                    starts.push(pos);
                 }
+                in_unk = true;
             }
         }
-        tokens
+        starts.push(chunk.len());
+        (tokens, flags, starts)
+    }
+
+    /// A symbol's text: its characters plus the continuation prefix and
+    /// end-of-word suffix its flags call for. Borrows when there are none.
+    #[inline]
+    fn symbol_text<'s>(&self, raw: &'s [u8], flags: u8) -> Cow<'s, [u8]> {
+        if flags == 0 {
+            return Cow::Borrowed(raw);
+        }
+        let prefix = if flags & PREFIXED != 0 {
+            self.options.prefix.as_bytes()
+        } else {
+            &[]
+        };
+        let suffix = if flags & SUFFIXED != 0 {
+            self.options.suffix.as_bytes()
+        } else {
+            &[]
+        };
+        Cow::Owned([prefix, raw, suffix].concat())
     }
 
-    fn bpe_count_chunked(&self, tokens: &[String]) -> usize {
+    fn bpe_chunked<F: FnMut(&[u8], usize, usize)>(
+        &self,
+        tokens: &[String],
+        flags: &[u8],
+        starts: &[usize],
+        emit: &mut F,
+    ) {
         let merge_left = &self.data[self.merge_left_off..];
         let merge_right = &self.data[self.merge_right_off..];
         let n = tokens.len();
-        let mut total = 0;
         let mut start = 0;
         let target_chunk = 256;
         let mut i = target_chunk.min(n.saturating_sub(1));
@@ -205,29 +299,56 @@ impl HfTokenizer {
             let scan_end = n.min(i + target_chunk);
             let mut found = false;
             for j in scan_start..scan_end {
-                if j == 0 { continue; }
-                if !frozen::frozen_set_contains(merge_left, tokens[j - 1].as_bytes())
-                    || !frozen::frozen_set_contains(merge_right, tokens[j].as_bytes())
+                if j == 0 {
+                    continue;
+                }
+                let left = self.symbol_text(tokens[j - 1].as_bytes(), flags[j - 1]);
+                let right = self.symbol_text(tokens[j].as_bytes(), flags[j]);
+                if !frozen::frozen_set_contains(merge_left, &left)
+                    || !frozen::frozen_set_contains(merge_right, &right)
                 {
-                    total += self.bpe_merge_count(&tokens[start..j]);
+                    self.bpe_merge(
+                        &tokens[start..j],
+                        &flags[start..j],
+                        &starts[start..=j],
+                        emit,
+                    );
                     start = j;
                     i = j + target_chunk;
                     found = true;
                     break;
                 }
             }
-            if !found { break; }
+            if !found {
+                break;
+            }
         }
         if start < n {
-            total += self.bpe_merge_count(&tokens[start..]);
+            self.bpe_merge(&tokens[start..], &flags[start..], &starts[start..], emit);
         }
-        total
     }
 
-    fn bpe_merge_count(&self, initial: &[String]) -> usize {
-        if initial.is_empty() { return 0; }
+    /// `starts` holds the chunk offset of each initial token plus one
+    /// trailing end offset.
+    fn bpe_merge<F: FnMut(&[u8], usize, usize)>(
+        &self,
+        initial: &[String],
+        flags: &[u8],
+        starts: &[usize],
+        emit: &mut F,
+    ) {
+        if initial.is_empty() {
+            return;
+        }
         let n = initial.len();
-        if n == 1 { return 1; }
+        if n == 1 {
+            emit(
+                &self.symbol_text(initial[0].as_bytes(), flags[0]),
+                starts[0],
+                starts[1],
+            );
+            return;
+        }
 
         let merges_table = &self.data[self.merges_off..];
 
@@ -247,166 +368,261 @@ impl HfTokenizer {
         let mut next: Vec<usize> = (1..=n).collect();
         let mut prev: Vec<usize> = Vec::with_capacity(n);
         prev.push(usize::MAX);
-        for i in 1..n { prev.push(i - 1); }
+        for i in 1..n {
+            prev.push(i - 1);
+        }
         let mut alive = vec![true; n];
         let mut gen: Vec<u32> = vec![0; n];
+        let mut flags = flags.to_vec();
         let mut heap = std::collections::BinaryHeap::new();
 
-        let pair_rank = |i: usize, parts: &[(usize, usize)], next: &[usize]| -> Option<u64> {
-            let j = next[i];
-            if j >= n { return None; }
-            frozen::frozen_map_get_pair(
-                merges_table,
-                &buf[parts[i].0..parts[i].1],
-                &buf[parts[j].0..parts[j].1],
-            ).map(|r| r as u64)
-        };
+        let pair_rank =
+            |i: usize, parts: &[(usize, usize)], flags: &[u8], next: &[usize]| -> Option<u64> {
+                let j = next[i];
+                if j >= n {
+                    return None;
+                }
+                frozen::frozen_map_get_pair(
+                    merges_table,
+                    &self.symbol_text(&buf[parts[i].0..parts[i].1], flags[i]),
+                    &self.symbol_text(&buf[parts[j].0..parts[j].1], flags[j]),
+                )
+                .map(|r| r as u64)
+            };
 
         for i in 0..n - 1 {
-            if let Some(rank) = pair_rank(i, &parts, &next) {
+            if let Some(rank) = pair_rank(i, &parts, &flags, &next) {
                 heap.push(std::cmp::Reverse((rank, i, 0u32)));
             }
         }
 
-        let mut count = n;
-
         while let Some(std::cmp::Reverse((rank, i, g))) = heap.pop() {
-            if !alive[i] || gen[i] != g { continue; }
+            if !alive[i] || gen[i] != g {
+                continue;
+            }
             let j = next[i];
-            if j >= n || !alive[j] { continue; }
-
-            let current_rank = match frozen::frozen_map_get_pair(
-                merges_table,
-                &buf[parts[i].0..parts[i].1],
-                &buf[parts[j].0..parts[j].1],
-            ) {
-                Some(r) => r as u64,
+            if j >= n || !alive[j] {
+                continue;
+            }
+
+            let current_rank = match pair_rank(i, &parts, &flags, &next) {
+                Some(r) => r,
                 None => continue,
             };
-            if current_rank != rank { continue; }
+            if current_rank != rank {
+                continue;
+            }
 
+            // Like HF's `a + b[prefix_len..]`: the merged symbol keeps the
+            // left one's prefix and the right one's suffix.
             parts[i].1 = parts[j].1;
+            flags[i] = (flags[i] & PREFIXED) | (flags[j] & SUFFIXED);
             gen[i] += 1;
 
             alive[j] = false;
             let k = next[j];
             next[i] = k;
-            if k < n { prev[k] = i; }
-            count -= 1;
+            if k < n {
+                prev[k] = i;
+            }
 
             if prev[i] != usize::MAX && alive[prev[i]] {
                 let p = prev[i];
-                if let Some(r) = pair_rank(p, &parts, &next) {
+                if let Some(r) = pair_rank(p, &parts, &flags, &next) {
                     heap.push(std::cmp::Reverse((r, p, gen[p])));
                 }
             }
             if next[i] < n {
-                if let Some(r) = pair_rank(i, &parts, &next) {
+                if let Some(r) = pair_rank(i, &parts, &flags, &next) {
                     heap.push(std::cmp::Reverse((r, i, gen[i])));
                 }
             }
         }
 
-        count
+        let mut i = 0;
+        while i < n {
+            emit(
+                &self.symbol_text(&buf[parts[i].0..parts[i].1], flags[i]),
+                starts[i],
+                starts[next[i]],
+            );
+            i = next[i];
+        }
     }
+}
 
-    fn vocab_contains_char(&self, ch: char) -> bool {
-        if self.vocab_count == 0 {
-            return false;
-        }
-        let target = ch as u32;
-        let base = self.vocab_off;
-        let mut lo = 0usize;
-        let mut hi = self.vocab_count;
-        while lo < hi {
-            let mid = lo + (hi - lo) / 2;
-            let cp = frozen::read_u32(self.data, base + mid * 4);
-            if cp == target {
-                return true;
-            } else if cp < target {
-                lo = mid + 1;
-            } else {
-                hi = mid;
-            }
-        }
-        false
+/// Model options from the `bpe` section.
+struct Options {
+    ignore_merges: bool,
+    fuse_unk: bool,
+    unk_token: Option<String>,
+    prefix: String,
+    suffix: String,
+}
+
+impl Options {
+    fn parse(section: &[u8]) -> Result<Self, String> {
+        let ignore_merges = frozen::get_u8(section, 0)? != 0;
+        let fuse_unk = frozen::get_u8(section, 1)? != 0;
+        let mut off = 2;
+        let mut next_str = || -> Result<String, String> {
+            let (s, len) = frozen::get_str(section, off)?;
+            off += len;
+            Ok(s)
+        };
+        let unk_token = Some(next_str()?).filter(|s| !s.is_empty());
+        let prefix = next_str()?;
+        let suffix = next_str()?;
+        Ok(Options {
+            ignore_merges,
+            fuse_unk,
+            unk_token,
+            prefix,
+            suffix,
+        })
     }
 }
 
-fn deserialize_normalizer(data: &[u8], off: usize) -> Result<(Normalizer, usize), String> {
-    let tag = data[off];
-    let mut pos = off + 1;
-    match tag {
-        NORM_NONE => Ok((Normalizer::None, 1)),
-        NORM_REPLACE => {
-            let (pattern, len1) = read_length_prefixed_str(data, pos)?;
-            pos += len1;
-            let (content, len2) = read_length_prefixed_str(data, pos)?;
-            pos += len2;
-            Ok((Normalizer::Replace { pattern, content }, pos - off))
-        }
-        NORM_PREPEND => {
-            let (prepend, len) = read_length_prefixed_str(data, pos)?;
-            pos += len;
-            Ok((Normalizer::Prepend(prepend), pos - off))
-        }
-        NORM_NFC => Ok((Normalizer::Nfc, 1)),
-        NORM_SEQUENCE => {
-            let count = frozen::read_u32(data, pos) as usize;
-            pos += 4;
-            let mut norms = Vec::with_capacity(count);
-            for _ in 0..count {
-                let (n, len) = deserialize_normalizer(data, pos)?;
-                pos += len;
-                norms.push(n);
-            }
-            if norms.is_empty() {
-                Ok((Normalizer::None, pos - off))
-            } else {
-                Ok((Normalizer::Sequence(norms), pos - off))
-            }
-        }
-        _ => Err(format!("unknown normalizer tag: {}", tag)),
+/// One-line summary of a `bpe` section, for `inspect`.
+pub(crate) fn describe_options(section: &[u8]) -> Result<String, String> {
+    let options = Options::parse(section)?;
+    let mut parts = Vec::new();
+    if options.ignore_merges {
+        parts.push("ignore merges".to_string());
+    }
+    if let Some(unk) = &options.unk_token {
+        let fused = if options.fuse_unk { " (fused)" } else { "" };
+        parts.push(format!("unk {:?}{}", unk, fused));
+    }
+    if !options.prefix.is_empty() {
+        parts.push(format!("prefix {:?}", options.prefix));
+    }
+    if !options.suffix.is_empty() {
+        parts.push(format!("suffix {:?}", options.suffix));
     }
+    Ok(if parts.is_empty() {
+        "defaults".to_string()
+    } else {
+        parts.join(", ")
+    })
 }
 
-fn deserialize_pre_tokenizer(data: &[u8], off: usize) -> Result<(PreTokenizer, usize), String> {
-    let mut pos = off;
-    let step_count = frozen::read_u32(data, pos) as usize;
-    pos += 4;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert;
 
-    if step_count == 0 {
-        return Ok((PreTokenizer::None, pos - off));
+    fn build(json: &str) -> HfTokenizer<'static> {
+        HfTokenizer::from_frozen(convert::build_hf_frozen("test", json).unwrap()).unwrap()
     }
 
-    let mut steps = Vec::with_capacity(step_count);
-    for _ in 0..step_count {
-        let step_tag = data[pos];
-        pos += 1;
-        match step_tag {
-            STEP_SPLIT => {
-                let (pattern, len) = read_length_prefixed_str(data, pos)?;
-                pos += len;
-                let regex = fancy_regex::Regex::new(&pattern)
-                    .map_err(|e| format!("invalid pre-tokenizer regex: {e}"))?;
-                steps.push(PreTokenizerStep::Split(SplitPattern { regex }));
-            }
-            STEP_BYTE_LEVEL => {
-                steps.push(PreTokenizerStep::ByteLevel {
-                    table: Box::new(byte_level::byte_to_char_table()),
-                });
-            }
-            _ => return Err(format!("unknown pre-tokenizer step tag: {}", step_tag)),
-        }
+    #[test]
+    fn nfkc_offsets_cover_only_the_rewritten_character() {
+        let tok = build(
+            r#"{
+                "added_tokens": [],
+                "normalizer": {"type": "NFKC"},
+                "pre_tokenizer": {"type": "WhitespaceSplit"},
+                "model": {"type": "BPE", "vocab": {"a": 0, "fi": 1, "f": 2, "i": 3, "ガ": 4}, "merges": ["f i"]}
+            }"#,
+        );
+        let spans = |text: &str| -> Vec<_> {
+            tok.encode_with_offsets(text)
+                .iter()
+                .map(|t| (t.id, t.start, t.end))
+                .collect()
+        };
+        // The ligature becomes "fi" without taking the space before it.
+        assert_eq!(spans("a \u{fb01}"), [(0, 0, 1), (1, 2, 5)]);
+        // A halfwidth voiced mark still composes with the kana before it.
+        assert_eq!(spans("\u{ff76}\u{ff9e}"), [(4, 0, 6)]);
     }
 
-    Ok((PreTokenizer::Sequence(steps), pos - off))
-}
+    #[test]
+    fn strip_accents_removes_every_combining_mark() {
+        // U+0301 is nonspacing (Mn), U+093E spacing (Mc), U+20DD enclosing (Me).
+        let with_normalizer = |normalizer: &str| {
+            build(&format!(
+                r#"{{
+                    "added_tokens": [],
+                    "normalizer": {normalizer},
+                    "pre_tokenizer": {{"type": "WhitespaceSplit"}},
+                    "model": {{
+                        "type": "BPE",
+                        "vocab": {{"e": 0, "\u0301": 1, "क": 2, "\u093e": 3, "x": 4, "\u20dd": 5}},
+                        "merges": []
+                    }}
+                }}"#
+            ))
+        };
+        let strip = with_normalizer(
+            r#"{"type": "Sequence", "normalizers": [{"type": "NFD"}, {"type": "StripAccents"}]}"#,
+        );
+        assert_eq!(strip.encode("\u{e9} \u{915}\u{93e} x\u{20dd}"), [0, 2, 4]);
+        // BertNormalizer strips only nonspacing marks.
+        let bert = with_normalizer(
+            r#"{"type": "BertNormalizer", "strip_accents": true, "lowercase": false}"#,
+        );
+        assert_eq!(
+            bert.encode("\u{e9} \u{915}\u{93e} x\u{20dd}"),
+            [0, 2, 3, 4, 5]
+        );
+    }
 
-fn read_length_prefixed_str(data: &[u8], off: usize) -> Result<(String, usize), String> {
-    let len = frozen::read_u32(data, off) as usize;
-    let s = std::str::from_utf8(&data[off + 4..off + 4 + len])
-        .map_err(|e| format!("invalid UTF-8 in frozen blob: {e}"))?
-        .to_string();
-    Ok((s, 4 + len))
+    #[test]
+    fn digits_split_every_numeric_character() {
+        let tok = build(
+            r#"{
+                "added_tokens": [],
+                "pre_tokenizer": {"type": "Digits", "individual_digits": true},
+                "model": {"type": "BPE", "vocab": {"1": 0, "2": 1, "12": 2, "١": 3, "٢": 4, "١٢": 5}, "merges": ["1 2", "١ ٢"]}
+            }"#,
+        );
+        assert_eq!(tok.encode("12"), [0, 1]);
+        assert_eq!(tok.encode("\u{661}\u{662}"), [3, 4]);
+    }
+
+    fn tokenizer(ignore_merges: bool) -> HfTokenizer<'static> {
+        build(&format!(
+            r#"{{
+                "added_tokens": [],
+                "pre_tokenizer": {{"type": "WhitespaceSplit"}},
+                "model": {{
+                    "type": "BPE",
+                    "vocab": {{"h": 0, "e": 1, "l": 2, "o": 3, "he": 4, "ll": 5, "hello": 6}},
+                    "merges": ["h e", "l l"],
+                    "ignore_merges": {ignore_merges}
+                }}
+            }}"#
+        ))
+    }
+
+    #[test]
+    fn ignore_merges_takes_whole_word_vocab_hits() {
+        // The merges alone stop at "he" + "ll" + "o".
+        assert_eq!(tokenizer(false).encode("hello hell"), [4, 5, 3, 4, 5]);
+        assert_eq!(tokenizer(false).count_tokens("hello hell"), 5);
+        // "hello" is in the vocab, so it is one token; "hell" is not.
+        assert_eq!(tokenizer(true).encode("hello hell"), [6, 4, 5]);
+        assert_eq!(tokenizer(true).count_tokens("hello hell"), 3);
+    }
+
+    #[test]
+    fn ignore_merges_looks_up_words_without_the_suffix() {
+        let tok = build(
+            r#"{
+                "added_tokens": [],
+                "pre_tokenizer": {"type": "WhitespaceSplit"},
+                "model": {
+                    "type": "BPE",
+                    "vocab": {"a": 0, "a</w>": 1, "b</w>": 2, "ab": 3},
+                    "merges": [],
+                    "ignore_merges": true,
+                    "end_of_word_suffix": "</w>"
+                }
+            }"#,
+        );
+        // Whole words are found as written, one-character ones included.
+        assert_eq!(tok.encode("a ab b"), [0, 3, 2]);
+    }
 }
@@ -30,3 +30,21 @@ pub fn encode_bytes(input: &[u8], table: &[char; 256]) -> String {
     }
     out
 }
+
+/// Inverse of [`byte_to_char_table`] for a single character.
+pub fn char_to_byte(c: char) -> Option<u8> {
+    let c = c as u32;
+    match c {
+        0x21..=0x7E | 0xA1..=0xAC | 0xAE..=0xFF => Some(c as u8),
+        0x100..=0x120 => Some((c - 0x100) as u8), // 0x00..=0x20
+        0x121..=0x142 => Some((c - 0x121 + 0x7F) as u8), // 0x7F..=0xA0
+        0x143 => Some(0xAD),
+        _ => None,
+    }
+}
+
+/// Decode a GPT-2 unicode string back to raw bytes, or `None` if it contains
+/// a character outside the byte-level alphabet.
+pub fn decode_str(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(char_to_byte).collect()
+}
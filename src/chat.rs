@@ -0,0 +1,622 @@
+//! Token counts for chat API payloads: OpenAI Chat Completions and
+//! Anthropic Messages request bodies.
+//!
+//! A request costs more than its message text: each provider frames every
+//! message (role headers, separators) and primes the reply. Neither
+//! publishes the exact framing, so it is estimated the way each is usually
+//! counted:
+//!
+//! - OpenAI, after the cookbook's `num_tokens_from_messages`: 3 tokens per
+//!   message plus its role, 1 more for a `name`, and 3 to prime the reply.
+//! - Anthropic, as its text-completions prompt: each turn is introduced by
+//!   `"\n\nHuman: "` or `"\n\nAssistant: "`, the system prompt leads
+//!   unframed, and the request ends with `"\n\nAssistant:"`.
+//!
+//! Tool calls count their name and arguments, and tool results their
+//! content. Images and other non-text parts are rejected rather than
+//! silently counted as nothing. Tool definitions sent with the request are
+//! counted as [`crate::tools`] does.
+//!
+//! Models with a chat template (open-weight models frozen with their
+//! `tokenizer_config.json`) are counted on the prompt the template renders
+//! instead: the payload is converted to Hugging Face messages, and each
+//! message is charged the tokens of the prompt between where the rendering
+//! of the messages before it ends and where its own does.
+
+use serde_json::{json, Value};
+
+use crate::template::ChatTemplate;
+use crate::{tools, Error, Tokenizer};
+
+/// OpenAI's per-message framing (`<|start|>{role}<|message|>...<|end|>`).
+const OPENAI_TOKENS_PER_MESSAGE: usize = 3;
+/// OpenAI's extra token for a message with a `name`.
+const OPENAI_TOKENS_PER_NAME: usize = 1;
+/// OpenAI's reply priming (`<|start|>assistant<|message|>`).
+const OPENAI_REPLY_PRIMING: usize = 3;
+
+/// Which API a payload is written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatFormat {
+    /// Chat Completions: `{"messages": [...]}`, the system prompt a message.
+    OpenAi,
+    /// Messages: `{"system": ..., "messages": [...]}`, with content blocks.
+    Anthropic,
+}
+
+/// Tokens of one message of a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCount {
+    pub role: String,
+    /// Tokens of the message's own text: content, names, tool calls and
+    /// tool results.
+    pub content: usize,
+    /// Tokens the provider's framing adds around it.
+    pub framing: usize,
+}
+
+impl MessageCount {
+    pub fn total(&self) -> usize {
+        self.content + self.framing
+    }
+}
+
+/// Tokens of a whole payload, message by message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatCount {
+    pub messages: Vec<MessageCount>,
+    /// Tokens outside any message: the reply priming, or what a chat
+    /// template adds after the last message.
+    pub overhead: usize,
+    /// Tokens of the request's tool definitions.
+    pub tools: usize,
+}
+
+impl ChatCount {
+    pub fn total(&self) -> usize {
+        self.messages.iter().map(MessageCount::total).sum::<usize>() + self.overhead + self.tools
+    }
+}
+
+/// Count a chat payload: a request body with a `messages` array, or the
+/// bare array. Text is counted with `tok` as configured, so turn off
+/// [`Tokenizer::set_add_special_tokens`] first for models that would add a
+/// BOS to every piece.
+pub fn count_chat(
+    tok: &dyn Tokenizer,
+    format: ChatFormat,
+    payload: &Value,
+) -> Result<ChatCount, Error> {
+    let messages = match payload {
+        Value::Array(messages) => messages,
+        Value::Object(body) => body
+            .get("messages")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("expected a \"messages\" array"))?,
+        _ => return Err(invalid("expected a JSON object or array")),
+    };
+    if let Some(template) = tok.chat_template() {
+        let messages = match format {
+            ChatFormat::OpenAi => openai_messages(messages)?,
+            ChatFormat::Anthropic => anthropic_messages(payload.get("system"), messages)?,
+        };
+        let tools = tools::template_tools(format, payload)?;
+        return count_templated(tok, template, &messages, tools.as_ref());
+    }
+    let mut count = match format {
+        ChatFormat::OpenAi => count_openai(tok, messages)?,
+        ChatFormat::Anthropic => count_anthropic(tok, payload.get("system"), messages)?,
+    };
+    if payload
+        .get("tools")
+        .or_else(|| payload.get("functions"))
+        .is_some()
+    {
+        count.tools = tools::count_tools(tok, format, payload)?.total();
+    }
+    Ok(count)
+}
+
+/// A payload message converted for a chat template, with the text of its
+/// own that counts as content.
+struct TemplateMessage {
+    message: Value,
+    text: Vec<String>,
+}
+
+/// Count messages through `template`. The whole prompt is tokenized once;
+/// message `k` is charged the tokens that start between the ends of the
+/// prompts rendered from the first `k - 1` and the first `k` messages, and
+/// the tokens after the last message (the generation prompt) are the
+/// overhead. `tools` are charged what they add to the whole prompt.
+fn count_templated(
+    tok: &dyn Tokenizer,
+    template: &ChatTemplate,
+    messages: &[TemplateMessage],
+    tools: Option<&Value>,
+) -> Result<ChatCount, Error> {
+    let hf: Vec<Value> = messages.iter().map(|m| m.message.clone()).collect();
+    let prompt = template.render(&hf, None, true)?;
+    // Where each message's rendering ends in the prompt. Templates may
+    // rewrite earlier turns (dropping past reasoning, say), so a prefix
+    // rendering only counts as far as it agrees with the prompt.
+    let mut ends = Vec::with_capacity(hf.len());
+    let mut end = 0;
+    for k in 0..hf.len() {
+        let rendered = template.render(&hf[..=k], None, false)?;
+        let agreed = common_prefix(&rendered, &prompt);
+        end = end.max(agreed);
+        ends.push(end);
+    }
+    let mut totals = vec![0; hf.len()];
+    let mut overhead = 0;
+    for token in tok.encode_with_offsets(&prompt) {
+        match ends.partition_point(|&end| end <= token.start) {
+            k if k < totals.len() => totals[k] += 1,
+            _ => overhead += 1,
+        }
+    }
+    let counts = messages
+        .iter()
+        .zip(totals)
+        .map(|(message, total)| {
+            let content = message
+                .text
+                .iter()
+                .map(|t| tok.count_tokens(t))
+                .sum::<usize>()
+                .min(total);
+            let role = message.message["role"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            MessageCount {
+                role,
+                content,
+                framing: total - content,
+            }
+        })
+        .collect();
+    let tools = match tools {
+        Some(tools) => {
+            let with_tools = tok.count_tokens(&template.render(&hf, Some(tools), true)?);
+            with_tools.saturating_sub(tok.count_tokens(&prompt))
+        }
+        None => 0,
+    };
+    Ok(ChatCount {
+        messages: counts,
+        overhead,
+        tools,
+    })
+}
+
+/// Length in bytes of the longest common prefix of `a` and `b`, backed up
+/// to a char boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    let mut n = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !a.is_char_boundary(n) {
+        n -= 1;
+    }
+    n
+}
+
+/// OpenAI messages as Hugging Face ones: content parts joined into one
+/// string, legacy `function_call`s as `tool_calls`, and call arguments
+/// parsed from their JSON string, as templates expect.
+fn openai_messages(messages: &[Value]) -> Result<Vec<TemplateMessage>, Error> {
+    let mut out = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        let context = |e: String| invalid(format!("message {}: {}", i, e));
+        let role = match role(message).map_err(context)? {
+            "developer" => "system",
+            role => role,
+        };
+        let mut text = Vec::new();
+        let content = match message.get("content") {
+            None | Some(Value::Null) => Value::Null,
+            Some(Value::String(s)) => Value::from(s.as_str()),
+            Some(Value::Array(parts)) => {
+                let parts = parts
+                    .iter()
+                    .map(|part| match part_type(part)? {
+                        kind @ ("text" | "refusal") => text_field(part, kind),
+                        other => Err(format!("unsupported content part type {:?}", other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(context)?;
+                Value::from(parts.join("\n"))
+            }
+            Some(_) => {
+                return Err(context(
+                    "content must be a string or an array of parts".to_string(),
+                ))
+            }
+        };
+        text.extend(content.as_str().map(str::to_string));
+        let mut hf = json!({ "role": role, "content": content });
+        for key in ["name", "tool_call_id"] {
+            if let Some(value) = message.get(key) {
+                hf[key] = value.clone();
+            }
+        }
+        text.extend(
+            message
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        );
+        let calls: Vec<(Option<&Value>, &Value)> = message
+            .get("function_call")
+            .map(|f| (None, f))
+            .into_iter()
+            .chain(
+                message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|call| Some((call.get("id"), call.get("function")?))),
+            )
+            .collect();
+        if !calls.is_empty() {
+            let mut tool_calls = Vec::new();
+            for (id, function) in calls {
+                let name = function.get("name").and_then(Value::as_str).unwrap_or("");
+                let arguments = function
+                    .get("arguments")
+                    .and_then(Value::as_str)
+                    .unwrap_or("");
+                text.push(name.to_string());
+                text.push(arguments.to_string());
+                let arguments =
+                    serde_json::from_str(arguments).unwrap_or_else(|_| Value::from(arguments));
+                let mut call = json!({ "type": "function", "function": { "name": name, "arguments": arguments } });
+                if let Some(id) = id {
+                    call["id"] = id.clone();
+                }
+                tool_calls.push(call);
+            }
+            hf["tool_calls"] = Value::Array(tool_calls);
+        }
+        out.push(TemplateMessage { message: hf, text });
+    }
+    Ok(out)
+}
+
+/// Anthropic messages as Hugging Face ones: the system prompt as a system
+/// message, `tool_use` blocks as `tool_calls`, `thinking` as
+/// `reasoning_content`, and each `tool_result` as a `tool` message ahead
+/// of the rest of its user turn.
+fn anthropic_messages(
+    system: Option<&Value>,
+    messages: &[Value],
+) -> Result<Vec<TemplateMessage>, Error> {
+    let mut out = Vec::new();
+    match system {
+        None | Some(Value::Null) => {}
+        Some(system) => {
+            let text = anthropic_text(system).map_err(|e| invalid(format!("system: {}", e)))?;
+            out.push(TemplateMessage {
+                message: json!({ "role": "system", "content": text }),
+                text: vec![text],
+            });
+        }
+    }
+    for (i, message) in messages.iter().enumerate() {
+        let context = |e: String| invalid(format!("message {}: {}", i, e));
+        let role = role(message).map_err(context)?;
+        if role != "user" && role != "assistant" {
+            return Err(context(format!("unsupported role {:?}", role)));
+        }
+        let content = message
+            .get("content")
+            .ok_or_else(|| context("missing content".to_string()))?;
+        let blocks = match content {
+            Value::String(_) => std::slice::from_ref(content),
+            Value::Array(blocks) => blocks.as_slice(),
+            _ => {
+                return Err(context(
+                    "content must be a string or an array of blocks".to_string(),
+                ))
+            }
+        };
+        let mut texts = Vec::new();
+        let mut reasoning = Vec::new();
+        let mut tool_calls = Vec::new();
+        let mut text = Vec::new();
+        let mut tool_results = 0;
+        for block in blocks {
+            if let Value::String(s) = block {
+                texts.push(s.clone());
+                continue;
+            }
+            match part_type(block).map_err(context)? {
+                "text" => texts.push(text_field(block, "text").map_err(context)?.to_string()),
+                "thinking" => {
+                    reasoning.push(text_field(block, "thinking").map_err(context)?.to_string())
+                }
+                "tool_use" => {
+                    let name = text_field(block, "name").map_err(context)?;
+                    let input = block.get("input").cloned().unwrap_or(Value::Null);
+                    text.push(name.to_string());
+                    text.push(input.to_string());
+                    let mut call = json!({ "type": "function", "function": { "name": name, "arguments": input } });
+                    if let Some(id) = block.get("id") {
+                        call["id"] = id.clone();
+                    }
+                    tool_calls.push(call);
+                }
+                "tool_result" => {
+                    let result = match block.get("content") {
+                        None | Some(Value::Null) => String::new(),
+                        Some(content) => anthropic_text(content).map_err(context)?,
+                    };
+                    let mut tool = json!({ "role": "tool", "content": result });
+                    if let Some(id) = block.get("tool_use_id") {
+                        tool["tool_call_id"] = id.clone();
+                    }
+                    out.push(TemplateMessage {
+                        message: tool,
+                        text: vec![result],
+                    });
+                    tool_results += 1;
+                }
+                other => {
+                    return Err(context(format!(
+                        "unsupported content block type {:?}",
+                        other
+                    )))
+                }
+            }
+        }
+        // A user turn of only tool results needs no message of its own.
+        if texts.is_empty() && tool_results > 0 {
+            continue;
+        }
+        let content = texts.join("\n");
+        text.push(content.clone());
+        let mut hf = json!({ "role": role, "content": content });
+        if !reasoning.is_empty() {
+            let reasoning = reasoning.join("\n");
+            text.push(reasoning.clone());
+            hf["reasoning_content"] = Value::from(reasoning);
+        }
+        if !tool_calls.is_empty() {
+            hf["tool_calls"] = Value::Array(tool_calls);
+        }
+        out.push(TemplateMessage { message: hf, text });
+    }
+    Ok(out)
+}
+
+/// Anthropic content, a string or text blocks, as one string.
+fn anthropic_text(content: &Value) -> Result<String, String> {
+    match content {
+        Value::String(text) => Ok(text.clone()),
+        Value::Array(blocks) => Ok(blocks
+            .iter()
+            .map(|block| match part_type(block)? {
+                "text" => text_field(block, "text"),
+                other => Err(format!("unsupported content block type {:?}", other)),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n")),
+        _ => Err("content must be a string or an array of blocks".to_string()),
+    }
+}
+
+fn count_openai(tok: &dyn Tokenizer, messages: &[Value]) -> Result<ChatCount, Error> {
+    let mut counts = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        let context = |e: String| invalid(format!("message {}: {}", i, e));
+        let role = role(message).map_err(context)?;
+        let mut content = count_openai_content(tok, message.get("content")).map_err(context)?;
+        let mut framing = OPENAI_TOKENS_PER_MESSAGE + tok.count_tokens(role);
+        if let Some(name) = message.get("name").and_then(Value::as_str) {
+            content += tok.count_tokens(name);
+            framing += OPENAI_TOKENS_PER_NAME;
+        }
+        // The legacy single `function_call` and the current `tool_calls`.
+        let calls = message.get("function_call").into_iter().chain(
+            message
+                .get("tool_calls")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|call| call.get("function")),
+        );
+        for function in calls {
+            for key in ["name", "arguments"] {
+                content +=
+                    tok.count_tokens(function.get(key).and_then(Value::as_str).unwrap_or(""));
+            }
+        }
+        counts.push(MessageCount {
+            role: role.to_string(),
+            content,
+            framing,
+        });
+    }
+    Ok(ChatCount {
+        messages: counts,
+        overhead: OPENAI_REPLY_PRIMING,
+        tools: 0,
+    })
+}
+
+/// `content` as a string, an array of `{"type": "text"}` parts, or null
+/// (an assistant message that only calls tools).
+fn count_openai_content(tok: &dyn Tokenizer, content: Option<&Value>) -> Result<usize, String> {
+    match content {
+        None | Some(Value::Null) => Ok(0),
+        Some(Value::String(text)) => Ok(tok.count_tokens(text)),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| match part_type(part)? {
+                kind @ ("text" | "refusal") => Ok(tok.count_tokens(text_field(part, kind)?)),
+                other => Err(format!("unsupported content part type {:?}", other)),
+            })
+            .sum(),
+        Some(_) => Err("content must be a string or an array of parts".to_string()),
+    }
+}
+
+fn count_anthropic(
+    tok: &dyn Tokenizer,
+    system: Option<&Value>,
+    messages: &[Value],
+) -> Result<ChatCount, Error> {
+    let mut counts = Vec::new();
+    match system {
+        None | Some(Value::Null) => {}
+        Some(system) => {
+            let content = count_anthropic_content(tok, system)
+                .map_err(|e| invalid(format!("system: {}", e)))?;
+            counts.push(MessageCount {
+                role: "system".to_string(),
+                content,
+                framing: 0,
+            });
+        }
+    }
+    for (i, message) in messages.iter().enumerate() {
+        let context = |e: String| invalid(format!("message {}: {}", i, e));
+        let role = role(message).map_err(context)?;
+        let header = match role {
+            "user" => "\n\nHuman: ",
+            "assistant" => "\n\nAssistant: ",
+            other => return Err(context(format!("unsupported role {:?}", other))),
+        };
+        let content = message
+            .get("content")
+            .ok_or_else(|| context("missing content".to_string()))?;
+        let content = count_anthropic_content(tok, content).map_err(context)?;
+        counts.push(MessageCount {
+            role: role.to_string(),
+            content,
+            framing: tok.count_tokens(header),
+        });
+    }
+    Ok(ChatCount {
+        messages: counts,
+        overhead: tok.count_tokens("\n\nAssistant:"),
+        tools: 0,
+    })
+}
+
+/// Content as a string or an array of content blocks.
+fn count_anthropic_content(tok: &dyn Tokenizer, content: &Value) -> Result<usize, String> {
+    let blocks = match content {
+        Value::String(text) => return Ok(tok.count_tokens(text)),
+        Value::Array(blocks) => blocks,
+        _ => return Err("content must be a string or an array of blocks".to_string()),
+    };
+    let mut total = 0;
+    for block in blocks {
+        total += match part_type(block)? {
+            "text" => tok.count_tokens(text_field(block, "text")?),
+            "thinking" => tok.count_tokens(text_field(block, "thinking")?),
+            "tool_use" => {
+                let input = block.get("input").map(Value::to_string).unwrap_or_default();
+                tok.count_tokens(text_field(block, "name")?) + tok.count_tokens(&input)
+            }
+            "tool_result" => match block.get("content") {
+                None | Some(Value::Null) => 0,
+                Some(content) => count_anthropic_content(tok, content)?,
+            },
+            other => return Err(format!("unsupported content block type {:?}", other)),
+        };
+    }
+    Ok(total)
+}
+
+fn role(message: &Value) -> Result<&str, String> {
+    message
+        .get("role")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "missing role".to_string())
+}
+
+fn part_type(part: &Value) -> Result<&str, String> {
+    part.get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "content part without a type".to_string())
+}
+
+fn text_field<'v>(part: &'v Value, key: &str) -> Result<&'v str, String> {
+    part.get(key).and_then(Value::as_str).ok_or_else(|| {
+        format!(
+            "{} part without a string {:?}",
+            part_type(part).unwrap_or("?"),
+            key
+        )
+    })
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidChat(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpe::HfTokenizer;
+    use crate::convert;
+
+    /// One token per word, with a template that puts the tool names first.
+    fn templated() -> HfTokenizer<'static> {
+        let words = [
+            "start",
+            "end",
+            "system",
+            "user",
+            "assistant",
+            "be",
+            "brief",
+            "hi",
+            "there",
+            "tools",
+            "ping",
+        ];
+        let vocab: serde_json::Map<String, Value> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.to_string(), Value::from(i)))
+            .collect();
+        let json = json!({
+            "added_tokens": [],
+            "pre_tokenizer": { "type": "WhitespaceSplit" },
+            "model": { "type": "BPE", "vocab": vocab, "merges": [], "ignore_merges": true },
+        });
+        let template =
+            "{% if tools %}tools {% for t in tools %}{{ t.function.name }} {% endfor %}{% endif %}\
+            {% for m in messages %}start {{ m.role }} {{ m.content }} end {% endfor %}\
+            {% if add_generation_prompt %}start assistant {% endif %}";
+        let blob = convert::build_hf_frozen("test", &json.to_string()).unwrap();
+        let blob =
+            convert::add_chat_template(&blob, &json!({ "chat_template": template }).to_string())
+                .unwrap();
+        HfTokenizer::from_frozen(blob).unwrap()
+    }
+
+    #[test]
+    fn templated_messages_are_charged_their_span_of_the_prompt() {
+        let payload = json!({
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": "hi there" },
+            ],
+            "tools": [{ "type": "function", "function": { "name": "ping" } }],
+        });
+        let count = count_chat(&templated(), ChatFormat::OpenAi, &payload).unwrap();
+        let message = |role: &str| MessageCount {
+            role: role.to_string(),
+            content: 2,
+            framing: 3,
+        };
+        assert_eq!(count.messages, [message("system"), message("user")]);
+        assert_eq!((count.overhead, count.tools), (2, 2));
+        assert_eq!(count.total(), 14);
+    }
+}
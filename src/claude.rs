@@ -1,30 +1,53 @@
 /// Claude tokenizer — double-array trie, pre-built at compile time by build.rs.
 ///
 /// O(1) per byte: transition t = base[s] + byte, valid if (check[t] & MASK) == s.
-/// Terminal flag packed into bit 31 of check.
+/// Terminal flag packed into bit 31 of check; the token ID of a terminal slot
+/// lives in a parallel `ids` array.
+///
+/// Anthropic does not publish token IDs, so IDs are indices into
+/// `data/claude-vocab.json`. Bytes with no vocab match are emitted as
+/// `vocab_len + byte`, keeping every encoding reversible.
+use crate::format::{self, Header};
+use crate::frozen;
+use crate::TokenSpan;
+use std::borrow::Cow;
+use std::ops::Range;
+
 const TRIE_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/trie.bin"));
 
 const TERM_BIT: u32 = 0x8000_0000;
 const IDX_MASK: u32 = 0x7FFF_FFFF;
 
-pub struct DATrie {
+pub struct DATrie<'a> {
+    data: Cow<'a, [u8]>,
+    trie: Range<usize>,
+    vocab_len: u32,
+    vocab_off: usize,
+}
+
+/// The arrays of a trie section, borrowed once per call so that per-byte
+/// loops index plain slices. Also used by the Unigram and WordPiece
+/// backends.
+pub(crate) struct TrieArrays<'t> {
     root: u32,
-    base: &'static [u8],
-    check: &'static [u8],
     array_size: usize,
+    base: &'t [u8],
+    check: &'t [u8],
+    ids: &'t [u8],
 }
 
-impl DATrie {
-    pub fn new() -> Self {
-        let array_size = read_u32(TRIE_BIN, 0) as usize;
-        let root = read_u32(TRIE_BIN, 4);
-        let base_start = 8;
-        let check_start = base_start + array_size * 4;
-        DATrie {
-            root,
-            base: &TRIE_BIN[base_start..check_start],
-            check: &TRIE_BIN[check_start..check_start + array_size * 4],
+impl<'t> TrieArrays<'t> {
+    /// Borrow a trie section that passed [`check_trie`].
+    pub(crate) fn new(trie: &'t [u8]) -> Self {
+        let array_size = read_u32(trie, 0) as usize;
+        let check_off = 12 + array_size * 4;
+        let ids_off = check_off + array_size * 4;
+        TrieArrays {
+            root: read_u32(trie, 4),
             array_size,
+            base: &trie[12..check_off],
+            check: &trie[check_off..ids_off],
+            ids: &trie[ids_off..ids_off + array_size * 4],
         }
     }
 
@@ -45,13 +68,16 @@ impl DATrie {
         Some((t as u32, c & TERM_BIT != 0))
     }
 
+    /// Longest vocab match at `pos`: returns its byte length and the DA slot
+    /// of its terminal node, or `(1, None)` when no token starts here.
     #[inline]
-    fn match_len(&self, bytes: &[u8], pos: usize) -> usize {
+    fn match_len(&self, bytes: &[u8], pos: usize) -> (usize, Option<u32>) {
         let (first, first_term) = match self.transition(self.root, bytes[pos]) {
             Some(v) => v,
-            None => return 1,
+            None => return (1, None),
         };
         let mut best = if first_term { 1 } else { 0 };
+        let mut best_slot = first;
         let mut cur = first;
         for (offset, &b) in bytes[pos + 1..].iter().enumerate() {
             match self.transition(cur, b) {
@@ -59,27 +85,174 @@ impl DATrie {
                     cur = next;
                     if is_term {
                         best = offset + 2; // offset+2 == (i - pos + 1) where i = pos+1+offset
+                        best_slot = next;
+                    }
+                }
+                None => break,
+            }
+        }
+        if best == 0 {
+            (1, None)
+        } else {
+            (best, Some(best_slot))
+        }
+    }
+
+    /// Longest vocab token made of `prefix` followed by at least one byte of
+    /// `bytes[pos..]`: returns how many bytes of `bytes` it covers and its
+    /// ID. Used by the WordPiece backend, with the continuation prefix.
+    #[inline]
+    pub(crate) fn longest_match(
+        &self,
+        prefix: &[u8],
+        bytes: &[u8],
+        pos: usize,
+    ) -> Option<(usize, u32)> {
+        let mut cur = self.root;
+        for &b in prefix {
+            cur = self.transition(cur, b)?.0;
+        }
+        let mut best = None;
+        for (offset, &b) in bytes[pos..].iter().enumerate() {
+            match self.transition(cur, b) {
+                Some((next, is_term)) => {
+                    cur = next;
+                    if is_term {
+                        best = Some((offset + 1, read_u32(self.ids, next as usize * 4)));
+                    }
+                }
+                None => break,
+            }
+        }
+        best
+    }
+
+    /// Call `f(len, id)` for every vocab token that `bytes[pos..]` starts
+    /// with, shortest first.
+    #[inline]
+    pub(crate) fn prefixes(&self, bytes: &[u8], pos: usize, mut f: impl FnMut(usize, u32)) {
+        let mut cur = self.root;
+        for (offset, &b) in bytes[pos..].iter().enumerate() {
+            match self.transition(cur, b) {
+                Some((next, is_term)) => {
+                    cur = next;
+                    if is_term {
+                        f(offset + 1, read_u32(self.ids, next as usize * 4));
                     }
                 }
                 None => break,
             }
         }
-        if best == 0 { 1 } else { best }
+    }
+}
+
+impl DATrie<'static> {
+    /// The trie for `data/claude-vocab.json`, embedded at build time.
+    pub fn new() -> Self {
+        Self::from_frozen(TRIE_BIN).expect("embedded Claude trie is invalid")
+    }
+}
+
+impl<'a> DATrie<'a> {
+    pub fn from_frozen(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, String> {
+        let data = data.into();
+        let header = Header::parse_kind(&data, format::KIND_CLAUDE)?;
+        let trie = header.checked_section(format::SEC_TRIE, check_trie)?;
+        let vocab_off = header
+            .checked_section(format::SEC_ID_TOKENS, frozen::check_string_array)?
+            .start;
+        let vocab_len = read_u32(&data, trie.start + 8);
+        Ok(DATrie {
+            data,
+            trie,
+            vocab_len,
+            vocab_off,
+        })
+    }
+
+    #[inline]
+    fn arrays(&self) -> TrieArrays<'_> {
+        TrieArrays::new(&self.data[self.trie.clone()])
     }
 
     pub fn count_tokens(&self, text: &str) -> usize {
         if text.is_empty() {
             return 0;
         }
+        let trie = self.arrays();
         let bytes = text.as_bytes();
         let mut count = 0;
         let mut pos = 0;
         while pos < bytes.len() {
-            pos += self.match_len(bytes, pos);
+            pos += trie.match_len(bytes, pos).0;
             count += 1;
         }
         count
     }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets(text)
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        let trie = self.arrays();
+        let bytes = text.as_bytes();
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (len, slot) = trie.match_len(bytes, pos);
+            let id = match slot {
+                Some(t) => read_u32(trie.ids, t as usize * 4),
+                None => self.vocab_len + bytes[pos] as u32,
+            };
+            tokens.push(TokenSpan {
+                id,
+                start: pos,
+                end: pos + len,
+            });
+            pos += len;
+        }
+        tokens
+    }
+
+    pub fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        let vocab = &self.data[self.vocab_off..];
+        let mut out = Vec::new();
+        for &id in ids {
+            if let Some(tok) = frozen::string_array_get(vocab, id as usize) {
+                out.extend_from_slice(tok);
+            } else if let Some(b) = id.checked_sub(self.vocab_len).filter(|b| *b < 256) {
+                out.push(b as u8);
+            }
+        }
+        out
+    }
+}
+
+impl Default for DATrie<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trie section layout: `[array_size][root][vocab_len]` then the base,
+/// check and ids arrays of `array_size` u32s each.
+pub(crate) fn check_trie(trie: &[u8]) -> Result<(), String> {
+    let array_size = frozen::get_u32(trie, 0)? as usize;
+    let root = frozen::get_u32(trie, 4)? as usize;
+    let expected = array_size.checked_mul(12).and_then(|n| n.checked_add(12));
+    if expected != Some(trie.len()) || root >= array_size {
+        return Err(format!(
+            "malformed trie: {} slots (root {}) in {} bytes",
+            array_size,
+            root,
+            trie.len()
+        ));
+    }
+    Ok(())
 }
 
 #[cold]
@@ -0,0 +1,1374 @@
+//! Conversion of upstream tokenizer files into the frozen blobs the
+//! backends read.
+//!
+//! Shared with `build.rs`, which embeds blobs at compile time; the library
+//! uses it to load `tokenizer.json` / `.tiktoken` files at runtime. Keep it
+//! free of dependencies that are not also build-dependencies.
+
+use crate::format::{self, write_frozen};
+use crate::frozen::{fast_reduce, fnv_hash, read_u64};
+use base64::Engine;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+const TERM_BIT: u32 = 0x8000_0000;
+
+/// Source file for a registry model under a `TOKEN_COUNT_MODELS` directory:
+/// the encoding's `.tiktoken` rank file for OpenAI models,
+/// `<model>/tokenizer.json` otherwise.
+pub fn model_source_path(dir: &Path, model: &str) -> PathBuf {
+    match TIKTOKEN_ENCODINGS.iter().find(|e| e.model == model) {
+        Some(encoding) => dir.join(encoding.rank_file),
+        None => dir.join(model).join("tokenizer.json"),
+    }
+}
+
+/// The `tokenizer_config.json` beside a model's `tokenizer.json`, which
+/// holds its chat template.
+pub fn tokenizer_config_path(source: &Path) -> PathBuf {
+    source.with_file_name("tokenizer_config.json")
+}
+
+/// Freeze a plain vocabulary (a JSON array of token strings, IDs by
+/// position, like `data/claude-vocab.json`) into the double-array trie read
+/// by [`crate::claude::DATrie::from_frozen`].
+pub fn build_trie_frozen(name: &str, json: &str) -> Result<Vec<u8>, String> {
+    let vocab: Vec<String> =
+        serde_json::from_str(json).map_err(|e| format!("invalid vocabulary JSON: {e}"))?;
+    let tokens: Vec<(u32, &[u8])> = vocab
+        .iter()
+        .enumerate()
+        .map(|(id, t)| (id as u32, t.as_bytes()))
+        .collect();
+    let trie = build_trie_section(&tokens, vocab.len() as u32);
+    let id_tokens: Vec<(u32, Vec<u8>)> = vocab
+        .iter()
+        .enumerate()
+        .map(|(id, t)| (id as u32, t.as_bytes().to_vec()))
+        .collect();
+    Ok(write_frozen(
+        format::KIND_CLAUDE,
+        name,
+        format::source_hash(json.as_bytes()),
+        &[
+            (format::SEC_TRIE, &trie),
+            (format::SEC_ID_TOKENS, &build_string_array(&id_tokens)),
+        ],
+    ))
+}
+
+/// Trie section over `(id, token)` pairs: `[array_size][root][vocab_len]`
+/// then the base, check and ids arrays, read by
+/// [`crate::claude::TrieArrays`].
+fn build_trie_section(tokens: &[(u32, &[u8])], vocab_len: u32) -> Vec<u8> {
+    let mut node_children: Vec<Vec<(u8, u32)>> = vec![vec![]];
+    let mut node_terminal: Vec<bool> = vec![false];
+    let mut node_id: Vec<u32> = vec![0];
+
+    for &(id, token) in tokens {
+        let mut cur: u32 = 0;
+        for &byte in token {
+            let existing = node_children[cur as usize].iter().find(|(k, _)| *k == byte);
+            cur = if let Some(&(_, idx)) = existing {
+                idx
+            } else {
+                let idx = node_children.len() as u32;
+                node_children.push(vec![]);
+                node_terminal.push(false);
+                node_id.push(0);
+                node_children[cur as usize].push((byte, idx));
+                idx
+            };
+        }
+        node_terminal[cur as usize] = true;
+        node_id[cur as usize] = id;
+    }
+
+    for children in &mut node_children {
+        children.sort_by_key(|(k, _)| *k);
+    }
+
+    let num_nodes = node_children.len();
+    let root_da = 0usize;
+    let initial_size = num_nodes + 512;
+    let mut base = vec![0u32; initial_size];
+    let mut check = vec![u32::MAX; initial_size];
+    let mut ids = vec![0u32; initial_size];
+    let mut occupied = vec![false; initial_size];
+
+    let mut da_pos = vec![0u32; num_nodes];
+    da_pos[0] = root_da as u32;
+    occupied[root_da] = true;
+
+    let mut queue = VecDeque::new();
+    queue.push_back(0usize);
+
+    while let Some(trie_node) = queue.pop_front() {
+        let s = da_pos[trie_node] as usize;
+        let ch = &node_children[trie_node];
+
+        if ch.is_empty() {
+            continue;
+        }
+
+        let keys: Vec<u8> = ch.iter().map(|&(k, _)| k).collect();
+        let b = find_base(&keys, &occupied);
+
+        let max_pos = b + 256;
+        if max_pos >= base.len() {
+            let new_size = max_pos + 512;
+            base.resize(new_size, 0);
+            check.resize(new_size, u32::MAX);
+            ids.resize(new_size, 0);
+            occupied.resize(new_size, false);
+        }
+
+        base[s] = b as u32;
+
+        for &(key, child_trie_idx) in ch {
+            let t = b + key as usize;
+            let term = if node_terminal[child_trie_idx as usize] {
+                TERM_BIT
+            } else {
+                0
+            };
+            check[t] = s as u32 | term;
+            ids[t] = node_id[child_trie_idx as usize];
+            occupied[t] = true;
+            da_pos[child_trie_idx as usize] = t as u32;
+            queue.push_back(child_trie_idx as usize);
+        }
+    }
+
+    let actual_size = occupied.iter().rposition(|&o| o).map_or(0, |i| i + 1);
+    base.truncate(actual_size);
+    check.truncate(actual_size);
+    ids.truncate(actual_size);
+
+    // `ids[t]` is only meaningful where check[t] carries TERM_BIT.
+    let mut trie = Vec::with_capacity(12 + actual_size * 12);
+    trie.extend_from_slice(&(actual_size as u32).to_le_bytes());
+    trie.extend_from_slice(&(root_da as u32).to_le_bytes());
+    trie.extend_from_slice(&vocab_len.to_le_bytes());
+    for &b in &base {
+        trie.extend_from_slice(&b.to_le_bytes());
+    }
+    for &c in &check {
+        trie.extend_from_slice(&c.to_le_bytes());
+    }
+    for &id in &ids {
+        trie.extend_from_slice(&id.to_le_bytes());
+    }
+    trie
+}
+
+fn find_base(keys: &[u8], occupied: &[bool]) -> usize {
+    let len = occupied.len();
+    let first_key = keys[0] as usize;
+    let mut b = 0usize;
+    'outer: loop {
+        let fpos = b + first_key;
+        if fpos < len && occupied[fpos] {
+            b += 1;
+            continue;
+        }
+        for &k in keys.iter().skip(1) {
+            let pos = b + k as usize;
+            if pos < len && occupied[pos] {
+                b += 1;
+                continue 'outer;
+            }
+        }
+        return b;
+    }
+}
+
+/// A tiktoken encoding: its rank file, plus the pre-tokenizer pattern and
+/// special tokens, which the rank file leaves out.
+pub struct TiktokenEncoding {
+    pub name: &'static str,
+    /// Registry (`-m`) name.
+    pub model: &'static str,
+    pub rank_file: &'static str,
+    pub pattern: &'static str,
+    pub special_tokens: &'static [(&'static str, u32)],
+    /// IDs that are also special tokens named `<|reserved_N|>`.
+    pub reserved: std::ops::Range<u32>,
+    /// ChatML markers the OpenAI API counts as single tokens, which are not
+    /// in tiktoken's table.
+    pub chat_tokens: &'static [(&'static str, u32)],
+}
+
+impl TiktokenEncoding {
+    /// `special_tokens` followed by the reserved ones.
+    pub fn all_special_tokens(&self) -> Vec<(String, u32)> {
+        let named = self
+            .special_tokens
+            .iter()
+            .map(|&(s, id)| (s.to_string(), id));
+        named
+            .chain(
+                self.reserved
+                    .clone()
+                    .map(|id| (format!("<|reserved_{}|>", id), id)),
+            )
+            .collect()
+    }
+}
+
+/// The pattern shared by r50k_base and p50k_base, and by HF's ByteLevel
+/// pre-tokenizer with `use_regex` (GPT-2 style).
+const R50K_PAT: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+const CL100K_PAT: &str = concat!(
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)",
+    r"|[^\r\n\p{L}\p{N}]?\p{L}+",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+const O200K_PAT: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+",
+    r"('s|'S|'t|'T|'re|'rE|'Re|'RE|'ve|'vE|'Ve|'VE|'m|'M|'ll|'lL|'Ll|'LL|'d|'D)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*",
+    r"('s|'S|'t|'T|'re|'rE|'Re|'RE|'ve|'vE|'Ve|'VE|'m|'M|'ll|'lL|'Ll|'LL|'d|'D)?",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n/]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+/// Every supported encoding, patterns and special tokens as published in
+/// tiktoken. The `<|im_*|>` chat markers are kept apart: they are not in
+/// tiktoken's tables, and count as special only with chat markers on.
+pub const TIKTOKEN_ENCODINGS: &[TiktokenEncoding] = &[
+    TiktokenEncoding {
+        name: "o200k_base",
+        model: "openai",
+        rank_file: "o200k_base.tiktoken",
+        pattern: O200K_PAT,
+        special_tokens: &[("<|endoftext|>", 199_999), ("<|endofprompt|>", 200_018)],
+        reserved: 0..0,
+        chat_tokens: &[
+            ("<|im_start|>", 200_264),
+            ("<|im_end|>", 200_265),
+            ("<|im_sep|>", 200_266),
+        ],
+    },
+    TiktokenEncoding {
+        name: "o200k_harmony",
+        model: "o200k-harmony",
+        rank_file: "o200k_base.tiktoken",
+        pattern: O200K_PAT,
+        special_tokens: &[
+            ("<|startoftext|>", 199_998),
+            ("<|endoftext|>", 199_999),
+            ("<|reserved_200000|>", 200_000),
+            ("<|reserved_200001|>", 200_001),
+            ("<|return|>", 200_002),
+            ("<|constrain|>", 200_003),
+            ("<|reserved_200004|>", 200_004),
+            ("<|channel|>", 200_005),
+            ("<|start|>", 200_006),
+            ("<|end|>", 200_007),
+            ("<|message|>", 200_008),
+            ("<|reserved_200009|>", 200_009),
+            ("<|reserved_200010|>", 200_010),
+            ("<|reserved_200011|>", 200_011),
+            ("<|call|>", 200_012),
+            ("<|endofprompt|>", 200_018),
+        ],
+        reserved: 200_013..201_088,
+        chat_tokens: &[],
+    },
+    TiktokenEncoding {
+        name: "cl100k_base",
+        model: "cl100k",
+        rank_file: "cl100k_base.tiktoken",
+        pattern: CL100K_PAT,
+        special_tokens: &[
+            ("<|endoftext|>", 100_257),
+            ("<|fim_prefix|>", 100_258),
+            ("<|fim_middle|>", 100_259),
+            ("<|fim_suffix|>", 100_260),
+            ("<|endofprompt|>", 100_276),
+        ],
+        reserved: 0..0,
+        chat_tokens: &[
+            ("<|im_start|>", 100_264),
+            ("<|im_end|>", 100_265),
+            ("<|im_sep|>", 100_266),
+        ],
+    },
+    TiktokenEncoding {
+        name: "p50k_base",
+        model: "p50k",
+        rank_file: "p50k_base.tiktoken",
+        pattern: R50K_PAT,
+        special_tokens: &[("<|endoftext|>", 50_256)],
+        reserved: 0..0,
+        chat_tokens: &[],
+    },
+    TiktokenEncoding {
+        name: "r50k_base",
+        model: "r50k",
+        rank_file: "r50k_base.tiktoken",
+        pattern: R50K_PAT,
+        special_tokens: &[("<|endoftext|>", 50_256)],
+        reserved: 0..0,
+        chat_tokens: &[],
+    },
+];
+
+/// Encoding for a model converted under `name`: a registry model, or a
+/// file named after its encoding (`cl100k_base.tiktoken`). Anything else is
+/// taken to be o200k_base.
+pub fn tiktoken_encoding(name: &str) -> &'static TiktokenEncoding {
+    let stem = Path::new(name).file_stem().and_then(|s| s.to_str());
+    TIKTOKEN_ENCODINGS
+        .iter()
+        .find(|e| e.model == name || Some(e.name) == stem)
+        .unwrap_or(&TIKTOKEN_ENCODINGS[0])
+}
+
+/// Freeze a `.tiktoken` rank file (`base64-token rank` per line) for the
+/// given encoding.
+pub fn build_tiktoken_frozen(
+    name: &str,
+    encoding: &TiktokenEncoding,
+    data: &str,
+) -> Result<Vec<u8>, String> {
+    let engine = base64::engine::general_purpose::STANDARD;
+
+    let mut entries: Vec<(Vec<u8>, u32)> = Vec::with_capacity(200_000);
+
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(2, ' ');
+        let token_b64 = parts.next().unwrap_or("");
+        let rank_str = parts.next().unwrap_or("");
+        if let (Ok(bytes), Ok(rank)) = (engine.decode(token_b64), rank_str.parse::<u32>()) {
+            entries.push((bytes, rank));
+        }
+    }
+
+    if entries.is_empty() {
+        return Err("no token ranks found".to_string());
+    }
+
+    let special_tokens = encoding.all_special_tokens();
+    let specials: Vec<(u32, u8, &str)> = special_tokens
+        .iter()
+        .map(|(content, id)| (*id, ADDED_SPECIAL, content.as_str()))
+        .chain(
+            encoding
+                .chat_tokens
+                .iter()
+                .map(|&(content, id)| (id, ADDED_SPECIAL | ADDED_CHAT, content)),
+        )
+        .collect();
+    let mut config = Vec::new();
+    write_length_prefixed_str(&mut config, encoding.name);
+    write_length_prefixed_str(&mut config, encoding.pattern);
+    let mut id_tokens: Vec<(u32, Vec<u8>)> = entries.iter().map(|(b, r)| (*r, b.clone())).collect();
+    id_tokens.extend(
+        specials
+            .iter()
+            .map(|&(id, _, content)| (id, content.as_bytes().to_vec())),
+    );
+    Ok(write_frozen(
+        format::KIND_TIKTOKEN,
+        name,
+        format::source_hash(data.as_bytes()),
+        &[
+            (format::SEC_CONFIG, &config),
+            (format::SEC_VOCAB, &build_frozen_map(&entries)),
+            (format::SEC_ID_TOKENS, &build_string_array(&id_tokens)),
+            (format::SEC_ADDED_TOKENS, &write_added_tokens(&specials)),
+        ],
+    ))
+}
+
+pub const NORM_NONE: u8 = 0;
+pub const NORM_REPLACE: u8 = 1;
+pub const NORM_PREPEND: u8 = 2;
+pub const NORM_NFC: u8 = 3;
+pub const NORM_SEQUENCE: u8 = 4;
+pub const NORM_NFD: u8 = 5;
+pub const NORM_NFKC: u8 = 6;
+pub const NORM_NFKD: u8 = 7;
+pub const NORM_LOWERCASE: u8 = 8;
+pub const NORM_STRIP: u8 = 9;
+pub const NORM_STRIP_ACCENTS: u8 = 10;
+pub const NORM_NMT: u8 = 11;
+pub const NORM_PRECOMPILED: u8 = 12;
+pub const NORM_REPLACE_REGEX: u8 = 13;
+pub const NORM_BERT_CLEAN_TEXT: u8 = 14;
+pub const NORM_BERT_CHINESE_CHARS: u8 = 15;
+pub const NORM_BYTE_LEVEL: u8 = 16;
+pub const NORM_BERT_STRIP_ACCENTS: u8 = 17;
+
+pub const STEP_SPLIT: u8 = 1;
+pub const STEP_BYTE_LEVEL: u8 = 2;
+pub const STEP_METASPACE: u8 = 3;
+pub const STEP_DIGITS: u8 = 4;
+pub const STEP_PUNCTUATION: u8 = 5;
+pub const STEP_WHITESPACE: u8 = 6;
+pub const STEP_WHITESPACE_SPLIT: u8 = 7;
+pub const STEP_UNICODE_SCRIPTS: u8 = 8;
+
+/// Metaspace `prepend_scheme`.
+pub const PREPEND_NEVER: u8 = 0;
+pub const PREPEND_FIRST: u8 = 1;
+pub const PREPEND_ALWAYS: u8 = 2;
+
+/// HF `SplitDelimiterBehavior`.
+pub const SPLIT_REMOVED: u8 = 0;
+pub const SPLIT_ISOLATED: u8 = 1;
+pub const SPLIT_MERGED_WITH_PREVIOUS: u8 = 2;
+pub const SPLIT_MERGED_WITH_NEXT: u8 = 3;
+pub const SPLIT_CONTIGUOUS: u8 = 4;
+
+pub const DEC_NONE: u8 = 0;
+pub const DEC_SEQUENCE: u8 = 1;
+pub const DEC_BYTE_LEVEL: u8 = 2;
+pub const DEC_BYTE_FALLBACK: u8 = 3;
+pub const DEC_FUSE: u8 = 4;
+pub const DEC_STRIP: u8 = 5;
+pub const DEC_REPLACE: u8 = 6;
+pub const DEC_METASPACE: u8 = 7;
+pub const DEC_WORDPIECE: u8 = 8;
+
+/// Flags of an `added_tokens` entry, one byte per token.
+pub const ADDED_SPECIAL: u8 = 1;
+pub const ADDED_SINGLE_WORD: u8 = 2;
+pub const ADDED_LSTRIP: u8 = 4;
+pub const ADDED_RSTRIP: u8 = 8;
+pub const ADDED_NORMALIZED: u8 = 16;
+/// A tiktoken chat marker, matched only when chat markers are on.
+pub const ADDED_CHAT: u8 = 32;
+
+/// Freeze a Hugging Face `tokenizer.json` into the layout read by
+/// [`crate::bpe::HfTokenizer::from_frozen`] for BPE models, or by
+/// [`crate::unigram::UnigramTokenizer::from_frozen`] for Unigram models, or
+/// by [`crate::wordpiece::WordPieceTokenizer::from_frozen`] for WordPiece
+/// models.
+pub fn build_hf_frozen(name: &str, json: &str) -> Result<Vec<u8>, String> {
+    let root: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("invalid JSON: {e}"))?;
+
+    let model = root.get("model").ok_or("missing model")?;
+    match model.get("type").and_then(|v| v.as_str()) {
+        None | Some("BPE") => build_hf_bpe(name, json, &root, model),
+        Some("Unigram") => build_hf_unigram(name, json, &root, model),
+        Some("WordPiece") => build_hf_wordpiece(name, json, &root, model),
+        Some(other) => Err(format!("unsupported model type {:?}", other)),
+    }
+}
+
+/// Add the `chat_template` of a Hugging Face `tokenizer_config.json` to a
+/// frozen HF model as its [`format::SEC_CHAT_TEMPLATE`] section, with the
+/// BOS and EOS token strings the template refers to. A list of named
+/// templates contributes its `default` one, or else its first. Returns the
+/// blob unchanged when the config has no template.
+pub fn add_chat_template(blob: &[u8], config_json: &str) -> Result<Vec<u8>, String> {
+    let config: serde_json::Value =
+        serde_json::from_str(config_json).map_err(|e| format!("invalid JSON: {e}"))?;
+    let template = match config.get("chat_template") {
+        None | Some(serde_json::Value::Null) => return Ok(blob.to_vec()),
+        Some(serde_json::Value::String(source)) => source.as_str(),
+        Some(serde_json::Value::Array(named)) => named
+            .iter()
+            .find(|t| t.get("name").and_then(|v| v.as_str()) == Some("default"))
+            .or(named.first())
+            .and_then(|t| t.get("template"))
+            .and_then(|v| v.as_str())
+            .ok_or("chat_template list without a template")?,
+        Some(_) => return Err("chat_template must be a string or a list".to_string()),
+    };
+    // Special tokens are written as strings or as AddedToken objects.
+    let token = |key: &str| match config.get(key) {
+        Some(serde_json::Value::String(s)) => s.as_str(),
+        Some(v) => v.get("content").and_then(|c| c.as_str()).unwrap_or(""),
+        None => "",
+    };
+    let mut section = Vec::new();
+    write_length_prefixed_str(&mut section, token("bos_token"));
+    write_length_prefixed_str(&mut section, token("eos_token"));
+    write_length_prefixed_str(&mut section, template);
+    format::add_section(blob, format::SEC_CHAT_TEMPLATE, &section)
+}
+
+fn build_hf_bpe(
+    name: &str,
+    json: &str,
+    root: &serde_json::Value,
+    model: &serde_json::Value,
+) -> Result<Vec<u8>, String> {
+    let merges_arr = model
+        .get("merges")
+        .and_then(|v| v.as_array())
+        .ok_or("missing merges")?;
+
+    let mut merge_entries: Vec<(Vec<u8>, u32)> = Vec::with_capacity(merges_arr.len());
+    let mut merge_left_keys: Vec<Vec<u8>> = Vec::new();
+    let mut merge_right_keys: Vec<Vec<u8>> = Vec::new();
+    let mut merge_left_seen = HashSet::new();
+    let mut merge_right_seen = HashSet::new();
+
+    for (rank, entry) in merges_arr.iter().enumerate() {
+        let (a, b) = if let Some(s) = entry.as_str() {
+            let mut parts = s.splitn(2, ' ');
+            let a = parts.next().unwrap_or("").to_string();
+            let b = parts.next().unwrap_or("").to_string();
+            (a, b)
+        } else if let Some(arr) = entry.as_array() {
+            let a = arr
+                .first()
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            let b = arr
+                .get(1)
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            (a, b)
+        } else {
+            continue;
+        };
+
+        let mut pair_key = Vec::with_capacity(a.len() + 1 + b.len());
+        pair_key.extend_from_slice(a.as_bytes());
+        pair_key.push(0);
+        pair_key.extend_from_slice(b.as_bytes());
+        merge_entries.push((pair_key, rank as u32));
+
+        if merge_left_seen.insert(a.clone()) {
+            merge_left_keys.push(a.as_bytes().to_vec());
+        }
+        if merge_right_seen.insert(b.clone()) {
+            merge_right_keys.push(b.as_bytes().to_vec());
+        }
+    }
+
+    let vocab_entries: Vec<(Vec<u8>, u32)> = model
+        .get("vocab")
+        .and_then(|v| v.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| Some((k.as_bytes().to_vec(), v.as_u64()? as u32)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // The unk token must be in the vocab; HF refuses to load otherwise.
+    let unk_token = model
+        .get("unk_token")
+        .and_then(|v| v.as_str())
+        .filter(|unk| vocab_entries.iter().any(|(k, _)| k == unk.as_bytes()))
+        .unwrap_or("");
+    let flag = |key: &str| model.get(key).and_then(|v| v.as_bool()).unwrap_or(false) as u8;
+    let affix = |key: &str| model.get(key).and_then(|v| v.as_str()).unwrap_or("");
+    let mut options = vec![flag("ignore_merges"), flag("fuse_unk")];
+    write_length_prefixed_str(&mut options, unk_token);
+    write_length_prefixed_str(&mut options, affix("continuing_subword_prefix"));
+    write_length_prefixed_str(&mut options, affix("end_of_word_suffix"));
+
+    let id_tokens = hf_id_tokens(
+        root,
+        vocab_entries
+            .iter()
+            .map(|(k, id)| (*id, k.clone()))
+            .collect(),
+    );
+    let added_tokens = write_added_tokens(&hf_added_tokens(root.get("added_tokens")));
+    let vocab_table = build_frozen_map(&vocab_entries);
+    let merges_table = build_frozen_map(&merge_entries);
+    let merge_left_table = build_frozen_set(&merge_left_keys);
+    let merge_right_table = build_frozen_set(&merge_right_keys);
+    let config = hf_config(root, model)?;
+
+    Ok(write_frozen(
+        format::KIND_HF_BPE,
+        name,
+        format::source_hash(json.as_bytes()),
+        &[
+            (format::SEC_CONFIG, &config),
+            (format::SEC_BPE, &options),
+            (format::SEC_VOCAB, &vocab_table),
+            (format::SEC_ID_TOKENS, &build_string_array(&id_tokens)),
+            (format::SEC_MERGES, &merges_table),
+            (format::SEC_MERGE_LEFT, &merge_left_table),
+            (format::SEC_MERGE_RIGHT, &merge_right_table),
+            (format::SEC_ADDED_TOKENS, &added_tokens),
+        ],
+    ))
+}
+
+/// Unigram vocabularies are `[piece, score]` pairs with IDs by position.
+/// Pieces go into a trie for the Viterbi lattice and their scores into
+/// [`format::SEC_SCORES`].
+fn build_hf_unigram(
+    name: &str,
+    json: &str,
+    root: &serde_json::Value,
+    model: &serde_json::Value,
+) -> Result<Vec<u8>, String> {
+    let vocab = model
+        .get("vocab")
+        .and_then(|v| v.as_array())
+        .ok_or("missing vocab")?;
+    let mut pieces: Vec<&str> = Vec::with_capacity(vocab.len());
+    let mut scores: Vec<f64> = Vec::with_capacity(vocab.len());
+    for (id, entry) in vocab.iter().enumerate() {
+        let (Some(piece), Some(score)) = (
+            entry.get(0).and_then(|v| v.as_str()),
+            entry.get(1).and_then(|v| v.as_f64()),
+        ) else {
+            return Err(format!("malformed vocab entry {}", id));
+        };
+        pieces.push(piece);
+        scores.push(score);
+    }
+    let unk_id = match model.get("unk_id").and_then(|v| v.as_u64()) {
+        Some(id) if id as usize >= pieces.len() => {
+            return Err(format!(
+                "unk_id {} is outside the vocab ({} pieces)",
+                id,
+                pieces.len()
+            ))
+        }
+        Some(id) => id as u32,
+        None => u32::MAX,
+    };
+
+    let tokens: Vec<(u32, &[u8])> = pieces
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| !p.is_empty())
+        .map(|(id, p)| (id as u32, p.as_bytes()))
+        .collect();
+    let trie = build_trie_section(&tokens, pieces.len() as u32);
+
+    let min_score = scores.iter().copied().reduce(f64::min).unwrap_or(0.0);
+    let mut score_table = Vec::with_capacity(16 + scores.len() * 8);
+    score_table.extend_from_slice(&unk_id.to_le_bytes());
+    score_table.extend_from_slice(&(scores.len() as u32).to_le_bytes());
+    score_table.extend_from_slice(&min_score.to_le_bytes());
+    for score in &scores {
+        score_table.extend_from_slice(&score.to_le_bytes());
+    }
+
+    let id_tokens = hf_id_tokens(
+        root,
+        pieces
+            .iter()
+            .enumerate()
+            .map(|(id, p)| (id as u32, p.as_bytes().to_vec()))
+            .collect(),
+    );
+    let added_tokens = write_added_tokens(&hf_added_tokens(root.get("added_tokens")));
+    let config = hf_config(root, model)?;
+
+    Ok(write_frozen(
+        format::KIND_HF_UNIGRAM,
+        name,
+        format::source_hash(json.as_bytes()),
+        &[
+            (format::SEC_CONFIG, &config),
+            (format::SEC_TRIE, &trie),
+            (format::SEC_SCORES, &score_table),
+            (format::SEC_ID_TOKENS, &build_string_array(&id_tokens)),
+            (format::SEC_ADDED_TOKENS, &added_tokens),
+        ],
+    ))
+}
+
+/// WordPiece vocabularies go into a trie, with the unk token, word length
+/// limit and continuation prefix in [`format::SEC_WORDPIECE`].
+fn build_hf_wordpiece(
+    name: &str,
+    json: &str,
+    root: &serde_json::Value,
+    model: &serde_json::Value,
+) -> Result<Vec<u8>, String> {
+    let vocab: Vec<(&str, u32)> = model
+        .get("vocab")
+        .and_then(|v| v.as_object())
+        .ok_or("missing vocab")?
+        .iter()
+        .filter_map(|(k, v)| Some((k.as_str(), v.as_u64()? as u32)))
+        .collect();
+    let unk_token = model
+        .get("unk_token")
+        .and_then(|v| v.as_str())
+        .unwrap_or("[UNK]");
+    let unk_id = vocab
+        .iter()
+        .find(|(k, _)| *k == unk_token)
+        .map_or(u32::MAX, |&(_, id)| id);
+    let max_chars = model
+        .get("max_input_chars_per_word")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(100)
+        .min(u32::MAX as u64) as u32;
+    let prefix = model
+        .get("continuing_subword_prefix")
+        .and_then(|v| v.as_str())
+        .unwrap_or("##");
+
+    let tokens: Vec<(u32, &[u8])> = vocab
+        .iter()
+        .filter(|(k, _)| !k.is_empty())
+        .map(|&(k, id)| (id, k.as_bytes()))
+        .collect();
+    let vocab_len = vocab.iter().map(|&(_, id)| id + 1).max().unwrap_or(0);
+    let trie = build_trie_section(&tokens, vocab_len);
+
+    let mut params = Vec::new();
+    params.extend_from_slice(&unk_id.to_le_bytes());
+    params.extend_from_slice(&max_chars.to_le_bytes());
+    write_length_prefixed_str(&mut params, prefix);
+
+    let id_tokens = hf_id_tokens(
+        root,
+        vocab
+            .iter()
+            .map(|&(k, id)| (id, k.as_bytes().to_vec()))
+            .collect(),
+    );
+    let added_tokens = write_added_tokens(&hf_added_tokens(root.get("added_tokens")));
+    let config = hf_config(root, model)?;
+
+    Ok(write_frozen(
+        format::KIND_HF_WORDPIECE,
+        name,
+        format::source_hash(json.as_bytes()),
+        &[
+            (format::SEC_CONFIG, &config),
+            (format::SEC_TRIE, &trie),
+            (format::SEC_WORDPIECE, &params),
+            (format::SEC_ID_TOKENS, &build_string_array(&id_tokens)),
+            (format::SEC_ADDED_TOKENS, &added_tokens),
+        ],
+    ))
+}
+
+/// The HF config section shared by every model type: byte fallback,
+/// post-processor IDs, then the normalizer, pre-tokenizer and decoder.
+fn hf_config(root: &serde_json::Value, model: &serde_json::Value) -> Result<Vec<u8>, String> {
+    let byte_fallback = model
+        .get("byte_fallback")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let mut post = PostTemplates::default();
+    collect_post_special_ids(root.get("post_processor"), &mut post)?;
+
+    let mut config = Vec::new();
+    config.push(if byte_fallback { 1 } else { 0 });
+    for ids in post.single.iter().chain(&post.pair) {
+        write_u32_list(&mut config, ids);
+    }
+    serialize_normalizer(&mut config, root.get("normalizer"))?;
+    serialize_pre_tokenizer(&mut config, root.get("pre_tokenizer"))?;
+    serialize_decoder(&mut config, root.get("decoder"))?;
+    Ok(config)
+}
+
+/// ID → token for decoding. Added tokens may live outside model.vocab
+/// (e.g. Llama 3's <|begin_of_text|>), so merge them in.
+fn hf_id_tokens(
+    root: &serde_json::Value,
+    mut id_tokens: Vec<(u32, Vec<u8>)>,
+) -> Vec<(u32, Vec<u8>)> {
+    for added in root
+        .get("added_tokens")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        if let (Some(id), Some(content)) = (
+            added.get("id").and_then(|v| v.as_u64()),
+            added.get("content").and_then(|v| v.as_str()),
+        ) {
+            id_tokens.push((id as u32, content.as_bytes().to_vec()));
+        }
+    }
+    id_tokens
+}
+
+/// The IDs a post-processor adds around a single sequence (before and
+/// after it) and around a pair (before, between and after the two).
+#[derive(Default)]
+struct PostTemplates {
+    single: [Vec<u32>; 2],
+    pair: [Vec<u32>; 3],
+}
+
+fn collect_post_special_ids(
+    val: Option<&serde_json::Value>,
+    post: &mut PostTemplates,
+) -> Result<(), String> {
+    let val = match val {
+        Some(v) if !v.is_null() => v,
+        _ => return Ok(()),
+    };
+    let ty = val.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    match ty {
+        "TemplateProcessing" => {
+            let special = val.get("special_tokens");
+            let single = template_parts(val.get("single"), special, &mut post.single);
+            let pair = template_parts(val.get("pair"), special, &mut post.pair);
+            // Without a pair template, each sequence is wrapped on its own.
+            if single && !pair {
+                let [prefix, suffix] = &post.single;
+                post.pair[0].extend(prefix);
+                post.pair[1].extend(suffix.iter().chain(prefix));
+                post.pair[2].extend(suffix);
+            }
+        }
+        // `"cls": ["[CLS]", 101], "sep": ["[SEP]", 102]`
+        "BertProcessing" | "RobertaProcessing" => {
+            let id = |key: &str| {
+                val.get(key)
+                    .and_then(|v| v.get(1))
+                    .and_then(|v| v.as_u64())
+                    .map(|id| id as u32)
+                    .ok_or_else(|| format!("{} is missing its {} token", ty, key))
+            };
+            let (cls, sep) = (id("cls")?, id("sep")?);
+            post.single[0].push(cls);
+            post.single[1].push(sep);
+            post.pair[0].push(cls);
+            // RoBERTa separates the pair with a doubled `</s></s>`.
+            post.pair[1].push(sep);
+            if ty == "RobertaProcessing" {
+                post.pair[1].push(sep);
+            }
+            post.pair[2].push(sep);
+        }
+        // Only trims offsets; adds no tokens.
+        "ByteLevel" => {}
+        "Sequence" => {
+            for p in val
+                .get("processors")
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+            {
+                collect_post_special_ids(Some(p), post)?;
+            }
+        }
+        _ => return Err(format!("unsupported post-processor type {:?}", ty)),
+    }
+    Ok(())
+}
+
+/// Split a TemplateProcessing template at its `Sequence` items, adding the
+/// IDs of the `SpecialToken` items before, between and after them to
+/// `parts`. Returns whether the template has any items.
+fn template_parts(
+    template: Option<&serde_json::Value>,
+    special: Option<&serde_json::Value>,
+    parts: &mut [Vec<u32>],
+) -> bool {
+    let items = template
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut part = 0;
+    for item in items {
+        if item.get("Sequence").is_some() {
+            part = (part + 1).min(parts.len() - 1);
+            continue;
+        }
+        let Some(name) = item
+            .get("SpecialToken")
+            .and_then(|t| t.get("id"))
+            .and_then(|v| v.as_str())
+        else {
+            continue;
+        };
+        let ids = special
+            .and_then(|s| s.get(name))
+            .and_then(|t| t.get("ids"))
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_u64())
+            .map(|v| v as u32);
+        parts[part].extend(ids);
+    }
+    !items.is_empty()
+}
+
+fn write_u32_list(blob: &mut Vec<u8>, values: &[u32]) {
+    blob.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for &v in values {
+        blob.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// `added_tokens` as `(id, flags, content)`, with HF's defaults for missing
+/// flags (`normalized` defaults to `!special`). Tokens with empty content
+/// can never match and are dropped.
+fn hf_added_tokens(val: Option<&serde_json::Value>) -> Vec<(u32, u8, &str)> {
+    let flag = |token: &serde_json::Value, key: &str, default: bool| {
+        token.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
+    };
+    let mut tokens = Vec::new();
+    for token in val.and_then(|v| v.as_array()).into_iter().flatten() {
+        let (Some(id), Some(content)) = (
+            token.get("id").and_then(|v| v.as_u64()),
+            token.get("content").and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+        if content.is_empty() {
+            continue;
+        }
+        let special = flag(token, "special", false);
+        let mut flags = 0;
+        for (key, bit, default) in [
+            ("special", ADDED_SPECIAL, false),
+            ("single_word", ADDED_SINGLE_WORD, false),
+            ("lstrip", ADDED_LSTRIP, false),
+            ("rstrip", ADDED_RSTRIP, false),
+            ("normalized", ADDED_NORMALIZED, !special),
+        ] {
+            if flag(token, key, default) {
+                flags |= bit;
+            }
+        }
+        tokens.push((id as u32, flags, content));
+    }
+    tokens
+}
+
+/// Added-tokens section: `[count]` then `[id u32][flags u8][content]` each.
+fn write_added_tokens(tokens: &[(u32, u8, &str)]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&(tokens.len() as u32).to_le_bytes());
+    for &(id, flags, content) in tokens {
+        blob.extend_from_slice(&id.to_le_bytes());
+        blob.push(flags);
+        write_length_prefixed_str(&mut blob, content);
+    }
+    blob
+}
+
+/// Serialize a normalizer, failing on types the backend cannot run rather
+/// than dropping them and producing wrong counts.
+fn serialize_normalizer(blob: &mut Vec<u8>, val: Option<&serde_json::Value>) -> Result<(), String> {
+    let val = match val {
+        Some(v) if !v.is_null() => v,
+        _ => {
+            blob.push(NORM_NONE);
+            return Ok(());
+        }
+    };
+    let str_field = |key: &str| val.get(key).and_then(|s| s.as_str()).unwrap_or("");
+    let bool_field =
+        |key: &str, default: bool| val.get(key).and_then(|v| v.as_bool()).unwrap_or(default);
+    let ty = val.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    match ty {
+        "Replace" => {
+            let pattern = val.get("pattern");
+            if let Some(regex) = pattern
+                .and_then(|p| p.get("Regex"))
+                .and_then(|s| s.as_str())
+            {
+                blob.push(NORM_REPLACE_REGEX);
+                write_length_prefixed_str(blob, regex);
+            } else {
+                blob.push(NORM_REPLACE);
+                let literal = pattern
+                    .and_then(|p| p.get("String"))
+                    .and_then(|s| s.as_str());
+                write_length_prefixed_str(blob, literal.unwrap_or(""));
+            }
+            write_length_prefixed_str(blob, str_field("content"));
+        }
+        "Prepend" => {
+            blob.push(NORM_PREPEND);
+            write_length_prefixed_str(blob, str_field("prepend"));
+        }
+        "NFC" => blob.push(NORM_NFC),
+        "NFD" => blob.push(NORM_NFD),
+        "NFKC" => blob.push(NORM_NFKC),
+        "NFKD" => blob.push(NORM_NFKD),
+        "Lowercase" => blob.push(NORM_LOWERCASE),
+        "Strip" => {
+            blob.push(NORM_STRIP);
+            blob.push(bool_field("strip_left", true) as u8);
+            blob.push(bool_field("strip_right", true) as u8);
+        }
+        "StripAccents" => blob.push(NORM_STRIP_ACCENTS),
+        "Nmt" => blob.push(NORM_NMT),
+        "ByteLevel" => blob.push(NORM_BYTE_LEVEL),
+        "Precompiled" => {
+            let charsmap = base64::engine::general_purpose::STANDARD
+                .decode(str_field("precompiled_charsmap"))
+                .map_err(|e| format!("invalid precompiled_charsmap: {e}"))?;
+            if charsmap.is_empty() {
+                blob.push(NORM_NONE);
+            } else {
+                blob.push(NORM_PRECOMPILED);
+                blob.extend_from_slice(&(charsmap.len() as u32).to_le_bytes());
+                blob.extend_from_slice(&charsmap);
+            }
+        }
+        "BertNormalizer" => {
+            // Expanded into its steps, in HF's order.
+            let lowercase = bool_field("lowercase", true);
+            let mut steps = Vec::new();
+            if bool_field("clean_text", true) {
+                steps.push(NORM_BERT_CLEAN_TEXT);
+            }
+            if bool_field("handle_chinese_chars", true) {
+                steps.push(NORM_BERT_CHINESE_CHARS);
+            }
+            if bool_field("strip_accents", lowercase) {
+                steps.extend([NORM_NFD, NORM_BERT_STRIP_ACCENTS]);
+            }
+            if lowercase {
+                steps.push(NORM_LOWERCASE);
+            }
+            blob.push(NORM_SEQUENCE);
+            blob.extend_from_slice(&(steps.len() as u32).to_le_bytes());
+            blob.extend_from_slice(&steps);
+        }
+        "Sequence" => {
+            let normalizers = val
+                .get("normalizers")
+                .and_then(|v| v.as_array())
+                .ok_or("Sequence normalizer without normalizers")?;
+            blob.push(NORM_SEQUENCE);
+            blob.extend_from_slice(&(normalizers.len() as u32).to_le_bytes());
+            for item in normalizers {
+                serialize_normalizer(blob, Some(item))?;
+            }
+        }
+        _ => return Err(format!("unsupported normalizer type {:?}", ty)),
+    }
+    Ok(())
+}
+
+/// Serialize a pre-tokenizer as a flat list of steps, failing on types the
+/// backend cannot run.
+fn serialize_pre_tokenizer(
+    blob: &mut Vec<u8>,
+    val: Option<&serde_json::Value>,
+) -> Result<(), String> {
+    let mut steps = Vec::new();
+    if let Some(val) = val.filter(|v| !v.is_null()) {
+        serialize_pre_tokenizer_steps(&mut steps, val)?;
+    }
+    // 0 steps = no pre-tokenizer
+    blob.extend_from_slice(&(steps.len() as u32).to_le_bytes());
+    for step in steps {
+        blob.extend_from_slice(&step);
+    }
+    Ok(())
+}
+
+fn serialize_pre_tokenizer_steps(
+    steps: &mut Vec<Vec<u8>>,
+    val: &serde_json::Value,
+) -> Result<(), String> {
+    let str_field = |key: &str| val.get(key).and_then(|s| s.as_str());
+    let bool_field =
+        |key: &str, default: bool| val.get(key).and_then(|v| v.as_bool()).unwrap_or(default);
+    let ty = str_field("type").unwrap_or("");
+    let mut step = Vec::new();
+    match ty {
+        "Sequence" => {
+            let pretokenizers = val
+                .get("pretokenizers")
+                .and_then(|v| v.as_array())
+                .ok_or("Sequence pre-tokenizer without pretokenizers")?;
+            for item in pretokenizers {
+                serialize_pre_tokenizer_steps(steps, item)?;
+            }
+            return Ok(());
+        }
+        "Split" => {
+            step.push(STEP_SPLIT);
+            let pattern = val.get("pattern");
+            let regex = match (
+                pattern
+                    .and_then(|p| p.get("Regex"))
+                    .and_then(|s| s.as_str()),
+                pattern
+                    .and_then(|p| p.get("String"))
+                    .and_then(|s| s.as_str()),
+            ) {
+                (Some(regex), _) => regex.to_string(),
+                (None, Some(literal)) => escape_regex(literal),
+                (None, None) => return Err("Split pre-tokenizer without a pattern".to_string()),
+            };
+            write_length_prefixed_str(&mut step, &regex);
+            step.push(split_behavior(str_field("behavior").unwrap_or("Isolated"))?);
+            step.push(bool_field("invert", false) as u8);
+        }
+        "ByteLevel" => {
+            step.push(STEP_BYTE_LEVEL);
+            step.push(bool_field("add_prefix_space", true) as u8);
+            // The GPT-2 split runs first unless `use_regex` is off; empty
+            // means no split.
+            let pattern = if bool_field("use_regex", true) {
+                R50K_PAT
+            } else {
+                ""
+            };
+            write_length_prefixed_str(&mut step, pattern);
+        }
+        "Metaspace" => {
+            step.push(STEP_METASPACE);
+            write_length_prefixed_str(&mut step, str_field("replacement").unwrap_or("\u{2581}"));
+            // Older files have `add_prefix_space` instead of a scheme.
+            let prepend = match str_field("prepend_scheme") {
+                Some("always") => PREPEND_ALWAYS,
+                Some("first") => PREPEND_FIRST,
+                Some("never") => PREPEND_NEVER,
+                Some(other) => {
+                    return Err(format!("unsupported Metaspace prepend_scheme {:?}", other))
+                }
+                None if bool_field("add_prefix_space", true) => PREPEND_ALWAYS,
+                None => PREPEND_NEVER,
+            };
+            step.push(prepend);
+            step.push(bool_field("split", true) as u8);
+        }
+        "Digits" => {
+            step.push(STEP_DIGITS);
+            step.push(bool_field("individual_digits", false) as u8);
+        }
+        "Punctuation" => {
+            step.push(STEP_PUNCTUATION);
+            step.push(split_behavior(str_field("behavior").unwrap_or("Isolated"))?);
+        }
+        "Whitespace" => step.push(STEP_WHITESPACE),
+        "WhitespaceSplit" => step.push(STEP_WHITESPACE_SPLIT),
+        "UnicodeScripts" => step.push(STEP_UNICODE_SCRIPTS),
+        "BertPreTokenizer" => {
+            // Whitespace split, then punctuation on its own.
+            steps.push(vec![STEP_WHITESPACE_SPLIT]);
+            step.extend([STEP_PUNCTUATION, SPLIT_ISOLATED]);
+        }
+        _ => return Err(format!("unsupported pre-tokenizer type {:?}", ty)),
+    }
+    steps.push(step);
+    Ok(())
+}
+
+/// `text` as a regex matching itself literally.
+fn escape_regex(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn split_behavior(name: &str) -> Result<u8, String> {
+    match name {
+        "Removed" => Ok(SPLIT_REMOVED),
+        "Isolated" => Ok(SPLIT_ISOLATED),
+        "MergedWithPrevious" => Ok(SPLIT_MERGED_WITH_PREVIOUS),
+        "MergedWithNext" => Ok(SPLIT_MERGED_WITH_NEXT),
+        "Contiguous" => Ok(SPLIT_CONTIGUOUS),
+        _ => Err(format!("unsupported split behavior {:?}", name)),
+    }
+}
+
+fn serialize_decoder(blob: &mut Vec<u8>, val: Option<&serde_json::Value>) -> Result<(), String> {
+    let val = match val {
+        Some(v) if !v.is_null() => v,
+        _ => {
+            blob.push(DEC_NONE);
+            return Ok(());
+        }
+    };
+    let ty = val.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    match ty {
+        "Sequence" => {
+            let decoders: &[serde_json::Value] = val
+                .get("decoders")
+                .and_then(|v| v.as_array())
+                .map_or(&[], |arr| arr);
+            blob.push(DEC_SEQUENCE);
+            blob.extend_from_slice(&(decoders.len() as u32).to_le_bytes());
+            for d in decoders {
+                serialize_decoder(blob, Some(d))?;
+            }
+        }
+        "ByteLevel" => blob.push(DEC_BYTE_LEVEL),
+        "ByteFallback" => blob.push(DEC_BYTE_FALLBACK),
+        "Fuse" => blob.push(DEC_FUSE),
+        "Strip" => {
+            blob.push(DEC_STRIP);
+            let content = val.get("content").and_then(|v| v.as_str()).unwrap_or(" ");
+            write_length_prefixed_str(blob, content);
+            for key in ["start", "stop"] {
+                let n = val.get(key).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+                blob.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        "Replace" => {
+            blob.push(DEC_REPLACE);
+            let pattern = val
+                .get("pattern")
+                .and_then(|p| p.get("String"))
+                .and_then(|s| s.as_str())
+                .ok_or("unsupported Replace decoder pattern (only String is)")?;
+            let content = val.get("content").and_then(|s| s.as_str()).unwrap_or("");
+            write_length_prefixed_str(blob, pattern);
+            write_length_prefixed_str(blob, content);
+        }
+        "Metaspace" => {
+            blob.push(DEC_METASPACE);
+            let replacement = val
+                .get("replacement")
+                .and_then(|v| v.as_str())
+                .unwrap_or("\u{2581}");
+            write_length_prefixed_str(blob, replacement);
+            let strip_first = match val.get("prepend_scheme").and_then(|v| v.as_str()) {
+                Some(scheme) => scheme != "never",
+                None => val
+                    .get("add_prefix_space")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(true),
+            };
+            blob.push(strip_first as u8);
+        }
+        "WordPiece" => {
+            blob.push(DEC_WORDPIECE);
+            let prefix = val.get("prefix").and_then(|v| v.as_str()).unwrap_or("##");
+            write_length_prefixed_str(blob, prefix);
+            let cleanup = val.get("cleanup").and_then(|v| v.as_bool()).unwrap_or(true);
+            blob.push(cleanup as u8);
+        }
+        _ => return Err(format!("unsupported decoder type {:?}", ty)),
+    }
+    Ok(())
+}
+
+fn write_length_prefixed_str(blob: &mut Vec<u8>, s: &str) {
+    blob.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    blob.extend_from_slice(s.as_bytes());
+}
+
+fn build_frozen_table(keys: &[Vec<u8>], values: Option<&[u32]>, slot_size: usize) -> Vec<u8> {
+    let num_entries = keys.len();
+    let num_slots = (num_entries * 4).div_ceil(3).max(4);
+    let mut string_pool = Vec::new();
+    let mut slots = vec![0u8; num_slots * slot_size];
+
+    for (i, key) in keys.iter().enumerate() {
+        let h = fnv_hash(key);
+        let key_off = string_pool.len() as u32;
+        let key_len = key.len() as u16;
+        string_pool.extend_from_slice(key);
+
+        let mut idx = fast_reduce(h, num_slots);
+        loop {
+            let s = idx * slot_size;
+            if read_u64(&slots, s) == 0 {
+                slots[s..s + 8].copy_from_slice(&h.to_le_bytes());
+                slots[s + 8..s + 12].copy_from_slice(&key_off.to_le_bytes());
+                slots[s + 12..s + 14].copy_from_slice(&key_len.to_le_bytes());
+                if let Some(vals) = values {
+                    slots[s + 14..s + 18].copy_from_slice(&vals[i].to_le_bytes());
+                }
+                break;
+            }
+            idx += 1;
+            if idx == num_slots {
+                idx = 0;
+            }
+        }
+    }
+
+    let mut table = Vec::with_capacity(12 + slots.len() + string_pool.len());
+    table.extend_from_slice(&(num_slots as u32).to_le_bytes());
+    table.extend_from_slice(&(num_entries as u32).to_le_bytes());
+    table.extend_from_slice(&(string_pool.len() as u32).to_le_bytes());
+    table.extend_from_slice(&slots);
+    table.extend_from_slice(&string_pool);
+    table
+}
+
+pub fn build_frozen_map(entries: &[(Vec<u8>, u32)]) -> Vec<u8> {
+    let (keys, values): (Vec<_>, Vec<_>) = entries.iter().cloned().unzip();
+    build_frozen_table(&keys, Some(&values), 18)
+}
+
+pub fn build_frozen_set(keys: &[Vec<u8>]) -> Vec<u8> {
+    build_frozen_table(keys, None, 14)
+}
+
+/// ID-indexed token table: `[count][offsets × count+1][pool]`. IDs with no
+/// entry get an empty slice.
+pub fn build_string_array(entries: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let count = entries
+        .iter()
+        .map(|(id, _)| *id as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut slots: Vec<&[u8]> = vec![&[]; count];
+    for (id, bytes) in entries {
+        slots[*id as usize] = bytes;
+    }
+    let mut out = Vec::with_capacity(4 + (count + 1) * 4);
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+    let mut off = 0u32;
+    out.extend_from_slice(&off.to_le_bytes());
+    for slot in &slots {
+        off += slot.len() as u32;
+        out.extend_from_slice(&off.to_le_bytes());
+    }
+    for slot in &slots {
+        out.extend_from_slice(slot);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_decoder(decoder: &str) -> Result<Vec<u8>, String> {
+        let json = format!(
+            r#"{{"decoder": {}, "model": {{"type": "BPE", "vocab": {{"a": 0}}, "merges": []}}}}"#,
+            decoder
+        );
+        build_hf_frozen("test", &json)
+    }
+
+    #[test]
+    fn unknown_decoders_are_rejected() {
+        assert!(with_decoder(r#"{"type": "Sequence", "decoders": [{"type": "Fuse"}]}"#).is_ok());
+        for decoder in [
+            r#"{"type": "CTC"}"#,
+            r#"{"type": "Sequence", "decoders": [{"type": "Fuse"}, {"type": "BPEDecoder"}]}"#,
+            r#"{"type": "Replace", "pattern": {"Regex": " +"}, "content": " "}"#,
+        ] {
+            let err = with_decoder(decoder).unwrap_err();
+            assert!(err.contains("unsupported"), "{}: {}", decoder, err);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors returned when resolving or loading a tokenizer model, or reading
+/// its input.
+#[derive(Debug)]
+pub enum Error {
+    /// The name is not one of [`crate::MODEL_NAMES`].
+    UnknownModel(String),
+    /// The model is known but its data was neither embedded at build time
+    /// nor found under `TOKEN_COUNT_MODELS` at runtime.
+    NotEmbedded(String),
+    /// A model file could not be read.
+    Io { path: PathBuf, source: io::Error },
+    /// The model data exists but could not be parsed.
+    InvalidModel { model: String, reason: String },
+    /// A chat payload is not a message list this crate can count.
+    InvalidChat(String),
+    /// A model's chat template failed to parse or render.
+    ChatTemplate(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownModel(name) => write!(
+                f,
+                "unknown model '{}'\nAvailable: {}",
+                name,
+                crate::MODEL_NAMES.join(", ")
+            ),
+            Error::NotEmbedded(name) => write!(
+                f,
+                "{} model not embedded (build with TOKEN_COUNT_MODELS, or set it at runtime)",
+                name
+            ),
+            Error::Io { path, source } => write!(f, "cannot read {}: {}", path.display(), source),
+            Error::InvalidModel { model, reason } => {
+                write!(f, "invalid {} model: {}", model, reason)
+            }
+            Error::InvalidChat(reason) => write!(f, "invalid chat payload: {}", reason),
+            Error::ChatTemplate(reason) => write!(f, "chat template: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Self-describing container for frozen model blobs.
+//!
+//! Every blob, embedded or loaded from disk, starts with a header naming
+//! what it holds and where each part lives. All integers are little-endian:
+//!
+//! ```text
+//! magic        8 bytes   "TCFROZEN"
+//! version      u32       FORMAT_VERSION
+//! kind         u32       KIND_*
+//! name         u32 length + UTF-8 bytes
+//! source_hash  u64       FNV-1a of the file the model was converted from
+//! sections     u32 count, then (tag u32, offset u32, length u32) × count
+//! payload      section bytes; offsets are from the start of the blob
+//! ```
+//!
+//! Parsing checks the header and that every section lies inside the blob;
+//! the backends then check each section's own layout, down to every hash
+//! slot and string offset, so a corrupt blob fails to load rather than
+//! panicking in a lookup. Section contents are not checksummed.
+
+use crate::frozen::{fnv_hash, get_bytes, get_u32, get_u64};
+
+pub const MAGIC: &[u8; 8] = b"TCFROZEN";
+/// Bump whenever the layout of the header or of any section changes.
+pub const FORMAT_VERSION: u32 = 7;
+
+pub const KIND_CLAUDE: u32 = 1;
+pub const KIND_TIKTOKEN: u32 = 2;
+pub const KIND_HF_BPE: u32 = 3;
+pub const KIND_HF_UNIGRAM: u32 = 4;
+pub const KIND_HF_WORDPIECE: u32 = 5;
+
+/// HF: byte_fallback, post-processor single and pair templates, normalizer,
+/// pre-tokenizer, decoder.
+/// tiktoken: encoding name and pre-tokenizer pattern.
+pub const SEC_CONFIG: u32 = 1;
+/// Token → ID frozen map (tiktoken: token bytes → rank).
+pub const SEC_VOCAB: u32 = 2;
+/// ID → token string array, for decoding.
+pub const SEC_ID_TOKENS: u32 = 3;
+/// HF merges map (`"a\0b"` → rank) and the sets of left and right parts.
+pub const SEC_MERGES: u32 = 4;
+pub const SEC_MERGE_LEFT: u32 = 5;
+pub const SEC_MERGE_RIGHT: u32 = 6;
+/// Double-array trie (Claude, Unigram and WordPiece vocabularies): `[array_size][root][vocab_len][base][check][ids]`.
+pub const SEC_TRIE: u32 = 7;
+/// HF `added_tokens` or tiktoken special tokens: `[count]` then
+/// `[id u32][flags u8][content]` each.
+pub const SEC_ADDED_TOKENS: u32 = 8;
+/// Unigram piece scores: `[unk_id][count][min score f64]` then one f64 per
+/// ID. `unk_id` is `u32::MAX` when the model has none.
+pub const SEC_SCORES: u32 = 9;
+/// WordPiece parameters: `[unk_id][max_input_chars_per_word]` then the
+/// continuing-subword prefix as a length-prefixed string. `unk_id` is
+/// `u32::MAX` when the unk token is not in the vocab.
+pub const SEC_WORDPIECE: u32 = 10;
+/// BPE options: `[ignore_merges u8][fuse_unk u8]` then the unk token
+/// (empty for none), continuing-subword prefix and end-of-word suffix as
+/// length-prefixed strings.
+pub const SEC_BPE: u32 = 11;
+/// Optional HF chat template from `tokenizer_config.json`: the BOS token,
+/// EOS token and Jinja source as length-prefixed strings.
+pub const SEC_CHAT_TEMPLATE: u32 = 12;
+
+pub fn kind_name(kind: u32) -> &'static str {
+    match kind {
+        KIND_CLAUDE => "claude-trie",
+        KIND_TIKTOKEN => "tiktoken",
+        KIND_HF_BPE => "hf-bpe",
+        KIND_HF_UNIGRAM => "hf-unigram",
+        KIND_HF_WORDPIECE => "hf-wordpiece",
+        _ => "unknown",
+    }
+}
+
+pub fn section_name(tag: u32) -> &'static str {
+    match tag {
+        SEC_CONFIG => "config",
+        SEC_VOCAB => "vocab",
+        SEC_ID_TOKENS => "id-tokens",
+        SEC_MERGES => "merges",
+        SEC_MERGE_LEFT => "merge-left",
+        SEC_MERGE_RIGHT => "merge-right",
+        SEC_TRIE => "trie",
+        SEC_ADDED_TOKENS => "added-tokens",
+        SEC_SCORES => "scores",
+        SEC_WORDPIECE => "wordpiece",
+        SEC_BPE => "bpe",
+        SEC_CHAT_TEMPLATE => "chat-template",
+        _ => "unknown",
+    }
+}
+
+/// Hash recorded as `source_hash`, so a frozen file can be matched against
+/// the file it was built from.
+pub fn source_hash(source: &[u8]) -> u64 {
+    fnv_hash(source)
+}
+
+/// Assemble a blob from its sections, in the order given.
+pub fn write_frozen(kind: u32, name: &str, source_hash: u64, sections: &[(u32, &[u8])]) -> Vec<u8> {
+    let header_len = MAGIC.len() + 4 + 4 + 4 + name.len() + 8 + 4 + sections.len() * 12;
+    let payload_len: usize = sections.iter().map(|(_, s)| s.len()).sum();
+    let mut out = Vec::with_capacity(header_len + payload_len);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(&source_hash.to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    let mut off = header_len;
+    for (tag, bytes) in sections {
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&(off as u32).to_le_bytes());
+        out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        off += bytes.len();
+    }
+    for (_, bytes) in sections {
+        out.extend_from_slice(bytes);
+    }
+    out
+}
+
+/// Rewrite a blob with `bytes` as its `tag` section, replacing any section
+/// it already has with that tag.
+pub fn add_section(data: &[u8], tag: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let header = Header::parse(data)?;
+    let mut sections: Vec<(u32, &[u8])> = header
+        .sections
+        .iter()
+        .filter(|s| s.0 != tag)
+        .map(|&(t, start, len)| (t, &data[start..start + len]))
+        .collect();
+    sections.push((tag, bytes));
+    Ok(write_frozen(
+        header.kind,
+        header.name,
+        header.source_hash,
+        &sections,
+    ))
+}
+
+/// Whether `data` starts with the frozen-blob magic.
+pub fn is_frozen(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// A parsed, bounds-checked header.
+pub struct Header<'a> {
+    pub version: u32,
+    pub kind: u32,
+    pub name: &'a str,
+    pub source_hash: u64,
+    /// `(tag, offset, length)` of each section, in file order.
+    pub sections: Vec<(u32, usize, usize)>,
+    data: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, String> {
+        if !is_frozen(data) {
+            return Err("not a frozen model (bad magic)".to_string());
+        }
+        let mut off = MAGIC.len();
+        let version = get_u32(data, off)?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "frozen format version {} is not supported (expected {}); re-freeze the model",
+                version, FORMAT_VERSION
+            ));
+        }
+        let kind = get_u32(data, off + 4)?;
+        off += 8;
+        let name_len = get_u32(data, off)? as usize;
+        let name = std::str::from_utf8(get_bytes(data, off + 4, name_len)?)
+            .map_err(|e| format!("invalid model name: {e}"))?;
+        off += 4 + name_len;
+        let source_hash = get_u64(data, off)?;
+        off += 8;
+        let count = get_u32(data, off)? as usize;
+        off += 4;
+        let mut sections = Vec::new();
+        for i in 0..count {
+            let entry = off + i * 12;
+            let tag = get_u32(data, entry)?;
+            let start = get_u32(data, entry + 4)? as usize;
+            let len = get_u32(data, entry + 8)? as usize;
+            if start.checked_add(len).is_none_or(|end| end > data.len()) {
+                return Err(format!(
+                    "{} section ({} bytes at offset {}) extends past the end of the data ({} bytes); file truncated?",
+                    section_name(tag),
+                    len,
+                    start,
+                    data.len()
+                ));
+            }
+            sections.push((tag, start, len));
+        }
+        Ok(Header {
+            version,
+            kind,
+            name,
+            source_hash,
+            sections,
+            data,
+        })
+    }
+
+    /// Parse and check that the blob holds a model of the given kind.
+    pub fn parse_kind(data: &'a [u8], kind: u32) -> Result<Self, String> {
+        let header = Header::parse(data)?;
+        if header.kind != kind {
+            return Err(format!(
+                "expected a {} model, found {}",
+                kind_name(kind),
+                kind_name(header.kind)
+            ));
+        }
+        Ok(header)
+    }
+
+    /// Byte range of a required section.
+    pub fn section(&self, tag: u32) -> Result<std::ops::Range<usize>, String> {
+        self.sections
+            .iter()
+            .find(|s| s.0 == tag)
+            .map(|&(_, start, len)| start..start + len)
+            .ok_or_else(|| format!("missing {} section", section_name(tag)))
+    }
+
+    /// Byte range of an optional section, if the blob has one.
+    pub fn optional_section(&self, tag: u32) -> Option<std::ops::Range<usize>> {
+        self.section(tag).ok()
+    }
+
+    /// Byte range of a required section, after checking its layout with `check`.
+    pub fn checked_section(
+        &self,
+        tag: u32,
+        check: fn(&[u8]) -> Result<(), String>,
+    ) -> Result<std::ops::Range<usize>, String> {
+        let range = self.section(tag)?;
+        check(&self.data[range.clone()])
+            .map_err(|e| format!("{} section: {}", section_name(tag), e))?;
+        Ok(range)
+    }
+}
@@ -59,7 +59,7 @@ pub fn fnv_hash_concat(a: &[u8], b: &[u8]) -> u64 {
 /// Lemire fast range reduction: maps a u64 hash into [0, n) via
 /// fixed-point multiply — one `mul` + shift, no division.
 #[inline(always)]
-fn fast_reduce(h: u64, n: usize) -> usize {
+pub fn fast_reduce(h: u64, n: usize) -> usize {
     ((h as u128).wrapping_mul(n as u128) >> 64) as usize
 }
 
@@ -91,7 +91,9 @@ pub fn frozen_map_get_pair(table: &[u8], left: &[u8], right: &[u8]) -> Option<u3
             }
         }
         idx += 1;
-        if idx == num_slots { idx = 0; }
+        if idx == num_slots {
+            idx = 0;
+        }
     }
     None
 }
@@ -124,11 +126,18 @@ pub fn frozen_map_get_concat(table: &[u8], a: &[u8], b: &[u8]) -> Option<u32> {
             }
         }
         idx += 1;
-        if idx == num_slots { idx = 0; }
+        if idx == num_slots {
+            idx = 0;
+        }
     }
     None
 }
 
+#[inline]
+pub fn frozen_map_get(table: &[u8], key: &[u8]) -> Option<u32> {
+    frozen_map_get_concat(table, key, &[])
+}
+
 #[inline]
 pub fn frozen_set_contains(table: &[u8], key: &[u8]) -> bool {
     let num_slots = read_u32(table, 0) as usize;
@@ -151,21 +160,159 @@ pub fn frozen_set_contains(table: &[u8], key: &[u8]) -> bool {
             }
         }
         idx += 1;
-        if idx == num_slots { idx = 0; }
+        if idx == num_slots {
+            idx = 0;
+        }
     }
     false
 }
 
-pub fn frozen_map_byte_len(table: &[u8]) -> usize {
-    let num_slots = read_u32(table, 0) as usize;
-    let string_pool_len = read_u32(table, 8) as usize;
-    MAP_HEADER + num_slots * MAP_SLOT + string_pool_len
+/// Check that `table` is exactly one frozen map and that every key lies in
+/// its pool, so lookups stay in bounds.
+pub fn check_frozen_map(table: &[u8]) -> Result<(), String> {
+    check_table(table, MAP_HEADER, MAP_SLOT, "hash map")
 }
 
-pub fn frozen_set_byte_len(table: &[u8]) -> usize {
-    let num_slots = read_u32(table, 0) as usize;
-    let string_pool_len = read_u32(table, 8) as usize;
-    SET_HEADER + num_slots * SET_SLOT + string_pool_len
+/// Check that `table` is exactly one frozen set, as [`check_frozen_map`].
+pub fn check_frozen_set(table: &[u8]) -> Result<(), String> {
+    check_table(table, SET_HEADER, SET_SLOT, "hash set")
+}
+
+fn check_table(table: &[u8], header: usize, slot: usize, what: &str) -> Result<(), String> {
+    let num_slots = get_u32(table, 0)? as usize;
+    let num_entries = get_u32(table, 4)? as usize;
+    let pool_len = get_u32(table, 8)? as usize;
+    let expected = num_slots
+        .checked_mul(slot)
+        .and_then(|n| n.checked_add(header + pool_len));
+    if num_slots == 0 || num_entries > num_slots || expected != Some(table.len()) {
+        return Err(format!(
+            "malformed {}: {} slots, {} entries and a {}-byte pool in {} bytes",
+            what,
+            num_slots,
+            num_entries,
+            pool_len,
+            table.len()
+        ));
+    }
+    for i in 0..num_slots {
+        let slot_off = header + i * slot;
+        if read_u64(table, slot_off) == 0 {
+            continue;
+        }
+        let key_off = read_u32(table, slot_off + 8) as usize;
+        let key_len = read_u16(table, slot_off + 12) as usize;
+        if key_off + key_len > pool_len {
+            return Err(format!(
+                "malformed {}: slot {} key at {}..{} outside the {}-byte pool",
+                what,
+                i,
+                key_off,
+                key_off + key_len,
+                pool_len
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `(num_slots, num_entries)` of a frozen map or set.
+pub fn table_occupancy(table: &[u8]) -> (usize, usize) {
+    (read_u32(table, 0) as usize, read_u32(table, 4) as usize)
+}
+
+/// Check that `table` is exactly one frozen string array whose offsets run
+/// in order through its pool.
+pub fn check_string_array(table: &[u8]) -> Result<(), String> {
+    let count = get_u32(table, 0)? as usize;
+    let pool_off = count
+        .checked_add(1)
+        .and_then(|n| n.checked_mul(4))
+        .and_then(|n| n.checked_add(4))
+        .filter(|&n| n <= table.len())
+        .ok_or_else(|| {
+            format!(
+                "malformed string array: {} entries in {} bytes",
+                count,
+                table.len()
+            )
+        })?;
+    let pool_len = read_u32(table, pool_off - 4) as usize;
+    if pool_off + pool_len != table.len() {
+        return Err(format!(
+            "malformed string array: {}-byte pool in {} bytes",
+            pool_len,
+            table.len() - pool_off
+        ));
+    }
+    let mut prev = 0;
+    for i in 0..=count {
+        let off = read_u32(table, 4 + i * 4) as usize;
+        if off < prev || off > pool_len {
+            return Err(format!(
+                "malformed string array: offset {} of entry {} out of order or past the {}-byte pool",
+                off, i, pool_len
+            ));
+        }
+        prev = off;
+    }
+    Ok(())
+}
+
+/// Number of IDs in a frozen string array, including empty ones.
+pub fn string_array_len(table: &[u8]) -> usize {
+    read_u32(table, 0) as usize
+}
+
+/// Entry `i` of a frozen string array (`[count][offsets × count+1][pool]`).
+/// Empty entries mark IDs with no token and read as `None`.
+#[inline]
+pub fn string_array_get(table: &[u8], i: usize) -> Option<&[u8]> {
+    let count = read_u32(table, 0) as usize;
+    if i >= count {
+        return None;
+    }
+    let pool_off = 4 + (count + 1) * 4;
+    let start = read_u32(table, 4 + i * 4) as usize;
+    let end = read_u32(table, 8 + i * 4) as usize;
+    if start == end {
+        return None;
+    }
+    Some(&table[pool_off + start..pool_off + end])
+}
+
+/// Bounds-checked reads for headers and config sections, where a truncated
+/// blob must produce an error rather than a panic.
+pub fn get_bytes(data: &[u8], off: usize, len: usize) -> Result<&[u8], String> {
+    off.checked_add(len)
+        .and_then(|end| data.get(off..end))
+        .ok_or_else(|| {
+            format!(
+                "unexpected end of data at offset {} (reading {} bytes)",
+                off, len
+            )
+        })
+}
+
+pub fn get_u8(data: &[u8], off: usize) -> Result<u8, String> {
+    Ok(get_bytes(data, off, 1)?[0])
+}
+
+pub fn get_u32(data: &[u8], off: usize) -> Result<u32, String> {
+    Ok(read_u32(get_bytes(data, off, 4)?, 0))
+}
+
+pub fn get_u64(data: &[u8], off: usize) -> Result<u64, String> {
+    Ok(read_u64(get_bytes(data, off, 8)?, 0))
+}
+
+/// A `[len u32][UTF-8 bytes]` string and the number of bytes it occupies.
+pub fn get_str(data: &[u8], off: usize) -> Result<(String, usize), String> {
+    let len = get_u32(data, off)? as usize;
+    let s = std::str::from_utf8(get_bytes(data, off + 4, len)?)
+        .map_err(|e| format!("invalid UTF-8 in frozen blob: {e}"))?
+        .to_string();
+    Ok((s, 4 + len))
 }
 
 #[inline(always)]
@@ -182,3 +329,38 @@ pub fn read_u16(data: &[u8], off: usize) -> u16 {
 pub fn read_u64(data: &[u8], off: usize) -> u64 {
     u64::from_le_bytes(data[off..off + 8].try_into().unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{build_frozen_map, build_string_array};
+
+    #[test]
+    fn map_slot_outside_the_pool_is_rejected() {
+        let mut table = build_frozen_map(&[(b"he".to_vec(), 0), (b"llo".to_vec(), 1)]);
+        assert!(check_frozen_map(&table).is_ok());
+        let num_slots = read_u32(&table, 0) as usize;
+        let slot = (0..num_slots)
+            .find(|&i| read_u64(&table, MAP_HEADER + i * MAP_SLOT) != 0)
+            .unwrap();
+        table[MAP_HEADER + slot * MAP_SLOT + 8..][..4].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(check_frozen_map(&table).unwrap_err().contains("outside"));
+    }
+
+    #[test]
+    fn string_array_offsets_must_stay_in_order_inside_the_pool() {
+        let table = build_string_array(&[(0, b"he".to_vec()), (2, b"llo".to_vec())]);
+        assert!(check_string_array(&table).is_ok());
+        assert_eq!(string_array_get(&table, 2), Some(&b"llo"[..]));
+        for (entry, off) in [(1, 9u32), (2, 1)] {
+            let mut bad = table.clone();
+            bad[4 + entry * 4..][..4].copy_from_slice(&off.to_le_bytes());
+            assert!(
+                check_string_array(&bad).is_err(),
+                "offset {} of entry {}",
+                off,
+                entry
+            );
+        }
+    }
+}
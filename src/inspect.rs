@@ -0,0 +1,106 @@
+//! Human-readable report on a frozen model blob, for `tokencount inspect`.
+
+use crate::format::{self, Header};
+use crate::{added, bpe, claude, frozen, pipeline, template, tiktoken, unigram, wordpiece};
+
+/// Describe a frozen blob: its header, section table, and what each section
+/// holds (pipeline configuration, entry counts, hash table load factors).
+/// Fails on the first malformed part, naming it.
+pub fn describe_frozen(data: &[u8]) -> Result<String, String> {
+    let header = Header::parse(data)?;
+    let mut out = String::new();
+    let mut field = |label: &str, value: String| {
+        out.push_str(&format!("{:<15} {}\n", label, value));
+    };
+    field("format", format!("v{}", header.version));
+    field("kind", format::kind_name(header.kind).to_string());
+    field("name", header.name.to_string());
+    field("source hash", format!("{:016x}", header.source_hash));
+    field("size", format!("{} bytes", data.len()));
+
+    out.push_str(&format!(
+        "\n{:<15} {:>10} {:>10}\n",
+        "section", "offset", "bytes"
+    ));
+    for &(tag, start, len) in &header.sections {
+        out.push_str(&format!(
+            "{:<15} {:>10} {:>10}\n",
+            format::section_name(tag),
+            start,
+            len
+        ));
+    }
+    out.push('\n');
+
+    for &(tag, start, len) in &header.sections {
+        let section = &data[start..start + len];
+        let name = format::section_name(tag);
+        let context = |e: String| format!("{} section: {}", name, e);
+        match tag {
+            format::SEC_CONFIG => {
+                let lines = if header.kind == format::KIND_TIKTOKEN {
+                    tiktoken::describe_config(section)
+                } else {
+                    pipeline::describe_config(section)
+                };
+                for (label, value) in lines.map_err(context)? {
+                    out.push_str(&format!("{:<15} {}\n", label, value));
+                }
+            }
+            format::SEC_VOCAB | format::SEC_MERGES => {
+                frozen::check_frozen_map(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, table_stats(section)));
+            }
+            format::SEC_MERGE_LEFT | format::SEC_MERGE_RIGHT => {
+                frozen::check_frozen_set(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, table_stats(section)));
+            }
+            format::SEC_ID_TOKENS => {
+                frozen::check_string_array(section).map_err(context)?;
+                let ids = frozen::string_array_len(section);
+                out.push_str(&format!("{:<15} {} IDs\n", name, ids));
+            }
+            format::SEC_TRIE => {
+                claude::check_trie(section).map_err(context)?;
+                let slots = frozen::read_u32(section, 0);
+                let vocab_len = frozen::read_u32(section, 8);
+                out.push_str(&format!(
+                    "{:<15} {} tokens in {} slots\n",
+                    name, vocab_len, slots
+                ));
+            }
+            format::SEC_SCORES => {
+                let summary = unigram::describe_scores(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, summary));
+            }
+            format::SEC_BPE => {
+                let summary = bpe::describe_options(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, summary));
+            }
+            format::SEC_WORDPIECE => {
+                let summary = wordpiece::describe_params(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, summary));
+            }
+            format::SEC_CHAT_TEMPLATE => {
+                let summary = template::describe_section(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, summary));
+            }
+            format::SEC_ADDED_TOKENS => {
+                let summary = added::describe_added_tokens(section).map_err(context)?;
+                out.push_str(&format!("{:<15} {}\n", name, summary));
+            }
+            _ => out.push_str(&format!("{:<15} unknown tag {}\n", name, tag)),
+        }
+    }
+    Ok(out)
+}
+
+fn table_stats(table: &[u8]) -> String {
+    let (slots, entries) = frozen::table_occupancy(table);
+    format!(
+        "{} entries, {} slots ({:.1}% load)",
+        entries,
+        slots,
+        entries as f64 * 100.0 / slots as f64
+    )
+}
@@ -0,0 +1,348 @@
+//! Offline token counting for LLM tokenizers.
+//!
+//! All backends are implemented from scratch over data frozen at build time
+//! (see `build.rs`) or converted from the upstream files at load time. Use
+//! [`load_model`] to get a [`Tokenizer`] by name, or [`load_model_file`] for
+//! a `tokenizer.json` / `.tiktoken` file:
+//!
+//! ```no_run
+//! let tok = tokencount::load_model("claude").unwrap();
+//! assert!(tok.count_tokens("Hello world") > 0);
+//! ```
+
+mod added;
+pub mod bpe;
+mod byte_level;
+pub mod chat;
+pub mod claude;
+mod convert;
+mod error;
+pub mod format;
+mod frozen;
+mod inspect;
+mod models;
+mod normalized;
+mod pipeline;
+pub mod split;
+pub mod template;
+pub mod tiktoken;
+pub mod tools;
+pub mod unigram;
+pub mod wordpiece;
+
+pub use error::Error;
+pub use inspect::describe_frozen;
+pub use models::{
+    embedded_data, freeze_model, freeze_model_file, load_model, load_model_file, ModelFile,
+    MODEL_NAMES,
+};
+
+/// One token of an encoding and the byte range of the input it came from.
+///
+/// Tokens that split a multi-byte character (byte-level or byte-fallback
+/// pieces) have ranges that are not on `char` boundaries. Tokens inserted
+/// by the model rather than read from the input, such as a BOS marker, have
+/// an empty range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub id: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// How special-token strings in the input (`<|endoftext|>`, `<s>`, ...)
+/// are encoded, like tiktoken's `allowed_special` / `disallowed_special`.
+/// Added tokens that are not marked special always match.
+///
+/// The default differs from tiktoken, whose `encode()` raises on any
+/// special-token string unless it is allowed: a counter should count
+/// whatever it is given rather than fail on it. Callers that want
+/// tiktoken's behavior check [`Tokenizer::find_special_token`] first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SpecialTokens {
+    /// Encode each as its single special token.
+    #[default]
+    Allow,
+    /// Encode the listed ones as special tokens and the rest as ordinary
+    /// text, like tiktoken's `allowed_special={...}, disallowed_special=()`.
+    AllowOnly(Vec<String>),
+    /// Encode them as ordinary text.
+    Plain,
+}
+
+impl SpecialTokens {
+    /// Whether the special token `content` is encoded as itself.
+    pub fn allows(&self, content: &str) -> bool {
+        match self {
+            SpecialTokens::Allow => true,
+            SpecialTokens::AllowOnly(allowed) => allowed.iter().any(|a| a == content),
+            SpecialTokens::Plain => false,
+        }
+    }
+}
+
+/// Convert byte-offset spans into `char` offsets of `text`, rounding
+/// boundaries that fall inside a character outward to include it.
+pub fn char_spans(text: &str, spans: &[TokenSpan]) -> Vec<(usize, usize)> {
+    // char_index[b] = index of the char containing byte b.
+    let mut char_index = vec![0; text.len() + 1];
+    let mut count = 0;
+    for (n, (i, c)) in text.char_indices().enumerate() {
+        char_index[i..i + c.len_utf8()].fill(n);
+        count = n + 1;
+    }
+    char_index[text.len()] = count;
+    spans
+        .iter()
+        .map(|t| {
+            let end = char_index[t.end] + usize::from(!text.is_char_boundary(t.end));
+            (char_index[t.start], end)
+        })
+        .collect()
+}
+
+/// Common interface implemented by every tokenizer backend.
+pub trait Tokenizer: Send + Sync {
+    /// Number of tokens `text` encodes to.
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// Number of tokens the pair `(a, b)` encodes to, as for a cross-encoder
+    /// or reranker input.
+    fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        self.count_tokens(a) + self.count_tokens(b)
+    }
+
+    /// Vocabulary IDs of the tokens `text` encodes to, including any
+    /// special tokens the model's post-processor adds (unless turned off
+    /// with [`set_add_special_tokens`](Tokenizer::set_add_special_tokens)).
+    fn encode(&self, text: &str) -> Vec<u32>;
+
+    /// Vocabulary IDs of the pair `(a, b)`, joined by the post-processor's
+    /// pair template (`[CLS] a [SEP] b [SEP]` for BERT). Backends without
+    /// one concatenate the two encodings.
+    fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        let mut ids = self.encode(a);
+        ids.extend(self.encode(b));
+        ids
+    }
+
+    /// Like [`encode`](Tokenizer::encode), with each token's byte range in
+    /// `text`.
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan>;
+
+    /// Raw bytes the token IDs decode to. Unknown IDs are skipped.
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8>;
+
+    /// Choose how special-token strings in the input are encoded. Backends
+    /// without special tokens ignore it.
+    fn set_special_tokens(&mut self, _mode: SpecialTokens) {}
+
+    /// Whether to add the special tokens of the model's post-processor (BOS,
+    /// EOS, `[CLS]`/`[SEP]`) around each input; on by default. Turn it off
+    /// when summing over many files or chunks that will be sent as one.
+    fn set_add_special_tokens(&mut self, _add: bool) {}
+
+    /// The model's chat template, for HF models frozen with their
+    /// `tokenizer_config.json`.
+    fn chat_template(&self) -> Option<&template::ChatTemplate> {
+        None
+    }
+
+    /// Whether the ChatML markers the OpenAI API counts as single tokens
+    /// (`<|im_start|>`, `<|im_end|>`, `<|im_sep|>`) are special tokens;
+    /// off by default, as they are not in tiktoken's tables. Only the
+    /// tiktoken backend has them.
+    fn set_chat_markers(&mut self, _on: bool) {}
+
+    /// Byte range of the first special-token string in `text` that the
+    /// current [`SpecialTokens`] mode encodes as ordinary text; for
+    /// rejecting such input outright, as tiktoken's `disallowed_special`.
+    fn find_special_token(&self, _text: &str) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Decode token IDs to text, failing if the bytes are not valid UTF-8
+    /// (e.g. a byte-level sequence cut in the middle of a character).
+    fn decode(&self, ids: &[u32]) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.decode_bytes(ids))
+    }
+
+    /// Decode token IDs to text, replacing invalid UTF-8 with U+FFFD.
+    fn decode_lossy(&self, ids: &[u32]) -> String {
+        String::from_utf8_lossy(&self.decode_bytes(ids)).into_owned()
+    }
+}
+
+impl Tokenizer for claude::DATrie<'_> {
+    fn count_tokens(&self, text: &str) -> usize {
+        claude::DATrie::count_tokens(self, text)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u32> {
+        claude::DATrie::encode(self, text)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        claude::DATrie::encode_with_offsets(self, text)
+    }
+
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        claude::DATrie::decode_bytes(self, ids)
+    }
+}
+
+impl Tokenizer for tiktoken::TiktokenTokenizer<'_> {
+    fn count_tokens(&self, text: &str) -> usize {
+        tiktoken::TiktokenTokenizer::count_tokens(self, text)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u32> {
+        tiktoken::TiktokenTokenizer::encode(self, text)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        tiktoken::TiktokenTokenizer::encode_with_offsets(self, text)
+    }
+
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        tiktoken::TiktokenTokenizer::decode_bytes(self, ids)
+    }
+
+    fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        tiktoken::TiktokenTokenizer::set_special_tokens(self, mode)
+    }
+
+    fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        tiktoken::TiktokenTokenizer::find_special_token(self, text)
+    }
+
+    fn set_chat_markers(&mut self, on: bool) {
+        tiktoken::TiktokenTokenizer::set_chat_markers(self, on)
+    }
+}
+
+impl Tokenizer for bpe::HfTokenizer<'_> {
+    fn count_tokens(&self, text: &str) -> usize {
+        bpe::HfTokenizer::count_tokens(self, text)
+    }
+
+    fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        bpe::HfTokenizer::count_pair_tokens(self, a, b)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u32> {
+        bpe::HfTokenizer::encode(self, text)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        bpe::HfTokenizer::encode_with_offsets(self, text)
+    }
+
+    fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        bpe::HfTokenizer::encode_pair(self, a, b)
+    }
+
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        bpe::HfTokenizer::decode_bytes(self, ids)
+    }
+
+    fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        bpe::HfTokenizer::set_special_tokens(self, mode)
+    }
+
+    fn set_add_special_tokens(&mut self, add: bool) {
+        bpe::HfTokenizer::set_add_special_tokens(self, add)
+    }
+
+    fn chat_template(&self) -> Option<&template::ChatTemplate> {
+        bpe::HfTokenizer::chat_template(self)
+    }
+
+    fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        bpe::HfTokenizer::find_special_token(self, text)
+    }
+}
+
+impl Tokenizer for unigram::UnigramTokenizer<'_> {
+    fn count_tokens(&self, text: &str) -> usize {
+        unigram::UnigramTokenizer::count_tokens(self, text)
+    }
+
+    fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        unigram::UnigramTokenizer::count_pair_tokens(self, a, b)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u32> {
+        unigram::UnigramTokenizer::encode(self, text)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        unigram::UnigramTokenizer::encode_with_offsets(self, text)
+    }
+
+    fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        unigram::UnigramTokenizer::encode_pair(self, a, b)
+    }
+
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        unigram::UnigramTokenizer::decode_bytes(self, ids)
+    }
+
+    fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        unigram::UnigramTokenizer::set_special_tokens(self, mode)
+    }
+
+    fn set_add_special_tokens(&mut self, add: bool) {
+        unigram::UnigramTokenizer::set_add_special_tokens(self, add)
+    }
+
+    fn chat_template(&self) -> Option<&template::ChatTemplate> {
+        unigram::UnigramTokenizer::chat_template(self)
+    }
+
+    fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        unigram::UnigramTokenizer::find_special_token(self, text)
+    }
+}
+
+impl Tokenizer for wordpiece::WordPieceTokenizer<'_> {
+    fn count_tokens(&self, text: &str) -> usize {
+        wordpiece::WordPieceTokenizer::count_tokens(self, text)
+    }
+
+    fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        wordpiece::WordPieceTokenizer::count_pair_tokens(self, a, b)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u32> {
+        wordpiece::WordPieceTokenizer::encode(self, text)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        wordpiece::WordPieceTokenizer::encode_with_offsets(self, text)
+    }
+
+    fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        wordpiece::WordPieceTokenizer::encode_pair(self, a, b)
+    }
+
+    fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        wordpiece::WordPieceTokenizer::decode_bytes(self, ids)
+    }
+
+    fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        wordpiece::WordPieceTokenizer::set_special_tokens(self, mode)
+    }
+
+    fn set_add_special_tokens(&mut self, add: bool) {
+        wordpiece::WordPieceTokenizer::set_add_special_tokens(self, add)
+    }
+
+    fn chat_template(&self) -> Option<&template::ChatTemplate> {
+        wordpiece::WordPieceTokenizer::chat_template(self)
+    }
+
+    fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        wordpiece::WordPieceTokenizer::find_special_token(self, text)
+    }
+}
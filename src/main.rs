@@ -1,64 +1,113 @@
-mod bpe;
-mod byte_level;
-mod claude;
-mod frozen;
-mod tiktoken;
-
-mod embedded {
-    include!(concat!(env!("OUT_DIR"), "/embedded_models.rs"));
-}
-
 use base64::Engine;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-
-const MODEL_NAMES: &[&str] = &[
-    "claude", "openai", "gemini", "deepseek", "qwen", "llama", "mistral", "grok", "minimax",
-];
+use tokencount::chat::{self, ChatCount, ChatFormat};
+use tokencount::tools;
+use tokencount::{Error, ModelFile, SpecialTokens, TokenSpan, Tokenizer, MODEL_NAMES};
 
 const DEFAULT_BASE_URL: &str = "https://tokencount.eordano.com/";
 
-enum Tokenizer {
-    Claude(claude::DATrie),
-    Tiktoken(tiktoken::TiktokenTokenizer),
-    Hf(bpe::HfTokenizer),
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Version of the `--format` record schema. Bump on any incompatible change
+/// to field names or meaning.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
 }
 
-impl Tokenizer {
-    fn count_tokens(&self, text: &str) -> usize {
-        match self {
-            Tokenizer::Claude(t) => t.count_tokens(text),
-            Tokenizer::Tiktoken(t) => t.count_tokens(text),
-            Tokenizer::Hf(t) => t.count_tokens(text),
-        }
-    }
+/// `--special`: how special-token strings in the input are handled.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SpecialPolicy {
+    /// Encode them as special tokens.
+    Allow,
+    /// Encode them as ordinary text.
+    Plain,
+    /// Refuse inputs that contain any.
+    Error,
 }
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+struct Input {
+    name: Option<String>,
+    text: String,
+}
+
+/// Exit status when a `--max-tokens*` budget is exceeded, distinct from the
+/// status 1 used for usage and I/O errors.
+const EXIT_OVER_BUDGET: i32 = 2;
+
+/// A `--max-tokens`/`--max-tokens-per-file` value: `N` applies to every
+/// selected model, `model=N` to one.
+struct TokenLimit {
+    model: Option<String>,
+    max: usize,
+}
+
+fn parse_limit(flag: &str, value: &str) -> TokenLimit {
+    let (model, n) = match value.split_once('=') {
+        Some((m, n)) => (Some(m.to_string()), n),
+        None => (None, value),
+    };
+    let max = n.parse().unwrap_or_else(|_| {
+        eprintln!("Error: {} expects N or model=N, got '{}'", flag, value);
+        std::process::exit(1);
+    });
+    TokenLimit { model, max }
+}
 
 struct Args {
     model: String,
+    model_file: Option<String>,
+    /// `None` until `--special` is given: the default depends on `--chat`
+    /// and `--tools`.
+    special: Option<SpecialPolicy>,
+    /// `--allowed-special`: the only special tokens encoded as such.
+    allowed_special: Option<Vec<String>>,
+    chat_markers: bool,
+    add_special_tokens: bool,
+    chat: Option<ChatFormat>,
+    tools: Option<ChatFormat>,
     all: bool,
     recursive: bool,
     gitignore: bool,
     ignore: Vec<String>,
     share: bool,
+    show_tokens: bool,
+    format: OutputFormat,
+    max_tokens: Vec<TokenLimit>,
+    max_tokens_per_file: Vec<TokenLimit>,
     help: bool,
     version: bool,
     paths: Vec<String>,
 }
 
-fn parse_args() -> Args {
-    let argv: Vec<String> = std::env::args().skip(1).collect();
+fn parse_args(argv: &[String]) -> Args {
     let mut args = Args {
         model: "claude".to_string(),
+        model_file: None,
+        special: None,
+        allowed_special: None,
+        chat_markers: false,
+        add_special_tokens: true,
+        chat: None,
+        tools: None,
         all: false,
         recursive: false,
         gitignore: true,
         ignore: Vec::new(),
         share: false,
+        show_tokens: false,
+        format: OutputFormat::Text,
+        max_tokens: Vec::new(),
+        max_tokens_per_file: Vec::new(),
         help: false,
         version: false,
         paths: Vec::new(),
@@ -72,7 +121,10 @@ fn parse_args() -> Args {
             "-r" | "--recursive" => args.recursive = true,
             "--no-gitignore" => args.gitignore = false,
             "-s" | "--share" => args.share = true,
+            "-t" | "--show-tokens" => args.show_tokens = true,
             "-a" | "--all" => args.all = true,
+            "--chat-markers" => args.chat_markers = true,
+            "--no-bos-eos" => args.add_special_tokens = false,
             "--ignore" => {
                 i += 1;
                 if i >= argv.len() {
@@ -89,6 +141,117 @@ fn parse_args() -> Args {
                 }
                 args.model = argv[i].clone();
             }
+            "--model-file" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --model-file requires a value");
+                    std::process::exit(1);
+                }
+                args.model_file = Some(argv[i].clone());
+            }
+            "--special" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --special requires a value");
+                    std::process::exit(1);
+                }
+                args.special = Some(match argv[i].as_str() {
+                    "allow" => SpecialPolicy::Allow,
+                    "plain" => SpecialPolicy::Plain,
+                    "error" => SpecialPolicy::Error,
+                    other => {
+                        eprintln!(
+                            "Error: unknown --special mode '{}'\nAvailable: allow, plain, error",
+                            other
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--chat" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --chat requires a value");
+                    std::process::exit(1);
+                }
+                args.chat = Some(match argv[i].as_str() {
+                    "openai" => ChatFormat::OpenAi,
+                    "anthropic" => ChatFormat::Anthropic,
+                    other => {
+                        eprintln!(
+                            "Error: unknown --chat format '{}'\nAvailable: openai, anthropic",
+                            other
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--allowed-special" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --allowed-special requires a value");
+                    std::process::exit(1);
+                }
+                let tokens = argv[i]
+                    .split(',')
+                    .filter(|t| !t.is_empty())
+                    .map(str::to_string);
+                args.allowed_special
+                    .get_or_insert_with(Vec::new)
+                    .extend(tokens);
+            }
+            "--tools" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --tools requires a value");
+                    std::process::exit(1);
+                }
+                args.tools = Some(match argv[i].as_str() {
+                    "openai" => ChatFormat::OpenAi,
+                    "anthropic" => ChatFormat::Anthropic,
+                    other => {
+                        eprintln!(
+                            "Error: unknown --tools format '{}'\nAvailable: openai, anthropic",
+                            other
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "-f" | "--format" => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: --format requires a value");
+                    std::process::exit(1);
+                }
+                args.format = match argv[i].as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "ndjson" => OutputFormat::Ndjson,
+                    "csv" => OutputFormat::Csv,
+                    "tsv" => OutputFormat::Tsv,
+                    other => {
+                        eprintln!(
+                            "Error: unknown format '{}'\nAvailable: text, json, ndjson, csv, tsv",
+                            other
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            flag @ ("--max-tokens" | "--max-tokens-per-file") => {
+                i += 1;
+                if i >= argv.len() {
+                    eprintln!("Error: {} requires a value", flag);
+                    std::process::exit(1);
+                }
+                let limit = parse_limit(flag, &argv[i]);
+                if flag == "--max-tokens" {
+                    args.max_tokens.push(limit);
+                } else {
+                    args.max_tokens_per_file.push(limit);
+                }
+            }
             s if s.starts_with('-') => {
                 eprintln!("Error: unknown option: {}", s);
                 std::process::exit(1);
@@ -108,19 +271,73 @@ fn print_help() {
          \n\
          Options:\n\
          \x20 -m, --model <name>   Tokenizer model (default: claude)\n\
+         \x20 --model-file <path>  Load a tokenizer.json, .tiktoken or frozen file\n\
          \x20 -a, --all            Show counts for all models\n\
          \x20 -r, --recursive      Recurse into directories\n\
          \x20 --ignore <pattern>   Skip files/dirs matching pattern (repeatable)\n\
          \x20 --no-gitignore       Don't skip .gitignore'd files when recursing\n\
          \x20 -s, --share          Print a shareable URL instead of counts\n\
+         \x20 -t, --show-tokens    Print the input with token boundaries marked\n\
+         \x20 -f, --format <fmt>   Output format: text, json, ndjson, csv, tsv\n\
+         \x20 --special <mode>     Special-token strings in the input: allow (count\n\
+         \x20                      as special tokens, default), plain (as text,\n\
+         \x20                      default with --chat or --tools unless the model\n\
+         \x20                      has a chat template), error (fail if any\n\
+         \x20                      appear)\n\
+         \x20 --allowed-special <tokens>\n\
+         \x20                      Comma-separated special tokens to count as\n\
+         \x20                      such; others are text, or fail with --special\n\
+         \x20                      error (repeatable)\n\
+         \x20 --chat-markers       Count <|im_start|>, <|im_end|> and <|im_sep|>\n\
+         \x20                      as special tokens, as the OpenAI API does\n\
+         \x20                      (openai and cl100k)\n\
+         \x20 --no-bos-eos         Don't count the BOS/EOS or [CLS]/[SEP] tokens\n\
+         \x20                      the model adds around each input\n\
+         \x20 --chat <format>      Inputs are chat request JSON (openai or\n\
+         \x20                      anthropic): count per message, with framing,\n\
+         \x20                      or through the model's chat template\n\
+         \x20 --tools <format>     Inputs are tool definitions (openai or anthropic\n\
+         \x20                      tools, or a request holding them): count each\n\
+         \x20                      tool as the provider serializes it\n\
+         \x20 --max-tokens <[model=]N>\n\
+         \x20                      Fail if the total exceeds N tokens (repeatable)\n\
+         \x20 --max-tokens-per-file <[model=]N>\n\
+         \x20                      Fail if any file exceeds N tokens (repeatable)\n\
          \x20 -V, --version        Show version\n\
          \x20 -h, --help           Show this help\n\
          \n\
+         Commands:\n\
+         \x20 decode [-m <name>] [id...]  Decode token IDs (args or stdin) to text\n\
+         \x20                             (also accepts --model-file)\n\
+         \x20 freeze [--name <name>] <input> <output>\n\
+         \x20                             Convert a tokenizer.json, .tiktoken or vocab\n\
+         \x20                             JSON file into a frozen model file\n\
+         \x20 inspect <file>              Describe a frozen model file\n\
+         \x20 dataset [--field <key>] [file.jsonl...]\n\
+         \x20                             Token statistics of a JSONL dataset, per\n\
+         \x20                             record (see dataset --help)\n\
+         \x20 split --max-tokens <N> [--overlap <N>] [-o <dir>] [file...]\n\
+         \x20                             Split files into chunks of at most N tokens\n\
+         \x20                             (NDJSON, or one file per chunk with -o)\n\
+         \n\
          Models: {}\n\
          \n\
+         Models not embedded at build time are read from the directory in\n\
+         TOKEN_COUNT_MODELS (<encoding>.tiktoken, <model>/tokenizer.json).\n\
+         \n\
          When no paths are given, reads from stdin.\n\
          Directories require -r; binary files are skipped.\n\
          \n\
+         Structured formats emit one record per file plus a total, with\n\
+         path, bytes, chars, lines (newline count) and tokens. With -a,\n\
+         tokens is a map of model to count (one column per model in\n\
+         csv/tsv); otherwise records also carry model. json and ndjson\n\
+         records include a schema version.\n\
+         \n\
+         Token limits apply to every selected model (all of them with -a)\n\
+         unless given as model=N. Files over a limit are listed on stderr\n\
+         and the exit status is 2.\n\
+         \n\
          Share mode (-s) takes one or two files (or stdin) and prints a URL\n\
          that opens the web app with the text pre-filled. Use two files to\n\
          get a side-by-side diff. Override the base URL with TOKEN_COUNT_URL.",
@@ -128,44 +345,132 @@ fn print_help() {
     );
 }
 
-fn embedded_data(name: &str) -> Option<&'static [u8]> {
-    match name {
-        "openai" => embedded::O200K,
-        "gemini" => embedded::GEMINI,
-        "deepseek" => embedded::DEEPSEEK,
-        "qwen" => embedded::QWEN,
-        "llama" => embedded::LLAMA,
-        "mistral" => embedded::MISTRAL,
-        "grok" => embedded::GROK,
-        "minimax" => embedded::MINIMAX,
-        _ => None,
-    }
+/// Load from the `--model-file` when given, otherwise the named registry
+/// model.
+fn load_model<'a>(name: &str, model_file: Option<&'a ModelFile>) -> Box<dyn Tokenizer + 'a> {
+    let result = match model_file {
+        Some(file) => file.load(),
+        None => tokencount::load_model(name),
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Open a `--model-file`; frozen files are mapped rather than read.
+fn open_model_file(path: &str) -> ModelFile {
+    ModelFile::open(path).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
 }
 
-fn load_model(name: &str) -> Tokenizer {
-    match name {
-        "claude" => Tokenizer::Claude(claude::DATrie::new()),
-        "openai" => {
-            let data = embedded_data("openai").unwrap_or_else(|| {
-                eprintln!("Error: openai model not embedded (build with TOKEN_COUNT_MODELS)");
+/// `--special error`: exit if any input contains a special-token string of
+/// any selected model.
+fn check_special_tokens(inputs: &[Input], tokenizers: &[(&str, Box<dyn Tokenizer + '_>)]) {
+    for input in inputs {
+        for (model_name, tok) in tokenizers {
+            if let Some((start, end)) = tok.find_special_token(&input.text) {
+                eprintln!(
+                    "Error: {}: special token '{}' for {} at byte {} (use --special allow or plain, or --allowed-special)",
+                    input.name.as_deref().unwrap_or("stdin"),
+                    &input.text[start..end],
+                    model_name,
+                    start
+                );
                 std::process::exit(1);
-            });
-            Tokenizer::Tiktoken(tiktoken::TiktokenTokenizer::new(data))
+            }
         }
-        model => {
-            let data = embedded_data(model).unwrap_or_else(|| {
-                eprintln!("Error: {} model not embedded (build with TOKEN_COUNT_MODELS)", model);
-                std::process::exit(1);
-            });
-            match bpe::HfTokenizer::from_frozen(data) {
-                Ok(t) => Tokenizer::Hf(t),
-                Err(e) => {
-                    eprintln!("Error loading {} model: {}", model, e);
+    }
+}
+
+/// `--chat`: parse every input as a request body and count it under each
+/// model. Exits on input that is not a payload of the chosen format.
+fn count_chats(
+    format: ChatFormat,
+    inputs: &[Input],
+    tokenizers: &[(&str, Box<dyn Tokenizer + '_>)],
+) -> Vec<Vec<ChatCount>> {
+    inputs
+        .iter()
+        .map(|input| {
+            let label = input.name.as_deref().unwrap_or("stdin");
+            let payload: serde_json::Value =
+                serde_json::from_str(&input.text).unwrap_or_else(|e| {
+                    eprintln!("Error: {}: invalid JSON: {}", label, e);
                     std::process::exit(1);
-                }
+                });
+            tokenizers
+                .iter()
+                .map(|(_, tok)| {
+                    chat::count_chat(tok.as_ref(), format, &payload).unwrap_or_else(|e| {
+                        eprintln!("Error: {}: {}", label, e);
+                        std::process::exit(1);
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// `--tools`: parse every input as tool definitions and count them under
+/// each model. Each input becomes one row per tool, labelled with the tool
+/// name, plus an "(overhead)" row, so the usual reports list them.
+fn count_tool_rows(
+    format: ChatFormat,
+    inputs: &[Input],
+    tokenizers: &[(&str, Box<dyn Tokenizer + '_>)],
+) -> (Vec<Input>, Vec<Vec<usize>>) {
+    let mut rows = Vec::new();
+    let mut results = Vec::new();
+    for input in inputs {
+        let label = input.name.as_deref().unwrap_or("stdin");
+        let payload: serde_json::Value = serde_json::from_str(&input.text).unwrap_or_else(|e| {
+            eprintln!("Error: {}: invalid JSON: {}", label, e);
+            std::process::exit(1);
+        });
+        let per_model: Vec<tools::ToolsCount> = tokenizers
+            .iter()
+            .map(|(_, tok)| {
+                tools::count_tools(tok.as_ref(), format, &payload).unwrap_or_else(|e| {
+                    eprintln!("Error: {}: {}", label, e);
+                    std::process::exit(1);
+                })
+            })
+            .collect();
+        let row_name = |name: &str| match &input.name {
+            Some(path) => format!("{}: {}", path, name),
+            None => name.to_string(),
+        };
+        // Every model parses the same payload, so the tools line up; each
+        // row still takes each model's count from that model's own list.
+        let first = &per_model[0].tools;
+        for count in &per_model[1..] {
+            if !count
+                .tools
+                .iter()
+                .map(|t| &t.name)
+                .eq(first.iter().map(|t| &t.name))
+            {
+                eprintln!("Error: {}: tool lists differ between models", label);
+                std::process::exit(1);
             }
         }
+        for (i, tool) in first.iter().enumerate() {
+            rows.push(Input {
+                name: Some(row_name(&tool.name)),
+                text: tool.text.clone(),
+            });
+            results.push(per_model.iter().map(|c| c.tools[i].tokens).collect());
+        }
+        rows.push(Input {
+            name: Some(row_name("(overhead)")),
+            text: String::new(),
+        });
+        results.push(per_model.iter().map(|c| c.overhead).collect());
     }
+    (rows, results)
 }
 
 fn is_binary(path: &Path) -> bool {
@@ -348,7 +653,13 @@ fn base64url_encode(data: &[u8]) -> String {
         .to_string()
 }
 
-fn build_share_url(text_a: &str, text_b: &str, model: &str, count_a: usize, count_b: usize) -> String {
+fn build_share_url(
+    text_a: &str,
+    text_b: &str,
+    model: &str,
+    count_a: usize,
+    count_b: usize,
+) -> String {
     let mut obj = serde_json::json!({ "a": text_a, "b": text_b });
     if model != "claude" {
         obj["m"] = serde_json::json!(model);
@@ -362,12 +673,1025 @@ fn build_share_url(text_a: &str, text_b: &str, model: &str, count_a: usize, coun
     format!("{}/?b={}", base, encoded)
 }
 
+/// Background colors for `--show-tokens`, matching the web overlay palette.
+const TOKEN_COLORS: [u8; 6] = [24, 22, 52, 54, 23, 58];
+
+/// Render `text` with token boundaries: alternating ANSI background colors
+/// when `color`, otherwise a `|` between tokens. Boundaries that fall inside
+/// a multi-byte character are widened to cover it, and text no token maps
+/// to (e.g. stripped by a normalizer) is printed unmarked.
+fn render_tokens(text: &str, spans: &[TokenSpan], color: bool) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut pos = 0;
+    let mut n = 0;
+    for span in spans {
+        let mut end = span.end;
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        if end <= pos {
+            continue;
+        }
+        let mut start = span.start;
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let start = start.max(pos);
+        out.push_str(&text[pos..start]);
+        let piece = &text[start..end];
+        if color {
+            // Reset before newlines so the background doesn't bleed to the
+            // end of the terminal line.
+            for (i, line) in piece.split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                if !line.is_empty() {
+                    let bg = TOKEN_COLORS[n % TOKEN_COLORS.len()];
+                    out.push_str(&format!("\x1b[48;5;{}m{}\x1b[0m", bg, line));
+                }
+            }
+        } else {
+            if n > 0 {
+                out.push('|');
+            }
+            out.push_str(piece);
+        }
+        n += 1;
+        pos = end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+/// Describe every count in `results` that exceeds a `--max-tokens*` limit.
+fn check_limits(
+    args: &Args,
+    models: &[&str],
+    inputs: &[Input],
+    results: &[Vec<usize>],
+) -> Vec<String> {
+    let applies =
+        |limit: &TokenLimit, model: &str| limit.model.as_deref().is_none_or(|m| m == model);
+    let mut violations = Vec::new();
+    for (input, counts) in inputs.iter().zip(results) {
+        let label = input.name.as_deref().unwrap_or("stdin");
+        for (model, &count) in models.iter().zip(counts) {
+            for limit in args
+                .max_tokens_per_file
+                .iter()
+                .filter(|l| applies(l, model))
+            {
+                if count > limit.max {
+                    violations.push(format!(
+                        "{}: {} tokens exceeds --max-tokens-per-file {} ({})",
+                        label, count, limit.max, model
+                    ));
+                }
+            }
+        }
+    }
+    for (j, model) in models.iter().enumerate() {
+        let total: usize = results.iter().map(|c| c[j]).sum();
+        for limit in args.max_tokens.iter().filter(|l| applies(l, model)) {
+            if total > limit.max {
+                violations.push(format!(
+                    "total: {} tokens exceeds --max-tokens {} ({})",
+                    total, limit.max, model
+                ));
+            }
+        }
+    }
+    violations
+}
+
+struct TextStats {
+    bytes: usize,
+    chars: usize,
+    lines: usize,
+}
+
+fn text_stats(text: &str) -> TextStats {
+    TextStats {
+        bytes: text.len(),
+        chars: text.chars().count(),
+        lines: text.bytes().filter(|&b| b == b'\n').count(),
+    }
+}
+
+/// Render counts as json/ndjson/csv/tsv. `results[i][j]` is the count of
+/// `inputs[i]` under `models[j]`. With `per_model_map` (the `-a` layout)
+/// each record carries a model → count map instead of a single model.
+/// With `chats` (`--chat`), json and ndjson file records also list their
+/// messages.
+fn format_report(
+    format: OutputFormat,
+    per_model_map: bool,
+    models: &[&str],
+    inputs: &[Input],
+    results: &[Vec<usize>],
+    chats: Option<&[Vec<ChatCount>]>,
+) -> String {
+    let mut total_tokens = vec![0usize; models.len()];
+    let mut total = TextStats {
+        bytes: 0,
+        chars: 0,
+        lines: 0,
+    };
+    let mut rows: Vec<(Option<&str>, TextStats, &[usize])> = Vec::new();
+    let tokens_value = |counts: &[usize]| {
+        if per_model_map {
+            let map: serde_json::Map<String, serde_json::Value> = models
+                .iter()
+                .zip(counts)
+                .map(|(m, c)| (m.to_string(), serde_json::json!(c)))
+                .collect();
+            serde_json::Value::Object(map)
+        } else {
+            serde_json::json!(counts[0])
+        }
+    };
+    for (input, counts) in inputs.iter().zip(results) {
+        let stats = text_stats(&input.text);
+        total.bytes += stats.bytes;
+        total.chars += stats.chars;
+        total.lines += stats.lines;
+        for (t, c) in total_tokens.iter_mut().zip(counts) {
+            *t += c;
+        }
+        rows.push((input.name.as_deref(), stats, counts));
+    }
+
+    let record = |kind: &str, path: Option<&str>, stats: &TextStats, counts: &[usize]| {
+        let mut obj = serde_json::json!({
+            "type": kind,
+            "path": path,
+            "bytes": stats.bytes,
+            "chars": stats.chars,
+            "lines": stats.lines,
+        });
+        if !per_model_map {
+            obj["model"] = serde_json::json!(models[0]);
+        }
+        obj["tokens"] = tokens_value(counts);
+        obj
+    };
+    // `[{"role", "tokens", "framing"}]` of input `i`; with `per_model_map`
+    // a model → list map, since models with a chat template split a payload
+    // into messages their own way.
+    let messages = |i: usize| {
+        let list = |count: &ChatCount| -> serde_json::Value {
+            count
+                .messages
+                .iter()
+                .map(|m| serde_json::json!({ "role": m.role, "tokens": m.total(), "framing": m.framing }))
+                .collect()
+        };
+        let per_model = &chats?[i];
+        Some(if per_model_map {
+            let map = models
+                .iter()
+                .zip(per_model)
+                .map(|(m, c)| (m.to_string(), list(c)))
+                .collect();
+            serde_json::Value::Object(map)
+        } else {
+            list(&per_model[0])
+        })
+    };
+    let file_record = |i: usize| {
+        let (path, stats, counts) = &rows[i];
+        let mut obj = record("file", *path, stats, counts);
+        if let Some(list) = messages(i) {
+            obj["messages"] = list;
+        }
+        obj
+    };
+
+    let mut out = String::new();
+    match format {
+        OutputFormat::Json => {
+            let files: Vec<serde_json::Value> = (0..rows.len()).map(file_record).collect();
+            let doc = serde_json::json!({
+                "version": FORMAT_VERSION,
+                "files": files,
+                "total": record("total", None, &total, &total_tokens),
+            });
+            out.push_str(&serde_json::to_string_pretty(&doc).unwrap());
+            out.push('\n');
+        }
+        OutputFormat::Ndjson => {
+            let lines = (0..rows.len())
+                .map(file_record)
+                .chain(std::iter::once(record(
+                    "total",
+                    None,
+                    &total,
+                    &total_tokens,
+                )));
+            for mut obj in lines {
+                obj["version"] = serde_json::json!(FORMAT_VERSION);
+                out.push_str(&obj.to_string());
+                out.push('\n');
+            }
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let sep = if format == OutputFormat::Csv {
+                ","
+            } else {
+                "\t"
+            };
+            let field = |s: &str| -> String {
+                if format == OutputFormat::Tsv {
+                    s.replace('\\', "\\\\")
+                        .replace('\t', "\\t")
+                        .replace('\n', "\\n")
+                } else if s.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", s.replace('"', "\"\""))
+                } else {
+                    s.to_string()
+                }
+            };
+            let mut header = vec!["type", "path", "bytes", "chars", "lines"];
+            if per_model_map {
+                header.extend_from_slice(models);
+            } else {
+                header.extend_from_slice(&["model", "tokens"]);
+            }
+            out.push_str(&header.join(sep));
+            out.push('\n');
+            let total_row = (None, total, &total_tokens[..]);
+            for (kind, (path, stats, counts)) in rows
+                .iter()
+                .map(|r| ("file", r))
+                .chain(std::iter::once(("total", &total_row)))
+            {
+                let mut cols = vec![
+                    kind.to_string(),
+                    field(path.unwrap_or("")),
+                    stats.bytes.to_string(),
+                    stats.chars.to_string(),
+                    stats.lines.to_string(),
+                ];
+                if per_model_map {
+                    cols.extend(counts.iter().map(|c| c.to_string()));
+                } else {
+                    cols.push(field(models[0]));
+                    cols.push(counts[0].to_string());
+                }
+                out.push_str(&cols.join(sep));
+                out.push('\n');
+            }
+        }
+        OutputFormat::Text => unreachable!("text output is written by main"),
+    }
+    out
+}
+
+/// Text output for `--chat`: each payload's messages (with the framing part
+/// of their count), reply priming, tool definitions and total under every
+/// model, then the grand total when there are several payloads.
+fn format_chat_breakdown(models: &[&str], inputs: &[Input], chats: &[Vec<ChatCount>]) -> String {
+    let labeled = inputs.len() > 1 || models.len() > 1;
+    let mut out = String::new();
+    for (input, counts) in inputs.iter().zip(chats) {
+        let label = input.name.as_deref().unwrap_or("stdin");
+        for (model_name, count) in models.iter().zip(counts) {
+            if labeled {
+                out.push_str(&format!("==> {} ({}) <==\n", label, model_name));
+            }
+            for (i, message) in count.messages.iter().enumerate() {
+                let label = format!("[{}] {} (+{} framing)", i, message.role, message.framing);
+                out.push_str(&format_line(&message.total().to_string(), &label));
+            }
+            out.push_str(&format_line(&count.overhead.to_string(), "reply priming"));
+            if count.tools > 0 {
+                out.push_str(&format_line(&count.tools.to_string(), "tool definitions"));
+            }
+            out.push_str(&format_line(&count.total().to_string(), "total"));
+        }
+    }
+    if inputs.len() > 1 && models.len() == 1 {
+        let total: usize = chats.iter().map(|c| c[0].total()).sum();
+        out.push('\n');
+        out.push_str(&format_line(&total.to_string(), "total"));
+    }
+    out
+}
+
 fn format_line(count: &str, label: &str) -> String {
     format!("{:>8} {}\n", count, label)
 }
 
+/// Walks a subcommand's arguments for its `match`: each option as a
+/// [`Opt::Flag`], whose value the arm then takes with [`Options::value`] or
+/// [`Options::number`], and everything else as an [`Opt::Arg`]. `-h` and
+/// `--help` print the usage and exit, and a missing or malformed value
+/// exits with the same error for every subcommand.
+struct Options<'a> {
+    argv: &'a [String],
+    next: usize,
+    flag: &'a str,
+    usage: &'static str,
+}
+
+enum Opt<'a> {
+    Flag(&'a str),
+    Arg(&'a str),
+}
+
+impl<'a> Options<'a> {
+    fn new(argv: &'a [String], usage: &'static str) -> Self {
+        Options {
+            argv,
+            next: 0,
+            flag: "",
+            usage,
+        }
+    }
+
+    fn next(&mut self) -> Option<Opt<'a>> {
+        let arg = self.argv.get(self.next)?.as_str();
+        self.next += 1;
+        match arg {
+            "-h" | "--help" => {
+                println!("{}", self.usage);
+                std::process::exit(0);
+            }
+            s if s.starts_with('-') => {
+                self.flag = s;
+                Some(Opt::Flag(s))
+            }
+            s => Some(Opt::Arg(s)),
+        }
+    }
+
+    /// The value of the option just returned.
+    fn value(&mut self) -> &'a str {
+        let value = self.argv.get(self.next).unwrap_or_else(|| {
+            eprintln!("Error: {} requires a value", self.flag);
+            std::process::exit(1);
+        });
+        self.next += 1;
+        value
+    }
+
+    /// The value of the option just returned, as a count.
+    fn number(&mut self) -> usize {
+        let value = self.value();
+        value.parse().unwrap_or_else(|_| {
+            eprintln!("Error: {} expects a number, got '{}'", self.flag, value);
+            std::process::exit(1);
+        })
+    }
+}
+
+fn unknown_option(flag: &str) -> ! {
+    eprintln!("Error: unknown option: {}", flag);
+    std::process::exit(1);
+}
+
+const DECODE_USAGE: &str = "Usage: tokencount decode [-m <name> | --model-file <path>] [id...]\n\
+    \n\
+    Decode token IDs to text. Reads IDs from stdin when none are given.";
+
+/// `tokencount decode [-m model] [id...]`: print the text for a token ID
+/// stream. Without IDs on the command line, every integer on stdin is read,
+/// so logged arrays like `[128000, 9906]` can be piped in directly.
+fn run_decode(argv: &[String]) {
+    let mut model = "claude".to_string();
+    let mut model_file = None;
+    let mut ids: Vec<u32> = Vec::new();
+    let mut opts = Options::new(argv, DECODE_USAGE);
+    while let Some(arg) = opts.next() {
+        match arg {
+            Opt::Flag("-m" | "--model") => model = opts.value().to_string(),
+            Opt::Flag("--model-file") => model_file = Some(opts.value().to_string()),
+            Opt::Flag(other) => unknown_option(other),
+            Opt::Arg(s) => match s.parse() {
+                Ok(id) => ids.push(id),
+                Err(_) => {
+                    eprintln!("Error: invalid token ID: {}", s);
+                    std::process::exit(1);
+                }
+            },
+        }
+    }
+
+    if ids.is_empty() {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf).unwrap_or_else(|e| {
+            eprintln!("Error reading stdin: {}", e);
+            std::process::exit(1);
+        });
+        ids = buf
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| s.parse().ok())
+            .collect();
+    }
+
+    let model_file = model_file.as_deref().map(open_model_file);
+    let tok = load_model(&model, model_file.as_ref());
+    let bytes = tok.decode_bytes(&ids);
+    let mut out = io::stdout().lock();
+    if let Err(e) = out.write_all(&bytes).and_then(|_| out.flush()) {
+        eprintln!("Error writing output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+const FREEZE_USAGE: &str = "Usage: tokencount freeze [--name <name>] <input> <output>\n\
+    \n\
+    Convert a HuggingFace tokenizer.json, a tiktoken rank file or a JSON\n\
+    array of token strings into a frozen model file. The name recorded\n\
+    in the file defaults to the input's file stem (its directory for\n\
+    <model>/tokenizer.json). A rank file's encoding is taken from that\n\
+    name (cl100k_base, p50k_base, r50k_base, o200k_harmony or an -m\n\
+    name), defaulting to o200k_base. A tokenizer.json takes the chat\n\
+    template of the tokenizer_config.json beside it, if any.";
+
+/// `tokencount freeze [--name <name>] <input> <output>`: convert a model
+/// source file into a frozen file that `--model-file` loads directly.
+fn run_freeze(argv: &[String]) {
+    let mut name = None;
+    let mut paths = Vec::new();
+    let mut opts = Options::new(argv, FREEZE_USAGE);
+    while let Some(arg) = opts.next() {
+        match arg {
+            Opt::Flag("--name") => name = Some(opts.value().to_string()),
+            Opt::Flag(other) => unknown_option(other),
+            Opt::Arg(path) => paths.push(path.to_string()),
+        }
+    }
+    let [input, output] = paths.as_slice() else {
+        eprintln!("Error: freeze expects an input and an output path");
+        std::process::exit(1);
+    };
+
+    let name = name.unwrap_or_else(|| default_model_name(Path::new(input)));
+    let blob = tokencount::freeze_model_file(&name, Path::new(input)).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    fs::write(output, &blob).unwrap_or_else(|e| {
+        eprintln!("Error writing {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let kind = tokencount::format::Header::parse(&blob).map_or(0, |h| h.kind);
+    println!(
+        "{}: {} model '{}', {} bytes",
+        output,
+        tokencount::format::kind_name(kind),
+        name,
+        blob.len()
+    );
+}
+
+/// Name recorded by `freeze` when `--name` is not given: the file stem, or
+/// the directory name for the `<model>/tokenizer.json` layout.
+fn default_model_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if stem == "tokenizer" {
+        if let Some(dir) = path.parent().and_then(Path::file_name) {
+            return dir.to_string_lossy().into_owned();
+        }
+    }
+    stem
+}
+
+/// `tokencount inspect <file>`: print a frozen model file's header, section
+/// table and contents summary.
+fn run_inspect(argv: &[String]) {
+    let mut paths = Vec::new();
+    let mut opts = Options::new(
+        argv,
+        "Usage: tokencount inspect <file>\n\nDescribe a frozen model file.",
+    );
+    while let Some(arg) = opts.next() {
+        match arg {
+            Opt::Flag(other) => unknown_option(other),
+            Opt::Arg(path) => paths.push(path.to_string()),
+        }
+    }
+    let [path] = paths.as_slice() else {
+        eprintln!("Error: inspect expects one frozen model file");
+        std::process::exit(1);
+    };
+    let data = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Error reading {}: {}", path, e);
+        std::process::exit(1);
+    });
+    match tokencount::describe_frozen(&data) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("Error: {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// One `dataset` record's count under one model, for the worst-offender list.
+struct RecordCount {
+    file: usize,
+    line: usize,
+    tokens: usize,
+}
+
+const DATASET_USAGE: &str = "Usage: tokencount dataset [options] [file.jsonl...]\n\
+    \n\
+    Token statistics of a JSONL dataset: total, mean, median, p95, p99\n\
+    and max tokens per record, for each selected model. Reads stdin\n\
+    when no files are given.\n\
+    \n\
+    Options:\n\
+    \x20 -m, --model <name>   Tokenizer model (default: claude)\n\
+    \x20 --model-file <path>  Load a tokenizer.json, .tiktoken or frozen file\n\
+    \x20 -a, --all            Report every model\n\
+    \x20 --field <key>        Count this field of each record, a top-level\n\
+    \x20                      key or a JSON pointer (/messages/0/content);\n\
+    \x20                      default: the whole record\n\
+    \x20 --chat <format>      Format of message lists (openai, default, or\n\
+    \x20                      anthropic)\n\
+    \x20 --no-bos-eos         Don't count the BOS/EOS tokens of text records\n\
+    \x20 --max-tokens <[model=]N>\n\
+    \x20                      Context limit: count the records over N and\n\
+    \x20                      list only those as offenders (repeatable)\n\
+    \x20 --top <N>            Offenders to list per model (default: 10)\n\
+    \x20 -f, --format <fmt>   Output format: text or json\n\
+    \n\
+    A string is counted as text. Anything else is a chat payload (a\n\
+    message list or a request body holding one) and is counted as\n\
+    --chat counts it, through the model's chat template if it has one.\n\
+    Special-token strings count as plain text unless the model has a\n\
+    chat template. Percentiles are nearest-rank. Offenders are the\n\
+    largest records, listed as file:line.";
+
+/// `tokencount dataset [options] [file.jsonl...]`: token statistics of a
+/// JSONL dataset, one count per record and model. Records are streamed; only
+/// their counts are kept.
+fn run_dataset(argv: &[String]) {
+    let mut model = "claude".to_string();
+    let mut model_file = None;
+    let mut all = false;
+    let mut field = None;
+    let mut format = ChatFormat::OpenAi;
+    let mut add_special_tokens = true;
+    let mut limits = Vec::new();
+    let mut top = 10;
+    let mut json = false;
+    let mut paths = Vec::new();
+    let mut opts = Options::new(argv, DATASET_USAGE);
+    while let Some(arg) = opts.next() {
+        match arg {
+            Opt::Flag("-m" | "--model") => model = opts.value().to_string(),
+            Opt::Flag("--model-file") => model_file = Some(opts.value().to_string()),
+            Opt::Flag("-a" | "--all") => all = true,
+            Opt::Flag("--field") => field = Some(opts.value().to_string()),
+            Opt::Flag("--chat") => {
+                format = match opts.value() {
+                    "openai" => ChatFormat::OpenAi,
+                    "anthropic" => ChatFormat::Anthropic,
+                    other => {
+                        eprintln!(
+                            "Error: unknown --chat format '{}'\nAvailable: openai, anthropic",
+                            other
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            Opt::Flag("--no-bos-eos") => add_special_tokens = false,
+            Opt::Flag(flag @ "--max-tokens") => limits.push(parse_limit(flag, opts.value())),
+            Opt::Flag("--top") => top = opts.number(),
+            Opt::Flag("-f" | "--format") => {
+                json = match opts.value() {
+                    "text" => false,
+                    "json" => true,
+                    other => {
+                        eprintln!("Error: unknown format '{}'\nAvailable: text, json", other);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            Opt::Flag(other) => unknown_option(other),
+            Opt::Arg(path) => paths.push(path.to_string()),
+        }
+    }
+    if model_file.is_some() && all {
+        eprintln!("Error: --model-file cannot be combined with -a");
+        std::process::exit(1);
+    }
+    let model_names: Vec<&str> = if all {
+        MODEL_NAMES.to_vec()
+    } else if let Some(path) = &model_file {
+        vec![path.as_str()]
+    } else {
+        vec![model.as_str()]
+    };
+    for m in &model_names {
+        if model_file.is_none() && !MODEL_NAMES.contains(m) {
+            eprintln!("Error: {}", Error::UnknownModel(m.to_string()));
+            std::process::exit(1);
+        }
+    }
+    for limit in &limits {
+        if let Some(m) = &limit.model {
+            if !model_names.contains(&m.as_str()) {
+                eprintln!(
+                    "Error: token limit for '{}', which is not a selected model",
+                    m
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let model_file = model_file.as_deref().map(open_model_file);
+    let mut tokenizers: Vec<(&str, Box<dyn Tokenizer>)> = Vec::new();
+    for &m in &model_names {
+        let mut t = load_model(m, model_file.as_ref());
+        // Records are data, not prompts: special-token strings in them are
+        // text, as --chat counts them.
+        if t.chat_template().is_none() {
+            t.set_special_tokens(SpecialTokens::Plain);
+        }
+        tokenizers.push((m, t));
+    }
+
+    let labels: Vec<String> = if paths.is_empty() {
+        vec!["stdin".to_string()]
+    } else {
+        paths.clone()
+    };
+    let mut counts: Vec<Vec<RecordCount>> = tokenizers.iter().map(|_| Vec::new()).collect();
+    for (file, label) in labels.iter().enumerate() {
+        let reader: Box<dyn BufRead> = if paths.is_empty() {
+            Box::new(io::stdin().lock())
+        } else {
+            let f = fs::File::open(label).unwrap_or_else(|e| {
+                eprintln!("Error reading {}: {}", label, e);
+                std::process::exit(1);
+            });
+            Box::new(io::BufReader::new(f))
+        };
+        for (n, line) in reader.lines().enumerate() {
+            let line = line.unwrap_or_else(|e| {
+                eprintln!("Error reading {}: {}", label, e);
+                std::process::exit(1);
+            });
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fail = |reason: &dyn std::fmt::Display| -> ! {
+                eprintln!("Error: {}:{}: {}", label, n + 1, reason);
+                std::process::exit(1);
+            };
+            let record: serde_json::Value = serde_json::from_str(&line)
+                .unwrap_or_else(|e| fail(&format_args!("invalid JSON: {}", e)));
+            let value = match field.as_deref() {
+                None => Some(&record),
+                Some(pointer) if pointer.starts_with('/') => record.pointer(pointer),
+                Some(key) => record.get(key),
+            };
+            let Some(value) = value else {
+                fail(&format_args!(
+                    "no field '{}'",
+                    field.as_deref().unwrap_or_default()
+                ))
+            };
+            for ((_, tok), counts) in tokenizers.iter_mut().zip(&mut counts) {
+                let tokens = match value {
+                    serde_json::Value::String(text) => {
+                        tok.set_add_special_tokens(add_special_tokens);
+                        tok.count_tokens(text)
+                    }
+                    payload => {
+                        tok.set_add_special_tokens(false);
+                        chat::count_chat(tok.as_ref(), format, payload)
+                            .unwrap_or_else(|e| fail(&e))
+                            .total()
+                    }
+                };
+                counts.push(RecordCount {
+                    file,
+                    line: n + 1,
+                    tokens,
+                });
+            }
+        }
+    }
+
+    let applies =
+        |limit: &TokenLimit, model: &str| limit.model.as_deref().is_none_or(|m| m == model);
+    let summaries: Vec<DatasetSummary> = model_names
+        .iter()
+        .zip(counts)
+        .map(|(&model, counts)| {
+            let limit = limits
+                .iter()
+                .filter(|l| applies(l, model))
+                .map(|l| l.max)
+                .min();
+            summarize_dataset(model, counts, limit, top, &labels)
+        })
+        .collect();
+    if json {
+        let doc = serde_json::json!({
+            "version": FORMAT_VERSION,
+            "models": summaries.iter().map(DatasetSummary::to_json).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+    } else {
+        for (i, summary) in summaries.iter().enumerate() {
+            if summaries.len() > 1 {
+                if i > 0 {
+                    println!();
+                }
+                println!("==> {} <==", summary.model);
+            }
+            print!("{}", summary.format_text());
+        }
+    }
+}
+
+/// `dataset` statistics of one model.
+struct DatasetSummary<'a> {
+    model: &'a str,
+    records: usize,
+    total: usize,
+    mean: f64,
+    median: usize,
+    p95: usize,
+    p99: usize,
+    max: usize,
+    /// `--max-tokens` for this model, with the number of records over it.
+    limit: Option<(usize, usize)>,
+    /// The largest records (over the limit, when there is one), as
+    /// `file:line` and count.
+    offenders: Vec<(String, usize)>,
+}
+
+fn summarize_dataset<'a>(
+    model: &'a str,
+    mut counts: Vec<RecordCount>,
+    limit: Option<usize>,
+    top: usize,
+    labels: &[String],
+) -> DatasetSummary<'a> {
+    // Largest first; ties in file order.
+    counts.sort_by(|a, b| {
+        b.tokens
+            .cmp(&a.tokens)
+            .then(a.file.cmp(&b.file))
+            .then(a.line.cmp(&b.line))
+    });
+    let records = counts.len();
+    let total: usize = counts.iter().map(|c| c.tokens).sum();
+    // Nearest rank: the smallest count with at least `p` of the records at
+    // or below it.
+    let percentile = |p: f64| {
+        let rank = ((p * records as f64).ceil() as usize).max(1);
+        counts
+            .get(records.wrapping_sub(rank))
+            .map_or(0, |c| c.tokens)
+    };
+    let over = limit.map(|max| (max, counts.iter().take_while(|c| c.tokens > max).count()));
+    let listed = over.map_or(records, |(_, n)| n).min(top);
+    DatasetSummary {
+        model,
+        records,
+        total,
+        mean: if records == 0 {
+            0.0
+        } else {
+            total as f64 / records as f64
+        },
+        median: percentile(0.5),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+        max: counts.first().map_or(0, |c| c.tokens),
+        limit: over,
+        offenders: counts[..listed]
+            .iter()
+            .map(|c| (format!("{}:{}", labels[c.file], c.line), c.tokens))
+            .collect(),
+    }
+}
+
+impl DatasetSummary<'_> {
+    fn format_text(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format_line(&self.records.to_string(), "records"));
+        out.push_str(&format_line(&self.total.to_string(), "total"));
+        out.push_str(&format_line(&format!("{:.1}", self.mean), "mean"));
+        out.push_str(&format_line(&self.median.to_string(), "median"));
+        out.push_str(&format_line(&self.p95.to_string(), "p95"));
+        out.push_str(&format_line(&self.p99.to_string(), "p99"));
+        out.push_str(&format_line(&self.max.to_string(), "max"));
+        if let Some((max, over)) = self.limit {
+            out.push_str(&format_line(
+                &over.to_string(),
+                &format!("over {} tokens", max),
+            ));
+        }
+        if !self.offenders.is_empty() {
+            out.push_str(if self.limit.is_some() {
+                "\nOver the limit:\n"
+            } else {
+                "\nLargest:\n"
+            });
+            for (location, tokens) in &self.offenders {
+                out.push_str(&format_line(&tokens.to_string(), location));
+            }
+        }
+        out
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let offenders: Vec<serde_json::Value> = self
+            .offenders
+            .iter()
+            .map(|(location, tokens)| serde_json::json!({ "record": location, "tokens": tokens }))
+            .collect();
+        let mut obj = serde_json::json!({
+            "model": self.model,
+            "records": self.records,
+            "total": self.total,
+            "mean": self.mean,
+            "median": self.median,
+            "p95": self.p95,
+            "p99": self.p99,
+            "max": self.max,
+        });
+        if let Some((max, over)) = self.limit {
+            obj["max_tokens"] = serde_json::json!(max);
+            obj["over"] = serde_json::json!(over);
+        }
+        obj["offenders"] = serde_json::Value::Array(offenders);
+        obj
+    }
+}
+
+const SPLIT_USAGE: &str = "Usage: tokencount split --max-tokens <N> [options] [file...]\n\
+    \n\
+    Split files (or stdin) into chunks of at most N tokens, cut at\n\
+    paragraph, line or sentence boundaries where possible. Each chunk's\n\
+    count is exact for the model. Prints one NDJSON record per chunk\n\
+    (path, chunk, start, end, tokens, text; start and end are byte\n\
+    offsets), or writes the chunks as files.\n\
+    \n\
+    Options:\n\
+    \x20 -m, --model <name>      Tokenizer model (default: claude)\n\
+    \x20 --model-file <path>     Load a tokenizer.json, .tiktoken or frozen file\n\
+    \x20 --max-tokens <N>        Chunk size limit in tokens\n\
+    \x20 --overlap <N>           Start each chunk about N tokens before the\n\
+    \x20                         end of the previous one (default: 0)\n\
+    \x20 -o, --output-dir <dir>  Write <name>-0001.<ext>, ... there and list\n\
+    \x20                         them with their counts instead\n\
+    \x20 --no-bos-eos            Don't count the BOS/EOS tokens the model adds\n\
+    \x20                         around each chunk";
+
+/// `tokencount split --max-tokens N [options] [file...]`: cut each input
+/// into chunks of at most N tokens under one model, written as NDJSON
+/// records or as one file per chunk.
+fn run_split(argv: &[String]) {
+    let mut model = "claude".to_string();
+    let mut model_file = None;
+    let mut max_tokens = None;
+    let mut overlap = 0;
+    let mut output_dir = None;
+    let mut add_special_tokens = true;
+    let mut paths = Vec::new();
+    let mut opts = Options::new(argv, SPLIT_USAGE);
+    while let Some(arg) = opts.next() {
+        match arg {
+            Opt::Flag("-m" | "--model") => model = opts.value().to_string(),
+            Opt::Flag("--model-file") => model_file = Some(opts.value().to_string()),
+            Opt::Flag("--max-tokens") => max_tokens = Some(opts.number()),
+            Opt::Flag("--overlap") => overlap = opts.number(),
+            Opt::Flag("-o" | "--output-dir") => output_dir = Some(PathBuf::from(opts.value())),
+            Opt::Flag("--no-bos-eos") => add_special_tokens = false,
+            Opt::Flag(other) => unknown_option(other),
+            Opt::Arg(path) => paths.push(path.to_string()),
+        }
+    }
+    let Some(max_tokens) = max_tokens.filter(|&n| n > 0) else {
+        eprintln!("Error: split requires --max-tokens with a positive N");
+        std::process::exit(1);
+    };
+    if overlap >= max_tokens {
+        eprintln!("Error: --overlap must be less than --max-tokens");
+        std::process::exit(1);
+    }
+    if model_file.is_none() && !MODEL_NAMES.contains(&model.as_str()) {
+        eprintln!("Error: {}", Error::UnknownModel(model));
+        std::process::exit(1);
+    }
+
+    // A model file is labelled by its path, as in the count records.
+    let model_label = model_file.clone().unwrap_or_else(|| model.clone());
+    let model_file = model_file.as_deref().map(open_model_file);
+    let mut tok = load_model(&model, model_file.as_ref());
+    tok.set_add_special_tokens(add_special_tokens);
+
+    let inputs: Vec<Input> = if paths.is_empty() {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf).unwrap_or_else(|e| {
+            eprintln!("Error reading stdin: {}", e);
+            std::process::exit(1);
+        });
+        vec![Input {
+            name: None,
+            text: buf,
+        }]
+    } else {
+        paths
+            .iter()
+            .map(|p| {
+                let text = fs::read_to_string(p).unwrap_or_else(|e| {
+                    eprintln!("Error reading {}: {}", p, e);
+                    std::process::exit(1);
+                });
+                Input {
+                    name: Some(p.clone()),
+                    text,
+                }
+            })
+            .collect()
+    };
+    if let Some(dir) = &output_dir {
+        fs::create_dir_all(dir).unwrap_or_else(|e| {
+            eprintln!("Error creating {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+    }
+
+    let mut out = io::stdout().lock();
+    for input in &inputs {
+        let chunks = tokencount::split::split_text(tok.as_ref(), &input.text, max_tokens, overlap);
+        for (n, chunk) in chunks.iter().enumerate() {
+            let text = &input.text[chunk.start..chunk.end];
+            let line = match &output_dir {
+                Some(dir) => {
+                    let path = dir.join(chunk_file_name(input.name.as_deref(), n + 1));
+                    fs::write(&path, text).unwrap_or_else(|e| {
+                        eprintln!("Error writing {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    });
+                    format_line(&chunk.tokens.to_string(), &path.to_string_lossy())
+                }
+                None => {
+                    let record = serde_json::json!({
+                        "version": FORMAT_VERSION,
+                        "type": "chunk",
+                        "path": input.name,
+                        "chunk": n + 1,
+                        "start": chunk.start,
+                        "end": chunk.end,
+                        "model": model_label,
+                        "tokens": chunk.tokens,
+                        "text": text,
+                    });
+                    format!("{}\n", record)
+                }
+            };
+            if let Err(e) = out.write_all(line.as_bytes()) {
+                eprintln!("Error writing output: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// `split -o` file name of chunk `n` of `path`: `<stem>-<n>.<ext>`, or
+/// `stdin-<n>.txt`.
+fn chunk_file_name(path: Option<&str>, n: usize) -> String {
+    let path = Path::new(path.unwrap_or("stdin.txt"));
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(ext) => format!("{}-{:04}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{:04}", stem, n),
+    }
+}
+
 fn main() {
-    let args = parse_args();
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    match argv.first().map(String::as_str) {
+        Some("decode") => return run_decode(&argv[1..]),
+        Some("freeze") => return run_freeze(&argv[1..]),
+        Some("inspect") => return run_inspect(&argv[1..]),
+        Some("dataset") => return run_dataset(&argv[1..]),
+        Some("split") => return run_split(&argv[1..]),
+        _ => {}
+    }
+    let args = parse_args(&argv);
 
     if args.version {
         println!("tokencount {}", VERSION);
@@ -378,26 +1702,43 @@ fn main() {
         return;
     }
 
+    if args.model_file.is_some() && (args.all || args.share) {
+        eprintln!("Error: --model-file cannot be combined with -a or -s");
+        std::process::exit(1);
+    }
+    if args.chat.is_some() && (args.share || args.show_tokens) {
+        eprintln!("Error: --chat cannot be combined with -s or -t");
+        std::process::exit(1);
+    }
+    if args.tools.is_some() && (args.chat.is_some() || args.share || args.show_tokens) {
+        eprintln!("Error: --tools cannot be combined with --chat, -s or -t");
+        std::process::exit(1);
+    }
+    // A model file is labelled by its path wherever a model name appears.
     let model_names: Vec<&str> = if args.all {
         MODEL_NAMES.to_vec()
+    } else if let Some(path) = &args.model_file {
+        vec![path.as_str()]
     } else {
         vec![args.model.as_str()]
     };
 
     for m in &model_names {
-        if !MODEL_NAMES.contains(m) {
-            eprintln!(
-                "Error: unknown model '{}'\nAvailable: {}",
-                m,
-                MODEL_NAMES.join(", ")
-            );
+        if args.model_file.is_none() && !MODEL_NAMES.contains(m) {
+            eprintln!("Error: {}", Error::UnknownModel(m.to_string()));
             std::process::exit(1);
         }
     }
-
-    struct Input {
-        name: Option<String>,
-        text: String,
+    for limit in args.max_tokens.iter().chain(&args.max_tokens_per_file) {
+        if let Some(m) = &limit.model {
+            if !model_names.contains(&m.as_str()) {
+                eprintln!(
+                    "Error: token limit for '{}', which is not a selected model",
+                    m
+                );
+                std::process::exit(1);
+            }
+        }
     }
 
     let inputs: Vec<Input> = if args.paths.is_empty() {
@@ -427,11 +1768,55 @@ fn main() {
             .collect()
     };
 
-    let mut tokenizers: Vec<(&str, Tokenizer)> = Vec::new();
+    let model_file = args.model_file.as_deref().map(open_model_file);
+    let mut tokenizers: Vec<(&str, Box<dyn Tokenizer>)> = Vec::new();
     for &m in &model_names {
-        let t = load_model(m);
+        let mut t = load_model(m, model_file.as_ref());
+        // The chat APIs encode message text with special tokens as plain
+        // text; a chat template's prompt relies on them being special.
+        let plain = match args.special {
+            Some(policy) => policy == SpecialPolicy::Plain,
+            None => (args.chat.is_some() || args.tools.is_some()) && t.chat_template().is_none(),
+        };
+        // Like tiktoken, `--special error` rejects every special token that
+        // is not explicitly allowed.
+        if let Some(allowed) = &args.allowed_special {
+            t.set_special_tokens(SpecialTokens::AllowOnly(allowed.clone()));
+        } else if args.special == Some(SpecialPolicy::Error) {
+            t.set_special_tokens(SpecialTokens::AllowOnly(Vec::new()));
+        } else if plain {
+            t.set_special_tokens(SpecialTokens::Plain);
+        }
+        t.set_chat_markers(args.chat_markers);
+        // A payload is counted piece by piece, which must not each get a BOS.
+        t.set_add_special_tokens(
+            args.add_special_tokens && args.chat.is_none() && args.tools.is_none(),
+        );
         tokenizers.push((m, t));
     }
+    if args.special == Some(SpecialPolicy::Error) {
+        check_special_tokens(&inputs, &tokenizers);
+    }
+
+    if args.show_tokens {
+        let color = io::stdout().is_terminal();
+        let labeled = inputs.len() > 1 || tokenizers.len() > 1;
+        for input in &inputs {
+            let label = input.name.as_deref().unwrap_or("stdin");
+            for (model_name, tok) in &tokenizers {
+                if labeled {
+                    println!("==> {} ({}) <==", label, model_name);
+                }
+                let spans = tok.encode_with_offsets(&input.text);
+                let rendered = render_tokens(&input.text, &spans, color);
+                print!("{}", rendered);
+                if !rendered.ends_with('\n') {
+                    println!();
+                }
+            }
+        }
+        return;
+    }
 
     if args.share {
         if inputs.len() > 2 {
@@ -444,10 +1829,7 @@ fn main() {
             .first()
             .and_then(|i| i.name.as_deref())
             .unwrap_or("A");
-        let label_b = inputs
-            .get(1)
-            .and_then(|i| i.name.as_deref())
-            .unwrap_or("B");
+        let label_b = inputs.get(1).and_then(|i| i.name.as_deref()).unwrap_or("B");
 
         let model_name = &args.model;
         let tok = &tokenizers[0].1;
@@ -464,10 +1846,7 @@ fn main() {
         eprint!("{}", format_line(&count_a.to_string(), label_a));
         if inputs.len() > 1 {
             eprint!("{}", format_line(&count_b.to_string(), label_b));
-            eprint!(
-                "{}",
-                format_line(&format!("{}{}", sign, delta), "delta")
-            );
+            eprint!("{}", format_line(&format!("{}{}", sign, delta), "delta"));
         }
         eprintln!();
 
@@ -476,22 +1855,62 @@ fn main() {
         return;
     }
 
+    let chats = args
+        .chat
+        .map(|format| count_chats(format, &inputs, &tokenizers));
+    // With --tools, each tool definition is reported as an input of its own.
+    let (inputs, tool_results) = match args.tools {
+        Some(format) => {
+            let (rows, results) = count_tool_rows(format, &inputs, &tokenizers);
+            (rows, Some(results))
+        }
+        None => (inputs, None),
+    };
+
     let use_parallel = inputs.len() > 1;
 
-    if args.all {
-        let count_all = |input: &Input| -> Vec<usize> {
-            tokenizers.iter().map(|(_, tok)| tok.count_tokens(&input.text)).collect()
-        };
-        let results: Vec<Vec<usize>> = if use_parallel {
-            std::thread::scope(|s| {
-                let handles: Vec<_> = inputs.iter().map(|input| {
-                    s.spawn(|| count_all(input))
-                }).collect();
-                handles.into_iter().map(|h| h.join().unwrap()).collect()
-            })
-        } else {
-            inputs.iter().map(count_all).collect()
-        };
+    let count_all = |input: &Input| -> Vec<usize> {
+        tokenizers
+            .iter()
+            .map(|(_, tok)| tok.count_tokens(&input.text))
+            .collect()
+    };
+    let results: Vec<Vec<usize>> = if let Some(results) = tool_results {
+        results
+    } else if let Some(chats) = &chats {
+        chats
+            .iter()
+            .map(|c| c.iter().map(ChatCount::total).collect())
+            .collect()
+    } else if use_parallel {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = inputs
+                .iter()
+                .map(|input| s.spawn(|| count_all(input)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    } else {
+        inputs.iter().map(count_all).collect()
+    };
+
+    let violations = check_limits(&args, &model_names, &inputs, &results);
+
+    if args.format != OutputFormat::Text {
+        print!(
+            "{}",
+            format_report(
+                args.format,
+                args.all,
+                &model_names,
+                &inputs,
+                &results,
+                chats.as_deref()
+            )
+        );
+    } else if let Some(chats) = &chats {
+        print!("{}", format_chat_breakdown(&model_names, &inputs, chats));
+    } else if args.all {
         for (input, counts) in inputs.iter().zip(results.iter()) {
             let label = input.name.as_deref().unwrap_or("stdin");
             for ((model_name, _), count) in tokenizers.iter().zip(counts.iter()) {
@@ -502,18 +1921,7 @@ fn main() {
             }
         }
     } else {
-        let tok = &tokenizers[0].1;
-        let count_one = |input: &Input| tok.count_tokens(&input.text);
-        let counts: Vec<usize> = if use_parallel {
-            std::thread::scope(|s| {
-                let handles: Vec<_> = inputs.iter().map(|input| {
-                    s.spawn(|| count_one(input))
-                }).collect();
-                handles.into_iter().map(|h| h.join().unwrap()).collect()
-            })
-        } else {
-            inputs.iter().map(count_one).collect()
-        };
+        let counts: Vec<usize> = results.iter().map(|c| c[0]).collect();
         let total: usize = counts.iter().sum();
         if inputs.len() > 1 {
             for (input, count) in inputs.iter().zip(counts.iter()) {
@@ -526,11 +1934,15 @@ fn main() {
         } else if inputs.len() == 1 {
             print!(
                 "{}",
-                format_line(
-                    &total.to_string(),
-                    inputs[0].name.as_deref().unwrap_or("")
-                )
+                format_line(&total.to_string(), inputs[0].name.as_deref().unwrap_or(""))
             );
         }
     }
+
+    if !violations.is_empty() {
+        for v in &violations {
+            eprintln!("Error: {}", v);
+        }
+        std::process::exit(EXIT_OVER_BUDGET);
+    }
 }
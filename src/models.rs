@@ -0,0 +1,267 @@
+//! Model registry: maps the `-m` names to their backend and data, either
+//! embedded at build time or converted from source files at runtime.
+
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::error::Error;
+use crate::format::{self, Header};
+use crate::{bpe, claude, convert, tiktoken, unigram, wordpiece, Tokenizer};
+
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_models.rs"));
+}
+
+/// Every model name accepted by [`load_model`], in display order.
+pub const MODEL_NAMES: &[&str] = &[
+    "claude",
+    "openai",
+    "cl100k",
+    "p50k",
+    "r50k",
+    "o200k-harmony",
+    "gemini",
+    "deepseek",
+    "qwen",
+    "llama",
+    "mistral",
+    "grok",
+    "minimax",
+];
+
+/// Frozen model blob compiled into the binary, if `TOKEN_COUNT_MODELS`
+/// provided it at build time. Claude is always available and returns `None`.
+pub fn embedded_data(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "openai" => embedded::O200K_BASE,
+        "cl100k" => embedded::CL100K_BASE,
+        "p50k" => embedded::P50K_BASE,
+        "r50k" => embedded::R50K_BASE,
+        "o200k-harmony" => embedded::O200K_HARMONY,
+        "gemini" => embedded::GEMINI,
+        "deepseek" => embedded::DEEPSEEK,
+        "qwen" => embedded::QWEN,
+        "llama" => embedded::LLAMA,
+        "mistral" => embedded::MISTRAL,
+        "grok" => embedded::GROK,
+        "minimax" => embedded::MINIMAX,
+        _ => None,
+    }
+}
+
+/// Load a tokenizer by registry name.
+///
+/// Uses the embedded blob when there is one; otherwise converts the model's
+/// source file from the directory named by the `TOKEN_COUNT_MODELS`
+/// environment variable at runtime, laid out as for the build.
+pub fn load_model(name: &str) -> Result<Box<dyn Tokenizer>, Error> {
+    if !MODEL_NAMES.contains(&name) {
+        return Err(Error::UnknownModel(name.to_string()));
+    }
+    match (name, embedded_data(name)) {
+        ("claude", _) => Ok(Box::new(claude::DATrie::new())),
+        (model, Some(data)) => from_frozen(model, data),
+        (model, None) => {
+            let path = env::var_os("TOKEN_COUNT_MODELS")
+                .map(|dir| convert::model_source_path(Path::new(&dir), model))
+                .filter(|path| path.is_file())
+                .ok_or_else(|| Error::NotEmbedded(model.to_string()))?;
+            from_frozen(model, freeze_model_file(model, &path)?)
+        }
+    }
+}
+
+/// Load a tokenizer from a frozen model file or from any source accepted by
+/// [`freeze_model`], converted in memory. Errors name the model by its path.
+///
+/// The whole file is read onto the heap; use [`ModelFile`] to map frozen
+/// files instead.
+pub fn load_model_file(path: impl AsRef<Path>) -> Result<Box<dyn Tokenizer>, Error> {
+    let path = path.as_ref();
+    let model = path.display().to_string();
+    let data = read_file(path)?;
+    if format::is_frozen(&data) {
+        return from_frozen(&model, data);
+    }
+    let blob = freeze_model(&model, &data)?;
+    from_frozen(&model, with_chat_template(&model, path, blob)?)
+}
+
+/// Convert a model source file into a frozen blob named `name`. The kind is
+/// detected from the content: a JSON object is a Hugging Face
+/// `tokenizer.json` (BPE, Unigram or WordPiece model), a JSON array is a
+/// plain vocabulary of token strings (loaded as a longest-match trie, like
+/// Claude's), and anything else is a `.tiktoken` rank file, for the
+/// encoding named by `name` (a registry model, or a file named like
+/// `cl100k_base.tiktoken`; o200k_base otherwise).
+pub fn freeze_model(name: &str, source: &[u8]) -> Result<Vec<u8>, Error> {
+    let text = std::str::from_utf8(source).map_err(|e| invalid(name)(e.to_string()))?;
+    let blob = match text.trim_start().as_bytes().first() {
+        Some(b'{') => convert::build_hf_frozen(name, text),
+        Some(b'[') => convert::build_trie_frozen(name, text),
+        _ => convert::build_tiktoken_frozen(name, convert::tiktoken_encoding(name), text),
+    };
+    blob.map_err(invalid(name))
+}
+
+/// Convert a model source file as by [`freeze_model`], adding the chat
+/// template from the `tokenizer_config.json` beside a `tokenizer.json`, if
+/// there is one.
+pub fn freeze_model_file(name: &str, path: &Path) -> Result<Vec<u8>, Error> {
+    let blob = freeze_model(name, &read_file(path)?)?;
+    with_chat_template(name, path, blob)
+}
+
+/// Add the chat template of the config beside `source` to an HF blob.
+fn with_chat_template(name: &str, source: &Path, blob: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let kind = Header::parse(&blob).map_err(invalid(name))?.kind;
+    let config = convert::tokenizer_config_path(source);
+    let is_hf = matches!(
+        kind,
+        format::KIND_HF_BPE | format::KIND_HF_UNIGRAM | format::KIND_HF_WORDPIECE
+    );
+    if !is_hf || !config.is_file() {
+        return Ok(blob);
+    }
+    let text = String::from_utf8(read_file(&config)?).map_err(|e| invalid(name)(e.to_string()))?;
+    convert::add_chat_template(&blob, &text)
+        .map_err(|e| invalid(name)(format!("{}: {}", config.display(), e)))
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn invalid(model: &str) -> impl FnOnce(String) -> Error + '_ {
+    move |reason| Error::InvalidModel {
+        model: model.to_string(),
+        reason,
+    }
+}
+
+/// A model file opened for loading. Frozen files are memory-mapped, so
+/// tokenizers loaded from them borrow the mapping rather than copying it to
+/// the heap and start in constant time; source files are converted in
+/// memory as by [`load_model_file`].
+///
+/// The file must not be modified or truncated while it is open.
+pub struct ModelFile {
+    name: String,
+    data: ModelData,
+}
+
+enum ModelData {
+    Mapped(Mmap),
+    Converted(Vec<u8>),
+}
+
+impl ModelFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let io_error = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = fs::File::open(path).map_err(io_error)?;
+        // SAFETY: the mapping is read-only, and `ModelFile` documents that
+        // the file must not change while it is open.
+        let map = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+        let data = if format::is_frozen(&map) {
+            ModelData::Mapped(map)
+        } else {
+            ModelData::Converted(with_chat_template(&name, path, freeze_model(&name, &map)?)?)
+        };
+        Ok(ModelFile { name, data })
+    }
+
+    /// The frozen blob: the mapped file, or the converted source.
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            ModelData::Mapped(map) => map,
+            ModelData::Converted(blob) => blob,
+        }
+    }
+
+    /// Load a tokenizer that borrows this file's data.
+    pub fn load(&self) -> Result<Box<dyn Tokenizer + '_>, Error> {
+        from_frozen(&self.name, self.data())
+    }
+}
+
+/// Load a frozen blob of any kind, dispatching on its header. The tokenizer
+/// borrows `data` when given a slice.
+fn from_frozen<'a>(
+    model: &str,
+    data: impl Into<Cow<'a, [u8]>>,
+) -> Result<Box<dyn Tokenizer + 'a>, Error> {
+    let data = data.into();
+    let kind = Header::parse(&data).map_err(invalid(model))?.kind;
+    let tok: Box<dyn Tokenizer + 'a> = match kind {
+        format::KIND_CLAUDE => Box::new(claude::DATrie::from_frozen(data).map_err(invalid(model))?),
+        format::KIND_TIKTOKEN => {
+            Box::new(tiktoken::TiktokenTokenizer::from_frozen(data).map_err(invalid(model))?)
+        }
+        format::KIND_HF_UNIGRAM => {
+            Box::new(unigram::UnigramTokenizer::from_frozen(data).map_err(invalid(model))?)
+        }
+        format::KIND_HF_WORDPIECE => {
+            Box::new(wordpiece::WordPieceTokenizer::from_frozen(data).map_err(invalid(model))?)
+        }
+        _ => Box::new(bpe::HfTokenizer::from_frozen(data).map_err(invalid(model))?),
+    };
+    Ok(tok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `blob` and use the tokenizer, if it loads.
+    fn exercise(blob: Vec<u8>) {
+        if let Ok(tok) = from_frozen("test", blob) {
+            let ids = tok.encode("hello hell <s> héllo");
+            tok.decode_bytes(&ids);
+            tok.decode_bytes(&[0, 3, 7, 9, u32::MAX]);
+            tok.encode_with_offsets("hello");
+        }
+    }
+
+    #[test]
+    fn truncated_and_corrupted_blobs_do_not_panic() {
+        let sources = [
+            r#"{"added_tokens": [{"id": 7, "content": "<s>", "special": true}],
+                "pre_tokenizer": {"type": "WhitespaceSplit"},
+                "decoder": {"type": "Fuse"},
+                "model": {"type": "BPE", "merges": ["h e", "l l"],
+                    "vocab": {"h": 0, "e": 1, "l": 2, "o": 3, "he": 4, "ll": 5, "<s>": 7}}}"#,
+            r###"{"pre_tokenizer": {"type": "WhitespaceSplit"},
+                "model": {"type": "WordPiece", "unk_token": "[UNK]",
+                    "vocab": {"[UNK]": 0, "hel": 1, "##lo": 2, "h": 3, "##e": 4, "##l": 5}}}"###,
+            r#"{"pre_tokenizer": {"type": "WhitespaceSplit"},
+                "model": {"type": "Unigram", "unk_id": 0,
+                    "vocab": [["<unk>", 0.0], ["he", -2.0], ["llo", -2.5], ["h", -3.0], ["l", -3.0]]}}"#,
+            r#"["he", "llo", "h", "e", "l", "o", " "]"#,
+        ];
+        for source in sources {
+            let blob = freeze_model("test", source.as_bytes()).unwrap();
+            for len in 0..blob.len() {
+                exercise(blob[..len].to_vec());
+            }
+            for i in 0..blob.len() {
+                for bit in [0, 4, 7] {
+                    let mut corrupt = blob.clone();
+                    corrupt[i] ^= 1 << bit;
+                    exercise(corrupt);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::borrow::Cow;
+
+/// Text flowing through the normalizer and pre-tokenizer, optionally
+/// carrying alignments back to the caller's original input.
+///
+/// `align[i]` is the byte range of the original text that produced byte `i`
+/// of `text`. Plain counting and encoding leave it `None`, so only
+/// offset-tracking callers pay for the bookkeeping.
+pub struct Normalized {
+    pub text: String,
+    pub align: Option<Vec<(usize, usize)>>,
+}
+
+impl Normalized {
+    pub fn new(text: &str, track_offsets: bool) -> Self {
+        let align = track_offsets.then(|| (0..text.len()).map(|i| (i, i + 1)).collect());
+        Normalized {
+            text: text.to_string(),
+            align,
+        }
+    }
+
+    /// Sub-slice by byte range (must lie on char boundaries).
+    pub fn slice(&self, start: usize, end: usize) -> Normalized {
+        Normalized {
+            text: self.text[start..end].to_string(),
+            align: self.align.as_ref().map(|a| a[start..end].to_vec()),
+        }
+    }
+
+    /// Original byte range covered by `text[start..end]`.
+    pub fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        match &self.align {
+            Some(a) if start < end => (a[start].0, a[end - 1].1),
+            Some(a) => {
+                let at = a
+                    .get(start)
+                    .map_or_else(|| a.last().map_or(0, |r| r.1), |r| r.0);
+                (at, at)
+            }
+            None => (start, end),
+        }
+    }
+
+    /// Build a new string piecewise: `pieces` yields `(output, src_start,
+    /// src_end)` in order, with source ranges into `text` that tile it
+    /// (empty ranges insert text). Unchanged pieces keep their per-byte
+    /// alignment; rewritten ones map every output byte to the whole source
+    /// range.
+    pub fn rebuild<'a>(
+        &self,
+        pieces: impl IntoIterator<Item = (Cow<'a, str>, usize, usize)>,
+    ) -> Normalized {
+        let mut text = String::with_capacity(self.text.len());
+        let mut align = self
+            .align
+            .as_ref()
+            .map(|_| Vec::with_capacity(self.text.len()));
+        for (out, s, e) in pieces {
+            if let Some(align) = align.as_mut() {
+                let span = self.original_range(s, e);
+                if out.len() == e - s && out.as_bytes() == &self.text.as_bytes()[s..e] {
+                    align.extend_from_slice(&self.align.as_ref().unwrap()[s..e]);
+                } else {
+                    align.extend(std::iter::repeat_n(span, out.len()));
+                }
+            }
+            text.push_str(&out);
+        }
+        Normalized { text, align }
+    }
+}
@@ -0,0 +1,1518 @@
+//! The Hugging Face `tokenizer.json` pipeline around a model: normalizers,
+//! pre-tokenizers, decoders and added tokens, as frozen in the config and
+//! added-tokens sections. Shared by the BPE, Unigram and WordPiece
+//! backends.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use unicode_categories::UnicodeCategories;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+use unicode_normalization::{
+    is_nfc_quick, is_nfd_quick, is_nfkc_quick, is_nfkd_quick, IsNormalized, UnicodeNormalization,
+};
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::added::{parse_added_tokens, AddedMatcher, AddedToken};
+use crate::byte_level;
+use crate::convert::{
+    ADDED_NORMALIZED, ADDED_SPECIAL, DEC_BYTE_FALLBACK, DEC_BYTE_LEVEL, DEC_FUSE, DEC_METASPACE,
+    DEC_NONE, DEC_REPLACE, DEC_SEQUENCE, DEC_STRIP, DEC_WORDPIECE, NORM_BERT_CHINESE_CHARS,
+    NORM_BERT_CLEAN_TEXT, NORM_BERT_STRIP_ACCENTS, NORM_BYTE_LEVEL, NORM_LOWERCASE, NORM_NFC,
+    NORM_NFD, NORM_NFKC, NORM_NFKD, NORM_NMT, NORM_NONE, NORM_PRECOMPILED, NORM_PREPEND,
+    NORM_REPLACE, NORM_REPLACE_REGEX, NORM_SEQUENCE, NORM_STRIP, NORM_STRIP_ACCENTS,
+    PREPEND_ALWAYS, PREPEND_FIRST, PREPEND_NEVER, SPLIT_CONTIGUOUS, SPLIT_ISOLATED,
+    SPLIT_MERGED_WITH_NEXT, SPLIT_MERGED_WITH_PREVIOUS, SPLIT_REMOVED, STEP_BYTE_LEVEL,
+    STEP_DIGITS, STEP_METASPACE, STEP_PUNCTUATION, STEP_SPLIT, STEP_UNICODE_SCRIPTS,
+    STEP_WHITESPACE, STEP_WHITESPACE_SPLIT,
+};
+use crate::format::{self, Header};
+use crate::frozen;
+use crate::normalized::Normalized;
+use crate::template::{self, ChatTemplate};
+use crate::{SpecialTokens, TokenSpan};
+
+enum Normalizer {
+    None,
+    Replace {
+        pattern: String,
+        content: String,
+    },
+    ReplaceRegex {
+        regex: fancy_regex::Regex,
+        content: String,
+    },
+    Prepend(String),
+    Unicode(Form),
+    Lowercase,
+    Strip {
+        left: bool,
+        right: bool,
+    },
+    /// Remove combining marks (after NFD, this strips accents).
+    StripAccents,
+    /// SentencePiece's NMT cleanup: drop control characters, map other
+    /// whitespace to spaces.
+    Nmt,
+    Precompiled(Box<Precompiled>),
+    BertCleanText,
+    BertChineseChars,
+    /// BertNormalizer's accent stripping, which removes only nonspacing
+    /// marks.
+    BertStripAccents,
+    ByteLevel {
+        table: Box<[char; 256]>,
+    },
+    Sequence(Vec<Normalizer>),
+}
+
+#[derive(Clone, Copy)]
+enum Form {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+impl Form {
+    fn apply(self, s: &str) -> String {
+        match self {
+            Form::Nfc => s.nfc().collect(),
+            Form::Nfd => s.nfd().collect(),
+            Form::Nfkc => s.nfkc().collect(),
+            Form::Nfkd => s.nfkd().collect(),
+        }
+    }
+
+    fn quick_check(self, c: char) -> IsNormalized {
+        let c = std::iter::once(c);
+        match self {
+            Form::Nfc => is_nfc_quick(c),
+            Form::Nfd => is_nfd_quick(c),
+            Form::Nfkc => is_nfkc_quick(c),
+            Form::Nfkd => is_nfkd_quick(c),
+        }
+    }
+
+    /// Whether `c` normalizes independently of what precedes it: it, or
+    /// else the first character it decomposes to, is a starter that the
+    /// form leaves alone and that cannot combine with what precedes it.
+    fn starts_segment(self, c: char) -> bool {
+        let independent =
+            |c: char| canonical_combining_class(c) == 0 && self.quick_check(c) == IsNormalized::Yes;
+        if independent(c) {
+            return true;
+        }
+        let c = std::iter::once(c);
+        let first = match self {
+            Form::Nfc | Form::Nfd => c.nfd().next(),
+            Form::Nfkc | Form::Nfkd => c.nfkd().next(),
+        };
+        first.is_some_and(independent)
+    }
+}
+
+fn apply_normalizer(norm: &Normalizer, n: Normalized) -> Normalized {
+    match norm {
+        Normalizer::None => n,
+        Normalizer::Replace { pattern, content } => {
+            if pattern.is_empty() {
+                return n;
+            }
+            let mut pieces = Vec::new();
+            let mut last = 0;
+            for (i, _) in n.text.match_indices(pattern.as_str()) {
+                pieces.push((Cow::Borrowed(&n.text[last..i]), last, i));
+                pieces.push((Cow::Borrowed(content.as_str()), i, i + pattern.len()));
+                last = i + pattern.len();
+            }
+            pieces.push((Cow::Borrowed(&n.text[last..]), last, n.text.len()));
+            n.rebuild(pieces)
+        }
+        Normalizer::ReplaceRegex { regex, content } => {
+            let mut pieces = Vec::new();
+            let mut last = 0;
+            for m in regex.find_iter(&n.text).filter_map(Result::ok) {
+                if m.start() == m.end() {
+                    continue;
+                }
+                pieces.push((Cow::Borrowed(&n.text[last..m.start()]), last, m.start()));
+                pieces.push((Cow::Borrowed(content.as_str()), m.start(), m.end()));
+                last = m.end();
+            }
+            if pieces.is_empty() {
+                return n;
+            }
+            pieces.push((Cow::Borrowed(&n.text[last..]), last, n.text.len()));
+            n.rebuild(pieces)
+        }
+        Normalizer::Prepend(prefix) => prepend_str(n, prefix),
+        Normalizer::Unicode(form) => {
+            if n.align.is_none() {
+                return Normalized {
+                    text: form.apply(&n.text),
+                    align: None,
+                };
+            }
+            n.rebuild(
+                unicode_segments(&n.text, *form)
+                    .map(|(s, e)| (Cow::Owned(form.apply(&n.text[s..e])), s, e)),
+            )
+        }
+        Normalizer::Lowercase => map_chars(n, |c| {
+            let mut lower = c.to_lowercase();
+            if lower.len() == 1 && lower.clone().next() == Some(c) {
+                return None;
+            }
+            Some(lower.by_ref().collect())
+        }),
+        Normalizer::Strip { left, right } => {
+            let len = n.text.len();
+            let start = if *left {
+                len - n.text.trim_start().len()
+            } else {
+                0
+            };
+            let end = if *right {
+                n.text.trim_end().len().max(start)
+            } else {
+                len
+            };
+            if start == 0 && end == len {
+                return n;
+            }
+            n.rebuild([
+                (Cow::Borrowed(""), 0, start),
+                (Cow::Borrowed(&n.text[start..end]), start, end),
+                (Cow::Borrowed(""), end, len),
+            ])
+        }
+        Normalizer::StripAccents => map_chars(n, |c| is_combining_mark(c).then(String::new)),
+        Normalizer::Nmt => map_chars(n, |c| match c as u32 {
+            0x0001..=0x0008 | 0x000B | 0x000E..=0x001F | 0x007F | 0x008F | 0x009F => {
+                Some(String::new())
+            }
+            0x0009
+            | 0x000A
+            | 0x000C
+            | 0x000D
+            | 0x1680
+            | 0x200B..=0x200F
+            | 0x2028
+            | 0x2029
+            | 0x2581
+            | 0xFEFF
+            | 0xFFFD => Some(" ".to_string()),
+            _ => None,
+        }),
+        Normalizer::Precompiled(charsmap) => charsmap.normalize(n),
+        Normalizer::BertCleanText => map_chars(n, |c| {
+            if c == '\0' || c == '\u{fffd}' || (!matches!(c, '\t' | '\n' | '\r') && c.is_other()) {
+                Some(String::new())
+            } else if c != ' ' && (matches!(c, '\t' | '\n' | '\r') || c.is_whitespace()) {
+                Some(" ".to_string())
+            } else {
+                None
+            }
+        }),
+        Normalizer::BertStripAccents => map_chars(n, |c| c.is_mark_nonspacing().then(String::new)),
+        Normalizer::BertChineseChars => {
+            map_chars(n, |c| is_chinese_char(c).then(|| format!(" {} ", c)))
+        }
+        Normalizer::ByteLevel { table } => byte_level_chunk(&n, table),
+        Normalizer::Sequence(norms) => norms.iter().fold(n, |n, norm| apply_normalizer(norm, n)),
+    }
+}
+
+fn prepend_str(n: Normalized, prefix: &str) -> Normalized {
+    n.rebuild([
+        (Cow::Borrowed(prefix), 0, 0),
+        (Cow::Borrowed(n.text.as_str()), 0, n.text.len()),
+    ])
+}
+
+/// Rebuild `n` character by character: `f` returns a replacement for a
+/// character (empty to delete it), or `None` to keep it.
+fn map_chars(n: Normalized, mut f: impl FnMut(char) -> Option<String>) -> Normalized {
+    let edits: Vec<_> = n
+        .text
+        .char_indices()
+        .filter_map(|(i, c)| f(c).map(|r| (i, i + c.len_utf8(), Cow::Owned(r))))
+        .collect();
+    replace_ranges(n, edits)
+}
+
+/// Rebuild `n` with the given ascending, non-overlapping byte ranges
+/// replaced.
+fn replace_ranges(n: Normalized, edits: Vec<(usize, usize, Cow<'_, str>)>) -> Normalized {
+    if edits.is_empty() {
+        return n;
+    }
+    let mut pieces = Vec::with_capacity(edits.len() * 2 + 1);
+    let mut kept = 0;
+    for (start, end, with) in edits {
+        if kept < start {
+            pieces.push((Cow::Borrowed(&n.text[kept..start]), kept, start));
+        }
+        pieces.push((with, start, end));
+        kept = end;
+    }
+    pieces.push((Cow::Borrowed(&n.text[kept..]), kept, n.text.len()));
+    n.rebuild(pieces)
+}
+
+/// The CJK ideograph blocks BERT surrounds with spaces.
+fn is_chinese_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x4E00..=0x9FFF
+            | 0x3400..=0x4DBF
+            | 0x20000..=0x2A6DF
+            | 0x2A700..=0x2B73F
+            | 0x2B740..=0x2B81F
+            | 0x2B920..=0x2CEAF
+            | 0xF900..=0xFAFF
+            | 0x2F800..=0x2FA1F
+    )
+}
+
+/// Split `text` into byte ranges that normalize independently under
+/// `form`, so a rewritten character (a ligature, say) keeps its own range.
+fn unicode_segments(text: &str, form: Form) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut bounds: Vec<usize> = text
+        .char_indices()
+        .filter(|&(i, c)| i == 0 || form.starts_segment(c))
+        .map(|(i, _)| i)
+        .collect();
+    bounds.push(text.len());
+    (0..bounds.len() - 1).map(move |k| (bounds[k], bounds[k + 1]))
+}
+
+/// SentencePiece's precompiled character map: a darts-clone double-array
+/// trie over UTF-8 sequences whose values index NUL-terminated
+/// replacements in `normalized`.
+struct Precompiled {
+    trie: Vec<u32>,
+    normalized: String,
+}
+
+impl Precompiled {
+    /// Layout: `[trie byte length u32][trie units][replacement strings]`.
+    fn parse(charsmap: &[u8]) -> Result<Self, String> {
+        let trie_len = frozen::get_u32(charsmap, 0)? as usize;
+        let units = frozen::get_bytes(charsmap, 4, trie_len)?;
+        if !trie_len.is_multiple_of(4) || trie_len == 0 {
+            return Err(format!("precompiled charsmap trie of {} bytes", trie_len));
+        }
+        let trie = units
+            .chunks_exact(4)
+            .map(|u| u32::from_le_bytes(u.try_into().unwrap()))
+            .collect();
+        let normalized = std::str::from_utf8(&charsmap[4 + trie_len..])
+            .map_err(|e| format!("invalid UTF-8 in precompiled charsmap: {e}"))?
+            .to_string();
+        Ok(Precompiled { trie, normalized })
+    }
+
+    /// Replacement for `chunk`, from the shortest key that prefixes it.
+    fn transform(&self, chunk: &str) -> Option<&str> {
+        let offset = |unit: u32| ((unit >> 10) << ((unit & (1 << 9)) >> 6)) as usize;
+        let mut pos = offset(*self.trie.first()?);
+        for &b in chunk.as_bytes() {
+            if b == 0 {
+                break;
+            }
+            pos ^= b as usize;
+            let unit = *self.trie.get(pos)?;
+            if unit & ((1 << 31) | 0xFF) != b as u32 {
+                return None;
+            }
+            pos ^= offset(unit);
+            if (unit >> 8) & 1 == 1 {
+                let value = (*self.trie.get(pos)? & ((1 << 31) - 1)) as usize;
+                let rest = self.normalized.get(value..)?;
+                return Some(&rest[..rest.find('\0').unwrap_or(rest.len())]);
+            }
+        }
+        None
+    }
+
+    /// Like HF's `Precompiled` normalizer: whole graphemes under 6 bytes
+    /// are looked up first, then each character on its own.
+    fn normalize(&self, n: Normalized) -> Normalized {
+        let mut edits = Vec::new();
+        for (g, grapheme) in n.text.grapheme_indices(true) {
+            if grapheme.len() < 6 {
+                if let Some(norm) = self.transform(grapheme) {
+                    edits.push((g, g + grapheme.len(), Cow::Borrowed(norm)));
+                    continue;
+                }
+            }
+            for (i, c) in grapheme.char_indices() {
+                let (start, end) = (g + i, g + i + c.len_utf8());
+                if let Some(norm) = self.transform(&n.text[start..end]) {
+                    edits.push((start, end, Cow::Borrowed(norm)));
+                }
+            }
+        }
+        replace_ranges(n, edits)
+    }
+}
+
+struct SplitPattern {
+    regex: fancy_regex::Regex,
+    behavior: SplitBehavior,
+    /// Split at what the regex does not match instead.
+    invert: bool,
+}
+
+enum PreTokenizer {
+    None,
+    Sequence(Vec<PreTokenizerStep>),
+}
+
+enum PreTokenizerStep {
+    Split(SplitPattern),
+    /// Optionally prepends a space and splits with the GPT-2 pattern, then
+    /// maps bytes to characters.
+    ByteLevel {
+        table: Box<[char; 256]>,
+        add_prefix_space: bool,
+        regex: Option<fancy_regex::Regex>,
+    },
+    Metaspace {
+        replacement: char,
+        prepend: PrependScheme,
+        split: bool,
+    },
+    Digits {
+        individual: bool,
+    },
+    Punctuation(SplitBehavior),
+    /// `\w+|[^\w\s]+`, dropping everything else.
+    Whitespace(fancy_regex::Regex),
+    WhitespaceSplit,
+    UnicodeScripts,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PrependScheme {
+    Never,
+    /// Only on the chunk at the very start of the input.
+    First,
+    Always,
+}
+
+/// What happens to the delimiters when a chunk is split, as in HF's
+/// `SplitDelimiterBehavior`.
+#[derive(Clone, Copy, Debug)]
+enum SplitBehavior {
+    Removed,
+    Isolated,
+    MergedWithPrevious,
+    MergedWithNext,
+    Contiguous,
+}
+
+/// Run the pre-tokenizer over one normalized piece. `at_start` says whether
+/// the piece begins the input, for Metaspace's `prepend_scheme: first`.
+fn apply_pre_tokenizer(pt: &PreTokenizer, n: Normalized, at_start: bool) -> Vec<Normalized> {
+    match pt {
+        PreTokenizer::None => {
+            vec![n]
+        }
+        PreTokenizer::Sequence(steps) => {
+            let mut chunks = vec![n];
+            let mut starts = vec![at_start];
+            // Scratch buffers, reused across chunks.
+            let mut runs = Vec::new();
+            let mut ranges = Vec::new();
+            for step in steps {
+                let mut next_chunks = Vec::new();
+                let mut next_starts = Vec::new();
+                for (chunk, &at_start) in chunks.iter().zip(&starts) {
+                    let mut metaspaced = None;
+                    let behavior = match step {
+                        PreTokenizerStep::Split(sp) => {
+                            regex_runs(&sp.regex, &chunk.text, &mut runs);
+                            if sp.invert {
+                                runs.iter_mut().for_each(|r| r.2 = !r.2);
+                            }
+                            sp.behavior
+                        }
+                        PreTokenizerStep::ByteLevel {
+                            table,
+                            add_prefix_space,
+                            regex,
+                        } => {
+                            let prefixed = (*add_prefix_space && !chunk.text.starts_with(' '))
+                                .then(|| prepend_str(chunk.slice(0, chunk.text.len()), " "));
+                            let chunk = prefixed.as_ref().unwrap_or(chunk);
+                            let Some(regex) = regex else {
+                                next_chunks.push(byte_level_chunk(chunk, table));
+                                next_starts.push(at_start);
+                                continue;
+                            };
+                            regex_runs(regex, &chunk.text, &mut runs);
+                            split_runs(&runs, SplitBehavior::Isolated, &mut ranges);
+                            for &(s, e) in &ranges {
+                                next_chunks.push(byte_level_chunk(&chunk.slice(s, e), table));
+                                next_starts.push(at_start && s == 0);
+                            }
+                            continue;
+                        }
+                        PreTokenizerStep::Metaspace {
+                            replacement,
+                            prepend,
+                            split,
+                        } => {
+                            let chunk = metaspace(chunk, *replacement, *prepend, at_start);
+                            if !split {
+                                next_chunks.push(chunk);
+                                next_starts.push(at_start);
+                                continue;
+                            }
+                            char_runs(&chunk.text, |c| c == *replacement, &mut runs);
+                            metaspaced = Some(chunk);
+                            SplitBehavior::MergedWithNext
+                        }
+                        PreTokenizerStep::Digits { individual } => {
+                            char_runs(&chunk.text, char::is_numeric, &mut runs);
+                            if *individual {
+                                SplitBehavior::Isolated
+                            } else {
+                                SplitBehavior::Contiguous
+                            }
+                        }
+                        PreTokenizerStep::Punctuation(behavior) => {
+                            let is_punctuation =
+                                |c: char| c.is_ascii_punctuation() || c.is_punctuation();
+                            char_runs(&chunk.text, is_punctuation, &mut runs);
+                            *behavior
+                        }
+                        PreTokenizerStep::Whitespace(regex) => {
+                            // Keep the matches, drop what lies between them.
+                            regex_runs(regex, &chunk.text, &mut runs);
+                            runs.iter_mut().for_each(|r| r.2 = !r.2);
+                            SplitBehavior::Removed
+                        }
+                        PreTokenizerStep::WhitespaceSplit => {
+                            char_runs(&chunk.text, char::is_whitespace, &mut runs);
+                            SplitBehavior::Removed
+                        }
+                        PreTokenizerStep::UnicodeScripts => {
+                            script_runs(&chunk.text, &mut runs);
+                            SplitBehavior::Isolated
+                        }
+                    };
+                    let chunk = metaspaced.as_ref().unwrap_or(chunk);
+                    split_runs(&runs, behavior, &mut ranges);
+                    for &(s, e) in &ranges {
+                        next_chunks.push(chunk.slice(s, e));
+                        next_starts.push(at_start && s == 0);
+                    }
+                }
+                chunks = next_chunks;
+                starts = next_starts;
+            }
+            chunks
+        }
+    }
+}
+
+/// Fill `runs` with `text` cut into regex matches and the gaps between
+/// them, as `(start, end, is_match)`.
+fn regex_runs(regex: &fancy_regex::Regex, text: &str, runs: &mut Vec<(usize, usize, bool)>) {
+    runs.clear();
+    let mut last = 0;
+    for m in regex.find_iter(text).filter_map(Result::ok) {
+        if m.start() > last {
+            runs.push((last, m.start(), false));
+        }
+        runs.push((m.start(), m.end(), true));
+        last = m.end();
+    }
+    if last < text.len() {
+        runs.push((last, text.len(), false));
+    }
+}
+
+/// Like [`regex_runs`], with every character matching `pred` a match of
+/// its own.
+fn char_runs(text: &str, pred: impl Fn(char) -> bool, runs: &mut Vec<(usize, usize, bool)>) {
+    runs.clear();
+    let mut last = 0;
+    for (i, c) in text.char_indices().filter(|&(_, c)| pred(c)) {
+        if i > last {
+            runs.push((last, i, false));
+        }
+        runs.push((i, i + c.len_utf8(), true));
+        last = i + c.len_utf8();
+    }
+    if last < text.len() {
+        runs.push((last, text.len(), false));
+    }
+}
+
+/// Fill `out` with the byte ranges of the chunks left after splitting at
+/// the matching runs, following HF's `NormalizedString::split`. Empty
+/// chunks are dropped.
+fn split_runs(
+    runs: &[(usize, usize, bool)],
+    behavior: SplitBehavior,
+    out: &mut Vec<(usize, usize)>,
+) {
+    out.clear();
+    let mut previous_match = false;
+    match behavior {
+        SplitBehavior::Isolated => out.extend(runs.iter().map(|&(s, e, _)| (s, e))),
+        SplitBehavior::Removed => out.extend(runs.iter().filter(|r| !r.2).map(|&(s, e, _)| (s, e))),
+        SplitBehavior::MergedWithPrevious => {
+            for &(s, e, is_match) in runs {
+                match out.last_mut() {
+                    Some(last) if is_match && !previous_match => last.1 = e,
+                    _ => out.push((s, e)),
+                }
+                previous_match = is_match;
+            }
+        }
+        SplitBehavior::MergedWithNext => {
+            for &(s, e, is_match) in runs.iter().rev() {
+                match out.last_mut() {
+                    Some(last) if is_match && !previous_match => last.0 = s,
+                    _ => out.push((s, e)),
+                }
+                previous_match = is_match;
+            }
+            out.reverse();
+        }
+        SplitBehavior::Contiguous => {
+            for &(s, e, is_match) in runs {
+                match out.last_mut() {
+                    Some(last) if is_match == previous_match => last.1 = e,
+                    _ => out.push((s, e)),
+                }
+                previous_match = is_match;
+            }
+        }
+    }
+    out.retain(|&(s, e)| s < e);
+}
+
+/// HF's Metaspace step, before any split: spaces become `replacement`,
+/// which is also prepended unless the chunk already starts with it.
+fn metaspace(
+    n: &Normalized,
+    replacement: char,
+    prepend: PrependScheme,
+    at_start: bool,
+) -> Normalized {
+    let n = map_chars(n.slice(0, n.text.len()), |c| {
+        (c == ' ').then(|| replacement.to_string())
+    });
+    let prepend = match prepend {
+        PrependScheme::Always => true,
+        PrependScheme::First => at_start,
+        PrependScheme::Never => false,
+    };
+    if !prepend || n.text.starts_with(replacement) {
+        return n;
+    }
+    prepend_str(n, replacement.encode_utf8(&mut [0; 4]))
+}
+
+/// Fill `runs` with the runs of `text` in one script, as HF's
+/// UnicodeScripts splits them: spaces join either neighbour, Hiragana,
+/// Katakana and `ー` count as Han, and (as in HF) leading spaces are
+/// dropped.
+fn script_runs(text: &str, runs: &mut Vec<(usize, usize, bool)>) {
+    let script = |c: char| match c {
+        ' ' => None,
+        '\u{30FC}' => Some(Script::Han),
+        c => match c.script() {
+            Script::Hiragana | Script::Katakana => Some(Script::Han),
+            s => Some(s),
+        },
+    };
+    runs.clear();
+    let mut last = None;
+    for (i, c) in text.char_indices() {
+        if let Some(s) = script(c) {
+            if last != Some(s) {
+                if let Some(run) = runs.last_mut() {
+                    run.1 = i;
+                }
+                runs.push((i, text.len(), false));
+            }
+            last = Some(s);
+        }
+    }
+}
+
+fn byte_level_chunk(chunk: &Normalized, table: &[char; 256]) -> Normalized {
+    let text = byte_level::encode_bytes(chunk.text.as_bytes(), table);
+    let align = chunk.align.as_ref().map(|a| {
+        chunk
+            .text
+            .bytes()
+            .zip(a)
+            .flat_map(|(b, &r)| std::iter::repeat_n(r, table[b as usize].len_utf8()))
+            .collect()
+    });
+    Normalized { text, align }
+}
+
+enum Decoder {
+    /// No decoder configured: tokens are joined with spaces.
+    None,
+    Sequence(Vec<Decoder>),
+    ByteLevel,
+    ByteFallback,
+    Fuse,
+    Strip {
+        content: String,
+        start: usize,
+        stop: usize,
+    },
+    Replace {
+        pattern: String,
+        content: String,
+    },
+    Metaspace {
+        replacement: String,
+        strip_first: bool,
+    },
+    WordPiece {
+        prefix: String,
+        cleanup: bool,
+    },
+}
+
+/// Apply a decoder chain to per-token byte strings, mirroring HuggingFace's
+/// `decode_chain`. Bytes rather than `String`s so that byte-fallback and
+/// byte-level tokens can carry partial UTF-8 sequences between tokens.
+fn apply_decoder(dec: &Decoder, tokens: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    match dec {
+        Decoder::None => vec![tokens.join(&b' ')],
+        Decoder::Sequence(decs) => decs.iter().fold(tokens, |t, d| apply_decoder(d, t)),
+        Decoder::ByteLevel => {
+            let bytes = tokens
+                .into_iter()
+                .flat_map(|t| {
+                    std::str::from_utf8(&t)
+                        .ok()
+                        .and_then(byte_level::decode_str)
+                        .unwrap_or(t)
+                })
+                .collect();
+            vec![bytes]
+        }
+        Decoder::ByteFallback => tokens
+            .into_iter()
+            .map(|t| match parse_byte_token(&t) {
+                Some(b) => vec![b],
+                None => t,
+            })
+            .collect(),
+        Decoder::Fuse => vec![tokens.concat()],
+        Decoder::Strip {
+            content,
+            start,
+            stop,
+        } => {
+            let c = content.as_bytes();
+            tokens
+                .into_iter()
+                .map(|t| {
+                    let mut s = &t[..];
+                    for _ in 0..*start {
+                        match s.strip_prefix(c) {
+                            Some(rest) if !c.is_empty() => s = rest,
+                            _ => break,
+                        }
+                    }
+                    for _ in 0..*stop {
+                        match s.strip_suffix(c) {
+                            Some(rest) if !c.is_empty() => s = rest,
+                            _ => break,
+                        }
+                    }
+                    s.to_vec()
+                })
+                .collect()
+        }
+        Decoder::Replace { pattern, content } => tokens
+            .into_iter()
+            .map(|t| replace_bytes(&t, pattern.as_bytes(), content.as_bytes()))
+            .collect(),
+        Decoder::Metaspace {
+            replacement,
+            strip_first,
+        } => tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let t = replace_bytes(&t, replacement.as_bytes(), b" ");
+                match t.strip_prefix(b" ") {
+                    Some(rest) if i == 0 && *strip_first => rest.to_vec(),
+                    _ => t,
+                }
+            })
+            .collect(),
+        Decoder::WordPiece { prefix, cleanup } => tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let t = if i == 0 {
+                    t
+                } else if let Some(rest) = t.strip_prefix(prefix.as_bytes()) {
+                    rest.to_vec()
+                } else {
+                    [b" ", &t[..]].concat()
+                };
+                if *cleanup {
+                    wordpiece_cleanup(t)
+                } else {
+                    t
+                }
+            })
+            .collect(),
+    }
+}
+
+/// WordPiece `cleanup`: undo the spaces BERT puts around punctuation and contractions.
+fn wordpiece_cleanup(t: Vec<u8>) -> Vec<u8> {
+    const EDITS: &[(&[u8], &[u8])] = &[
+        (b" .", b"."),
+        (b" ?", b"?"),
+        (b" !", b"!"),
+        (b" ,", b","),
+        (b" ' ", b"'"),
+        (b" n't", b"n't"),
+        (b" 'm", b"'m"),
+        (b" do not", b" don't"),
+        (b" 's", b"'s"),
+        (b" 've", b"'ve"),
+        (b" 're", b"'re"),
+    ];
+    EDITS.iter().fold(t, |t, (pattern, content)| {
+        replace_bytes(&t, pattern, content)
+    })
+}
+
+/// Parse a SentencePiece byte-fallback token like `<0x0A>`.
+fn parse_byte_token(t: &[u8]) -> Option<u8> {
+    if t.len() != 6 || !t.starts_with(b"<0x") || t[5] != b'>' {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(&t[3..5]).ok()?, 16).ok()
+}
+
+fn replace_bytes(haystack: &[u8], pattern: &[u8], content: &[u8]) -> Vec<u8> {
+    if pattern.is_empty() {
+        return haystack.to_vec();
+    }
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(pattern) {
+            out.extend_from_slice(content);
+            i += pattern.len();
+        } else {
+            out.push(haystack[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// A stretch of input after added-token extraction.
+pub(crate) enum Piece {
+    Added(TokenSpan),
+    Text(Normalized),
+}
+
+/// Everything in an HF model but the model itself: the config section's
+/// normalizer, pre-tokenizer, decoder and post-processor IDs, and the added
+/// tokens matched around them.
+pub(crate) struct Pipeline {
+    pub byte_fallback: bool,
+    /// Post-processor IDs before and after a single sequence, and before,
+    /// between and after a pair.
+    post_single: [Vec<u32>; 2],
+    post_pair: [Vec<u32>; 3],
+    add_special_tokens: bool,
+    normalizer: Normalizer,
+    pre_tokenizer: PreTokenizer,
+    decoder: Decoder,
+    /// Added tokens matched in the raw input, and those matched after
+    /// normalization.
+    added: AddedMatcher,
+    added_normalized: AddedMatcher,
+    /// Every special token by its original content, for `find_special_token`.
+    specials: AddedMatcher,
+    special_tokens: SpecialTokens,
+    chat_template: Option<ChatTemplate>,
+}
+
+impl Pipeline {
+    /// Read the config and added-tokens sections of an HF model blob, and
+    /// its chat template if it has one.
+    pub fn from_frozen(header: &Header, data: &[u8]) -> Result<Self, String> {
+        let config = header.section(format::SEC_CONFIG)?;
+        let added_tokens = header.section(format::SEC_ADDED_TOKENS)?;
+        let Config {
+            byte_fallback,
+            post_single,
+            post_pair,
+            normalizer,
+            pre_tokenizer,
+            decoder,
+        } = parse_config(&data[config])?;
+        let added_tokens = parse_added_tokens(&data[added_tokens])
+            .map_err(|e| format!("added-tokens section: {}", e))?;
+        let chat_template = header
+            .optional_section(format::SEC_CHAT_TEMPLATE)
+            .map(|range| template::parse_section(&data[range]))
+            .transpose()
+            .map_err(|e| format!("chat-template section: {}", e))?;
+
+        let specials = added_tokens
+            .iter()
+            .filter(|t| t.flags & ADDED_SPECIAL != 0)
+            .map(|t| AddedToken {
+                id: t.id,
+                content: t.content.clone(),
+                flags: ADDED_SPECIAL,
+            })
+            .collect();
+        let (normalized, raw): (Vec<_>, Vec<_>) = added_tokens
+            .into_iter()
+            .partition(|t| t.flags & ADDED_NORMALIZED != 0);
+        let normalized = normalized
+            .into_iter()
+            .map(|t| AddedToken {
+                content: apply_normalizer(&normalizer, Normalized::new(&t.content, false)).text,
+                ..t
+            })
+            .collect();
+
+        Ok(Pipeline {
+            byte_fallback,
+            post_single,
+            post_pair,
+            add_special_tokens: true,
+            normalizer,
+            pre_tokenizer,
+            decoder,
+            added: AddedMatcher::new(raw),
+            added_normalized: AddedMatcher::new(normalized),
+            specials: AddedMatcher::new(specials),
+            special_tokens: SpecialTokens::default(),
+            chat_template,
+        })
+    }
+
+    pub fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        self.special_tokens = mode;
+    }
+
+    pub fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        self.specials.find_disallowed(text, &self.special_tokens)
+    }
+
+    pub fn set_add_special_tokens(&mut self, add: bool) {
+        self.add_special_tokens = add;
+    }
+
+    pub fn chat_template(&self) -> Option<&ChatTemplate> {
+        self.chat_template.as_ref()
+    }
+
+    /// Number of IDs the post-processor adds around one sequence, or around
+    /// a pair.
+    pub fn post_count(&self, pair: bool) -> usize {
+        match (self.add_special_tokens, pair) {
+            (false, _) => 0,
+            (true, false) => self.post_single.iter().map(Vec::len).sum(),
+            (true, true) => self.post_pair.iter().map(Vec::len).sum(),
+        }
+    }
+
+    /// Encode one sequence of `len` bytes: the post-processor's IDs around
+    /// the spans `encode` pushes, with empty ranges at the input's start and
+    /// end.
+    pub fn encode_single(
+        &self,
+        len: usize,
+        encode: impl FnOnce(&mut Vec<TokenSpan>),
+    ) -> Vec<TokenSpan> {
+        let mut spans = Vec::new();
+        let add = self.add_special_tokens;
+        let [before, after] = &self.post_single;
+        spans.extend(before.iter().filter(|_| add).map(|&id| TokenSpan {
+            id,
+            start: 0,
+            end: 0,
+        }));
+        encode(&mut spans);
+        spans.extend(after.iter().filter(|_| add).map(|&id| TokenSpan {
+            id,
+            start: len,
+            end: len,
+        }));
+        spans
+    }
+
+    /// Encode a pair: the post-processor's IDs before, between and after
+    /// the spans `encode_a` and `encode_b` push.
+    pub fn encode_pair(
+        &self,
+        encode_a: impl FnOnce(&mut Vec<TokenSpan>),
+        encode_b: impl FnOnce(&mut Vec<TokenSpan>),
+    ) -> Vec<u32> {
+        let mut spans = Vec::new();
+        let add = self.add_special_tokens;
+        let special = |id: &u32| TokenSpan {
+            id: *id,
+            start: 0,
+            end: 0,
+        };
+        let [before, between, after] = &self.post_pair;
+        spans.extend(before.iter().filter(|_| add).map(special));
+        encode_a(&mut spans);
+        spans.extend(between.iter().filter(|_| add).map(special));
+        encode_b(&mut spans);
+        spans.extend(after.iter().filter(|_| add).map(special));
+        spans.into_iter().map(|t| t.id).collect()
+    }
+
+    /// Split `text` into added tokens and the non-empty pre-tokenized
+    /// chunks the model encodes, in order.
+    pub fn pre_tokenize(&self, text: &str, track_offsets: bool) -> Vec<Piece> {
+        let mut out = Vec::new();
+        for (i, piece) in self
+            .split_added(text, track_offsets)
+            .into_iter()
+            .enumerate()
+        {
+            match piece {
+                Piece::Added(span) => out.push(Piece::Added(span)),
+                Piece::Text(n) => out.extend(
+                    apply_pre_tokenizer(&self.pre_tokenizer, n, i == 0)
+                        .into_iter()
+                        .filter(|chunk| !chunk.text.is_empty())
+                        .map(Piece::Text),
+                ),
+            }
+        }
+        out
+    }
+
+    /// Split `text` around added tokens and normalize the rest, like HF's
+    /// `AddedVocabulary`: tokens are matched in the raw input first, then
+    /// `normalized` ones in the normalized text between those matches.
+    fn split_added(&self, text: &str, track_offsets: bool) -> Vec<Piece> {
+        let specials = &self.special_tokens;
+        let whole = Normalized::new(text, track_offsets);
+        let mut pieces = Vec::new();
+        let matches = self.added.find(text, specials);
+        if matches.is_empty() {
+            self.normalize_piece(whole, specials, &mut pieces);
+            return pieces;
+        }
+        let mut last = 0;
+        for (start, end, id) in matches {
+            if start > last {
+                self.normalize_piece(whole.slice(last, start), specials, &mut pieces);
+            }
+            pieces.push(Piece::Added(TokenSpan { id, start, end }));
+            last = end;
+        }
+        if last < text.len() {
+            self.normalize_piece(whole.slice(last, text.len()), specials, &mut pieces);
+        }
+        pieces
+    }
+
+    fn normalize_piece(&self, raw: Normalized, specials: &SpecialTokens, pieces: &mut Vec<Piece>) {
+        let n = apply_normalizer(&self.normalizer, raw);
+        let matches = self.added_normalized.find(&n.text, specials);
+        if matches.is_empty() {
+            pieces.push(Piece::Text(n));
+            return;
+        }
+        let mut last = 0;
+        for (s, e, id) in matches {
+            if s > last {
+                pieces.push(Piece::Text(n.slice(last, s)));
+            }
+            let (start, end) = n.original_range(s, e);
+            pieces.push(Piece::Added(TokenSpan { id, start, end }));
+            last = e;
+        }
+        if last < n.text.len() {
+            pieces.push(Piece::Text(n.slice(last, n.text.len())));
+        }
+    }
+
+    /// Join per-token byte strings through the decoder chain.
+    pub fn decode(&self, tokens: Vec<Vec<u8>>) -> Vec<u8> {
+        apply_decoder(&self.decoder, tokens).concat()
+    }
+}
+
+/// Everything in the config section.
+struct Config {
+    byte_fallback: bool,
+    post_single: [Vec<u32>; 2],
+    post_pair: [Vec<u32>; 3],
+    normalizer: Normalizer,
+    pre_tokenizer: PreTokenizer,
+    decoder: Decoder,
+}
+
+fn parse_config(config: &[u8]) -> Result<Config, String> {
+    let mut off = 0;
+    let byte_fallback = frozen::get_u8(config, off)? != 0;
+    off += 1;
+    let mut next_list = || -> Result<Vec<u32>, String> {
+        let (ids, len) = read_u32_list(config, off)?;
+        off += len;
+        Ok(ids)
+    };
+    let post_single = [next_list()?, next_list()?];
+    let post_pair = [next_list()?, next_list()?, next_list()?];
+    let (normalizer, norm_len) = deserialize_normalizer(config, off)?;
+    off += norm_len;
+    let (pre_tokenizer, pt_len) = deserialize_pre_tokenizer(config, off)?;
+    off += pt_len;
+    let (decoder, dec_len) = deserialize_decoder(config, off)?;
+    off += dec_len;
+    if off != config.len() {
+        return Err(format!(
+            "config section has {} unread bytes",
+            config.len() - off
+        ));
+    }
+    Ok(Config {
+        byte_fallback,
+        post_single,
+        post_pair,
+        normalizer,
+        pre_tokenizer,
+        decoder,
+    })
+}
+
+/// Labelled, human-readable lines for a config section (`tokencount inspect`).
+pub(crate) fn describe_config(config: &[u8]) -> Result<Vec<(&'static str, String)>, String> {
+    let c = parse_config(config)?;
+    Ok(vec![
+        (
+            "byte fallback",
+            if c.byte_fallback { "yes" } else { "no" }.to_string(),
+        ),
+        (
+            "post-processor",
+            format!("{:?} + sequence + {:?}", c.post_single[0], c.post_single[1]),
+        ),
+        (
+            "pair template",
+            format!(
+                "{:?} + A + {:?} + B + {:?}",
+                c.post_pair[0], c.post_pair[1], c.post_pair[2]
+            ),
+        ),
+        ("normalizer", c.normalizer.to_string()),
+        ("pre-tokenizer", c.pre_tokenizer.to_string()),
+        ("decoder", c.decoder.to_string()),
+    ])
+}
+
+fn join_chain<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(" → ")
+}
+
+impl fmt::Display for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Normalizer::None => f.write_str("none"),
+            Normalizer::Replace { pattern, content } => {
+                write!(f, "Replace({:?} → {:?})", pattern, content)
+            }
+            Normalizer::ReplaceRegex { regex, content } => {
+                write!(f, "Replace(/{}/ → {:?})", regex.as_str(), content)
+            }
+            Normalizer::Prepend(s) => write!(f, "Prepend({:?})", s),
+            Normalizer::Unicode(Form::Nfc) => f.write_str("NFC"),
+            Normalizer::Unicode(Form::Nfd) => f.write_str("NFD"),
+            Normalizer::Unicode(Form::Nfkc) => f.write_str("NFKC"),
+            Normalizer::Unicode(Form::Nfkd) => f.write_str("NFKD"),
+            Normalizer::Lowercase => f.write_str("Lowercase"),
+            Normalizer::Strip { left, right } => write!(f, "Strip(left {}, right {})", left, right),
+            Normalizer::StripAccents => f.write_str("StripAccents"),
+            Normalizer::Nmt => f.write_str("Nmt"),
+            Normalizer::Precompiled(charsmap) => {
+                write!(f, "Precompiled({} trie units)", charsmap.trie.len())
+            }
+            Normalizer::BertCleanText => f.write_str("BertCleanText"),
+            Normalizer::BertChineseChars => f.write_str("BertChineseChars"),
+            Normalizer::BertStripAccents => f.write_str("BertStripAccents"),
+            Normalizer::ByteLevel { .. } => f.write_str("ByteLevel"),
+            Normalizer::Sequence(norms) => f.write_str(&join_chain(norms)),
+        }
+    }
+}
+
+impl fmt::Display for PreTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreTokenizer::None => f.write_str("none"),
+            PreTokenizer::Sequence(steps) => f.write_str(&join_chain(steps)),
+        }
+    }
+}
+
+impl fmt::Display for PreTokenizerStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreTokenizerStep::Split(p) => {
+                let invert = if p.invert { ", inverted" } else { "" };
+                write!(
+                    f,
+                    "Split(/{}/, {:?}{})",
+                    p.regex.as_str(),
+                    p.behavior,
+                    invert
+                )
+            }
+            PreTokenizerStep::ByteLevel {
+                add_prefix_space,
+                regex,
+                ..
+            } => {
+                let mut options = Vec::new();
+                if *add_prefix_space {
+                    options.push("prefix space");
+                }
+                if regex.is_some() {
+                    options.push("GPT-2 split");
+                }
+                if options.is_empty() {
+                    f.write_str("ByteLevel")
+                } else {
+                    write!(f, "ByteLevel({})", options.join(", "))
+                }
+            }
+            PreTokenizerStep::Metaspace {
+                replacement,
+                prepend,
+                split,
+            } => {
+                let prepend = match prepend {
+                    PrependScheme::Never => "never",
+                    PrependScheme::First => "first",
+                    PrependScheme::Always => "always",
+                };
+                let split = if *split { ", split" } else { "" };
+                write!(
+                    f,
+                    "Metaspace({:?}, prepend {}{})",
+                    replacement, prepend, split
+                )
+            }
+            PreTokenizerStep::Digits { individual: true } => f.write_str("Digits(individual)"),
+            PreTokenizerStep::Digits { individual: false } => f.write_str("Digits"),
+            PreTokenizerStep::Punctuation(behavior) => write!(f, "Punctuation({:?})", behavior),
+            PreTokenizerStep::Whitespace(_) => f.write_str("Whitespace"),
+            PreTokenizerStep::WhitespaceSplit => f.write_str("WhitespaceSplit"),
+            PreTokenizerStep::UnicodeScripts => f.write_str("UnicodeScripts"),
+        }
+    }
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoder::None => f.write_str("none"),
+            Decoder::Sequence(decs) if decs.is_empty() => f.write_str("pass-through"),
+            Decoder::Sequence(decs) => f.write_str(&join_chain(decs)),
+            Decoder::ByteLevel => f.write_str("ByteLevel"),
+            Decoder::ByteFallback => f.write_str("ByteFallback"),
+            Decoder::Fuse => f.write_str("Fuse"),
+            Decoder::Strip {
+                content,
+                start,
+                stop,
+            } => {
+                write!(f, "Strip({:?}, start {}, stop {})", content, start, stop)
+            }
+            Decoder::Replace { pattern, content } => {
+                write!(f, "Replace({:?} → {:?})", pattern, content)
+            }
+            Decoder::Metaspace {
+                replacement,
+                strip_first,
+            } => {
+                let strip = if *strip_first { ", strip first" } else { "" };
+                write!(f, "Metaspace({:?}{})", replacement, strip)
+            }
+            Decoder::WordPiece { prefix, cleanup } => {
+                let cleanup = if *cleanup { ", cleanup" } else { "" };
+                write!(f, "WordPiece({:?}{})", prefix, cleanup)
+            }
+        }
+    }
+}
+
+fn deserialize_normalizer(data: &[u8], off: usize) -> Result<(Normalizer, usize), String> {
+    let tag = frozen::get_u8(data, off)?;
+    let mut pos = off + 1;
+    match tag {
+        NORM_NONE => Ok((Normalizer::None, 1)),
+        NORM_REPLACE => {
+            let (pattern, len1) = frozen::get_str(data, pos)?;
+            pos += len1;
+            let (content, len2) = frozen::get_str(data, pos)?;
+            pos += len2;
+            Ok((Normalizer::Replace { pattern, content }, pos - off))
+        }
+        NORM_PREPEND => {
+            let (prepend, len) = frozen::get_str(data, pos)?;
+            pos += len;
+            Ok((Normalizer::Prepend(prepend), pos - off))
+        }
+        NORM_REPLACE_REGEX => {
+            let (pattern, len1) = frozen::get_str(data, pos)?;
+            pos += len1;
+            let (content, len2) = frozen::get_str(data, pos)?;
+            pos += len2;
+            let regex = fancy_regex::Regex::new(&pattern)
+                .map_err(|e| format!("invalid Replace regex {:?}: {}", pattern, e))?;
+            Ok((Normalizer::ReplaceRegex { regex, content }, pos - off))
+        }
+        NORM_NFC => Ok((Normalizer::Unicode(Form::Nfc), 1)),
+        NORM_NFD => Ok((Normalizer::Unicode(Form::Nfd), 1)),
+        NORM_NFKC => Ok((Normalizer::Unicode(Form::Nfkc), 1)),
+        NORM_NFKD => Ok((Normalizer::Unicode(Form::Nfkd), 1)),
+        NORM_LOWERCASE => Ok((Normalizer::Lowercase, 1)),
+        NORM_STRIP => {
+            let left = frozen::get_u8(data, pos)? != 0;
+            let right = frozen::get_u8(data, pos + 1)? != 0;
+            Ok((Normalizer::Strip { left, right }, 3))
+        }
+        NORM_STRIP_ACCENTS => Ok((Normalizer::StripAccents, 1)),
+        NORM_NMT => Ok((Normalizer::Nmt, 1)),
+        NORM_PRECOMPILED => {
+            let len = frozen::get_u32(data, pos)? as usize;
+            let charsmap = frozen::get_bytes(data, pos + 4, len)?;
+            let charsmap = Precompiled::parse(charsmap)?;
+            Ok((Normalizer::Precompiled(Box::new(charsmap)), 5 + len))
+        }
+        NORM_BERT_CLEAN_TEXT => Ok((Normalizer::BertCleanText, 1)),
+        NORM_BERT_CHINESE_CHARS => Ok((Normalizer::BertChineseChars, 1)),
+        NORM_BERT_STRIP_ACCENTS => Ok((Normalizer::BertStripAccents, 1)),
+        NORM_BYTE_LEVEL => Ok((
+            Normalizer::ByteLevel {
+                table: Box::new(byte_level::byte_to_char_table()),
+            },
+            1,
+        )),
+        NORM_SEQUENCE => {
+            let count = frozen::get_u32(data, pos)? as usize;
+            pos += 4;
+            let mut norms = Vec::new();
+            for _ in 0..count {
+                let (n, len) = deserialize_normalizer(data, pos)?;
+                pos += len;
+                norms.push(n);
+            }
+            if norms.is_empty() {
+                Ok((Normalizer::None, pos - off))
+            } else {
+                Ok((Normalizer::Sequence(norms), pos - off))
+            }
+        }
+        _ => Err(format!("unknown normalizer tag: {}", tag)),
+    }
+}
+
+fn deserialize_split_behavior(tag: u8) -> Result<SplitBehavior, String> {
+    match tag {
+        SPLIT_REMOVED => Ok(SplitBehavior::Removed),
+        SPLIT_ISOLATED => Ok(SplitBehavior::Isolated),
+        SPLIT_MERGED_WITH_PREVIOUS => Ok(SplitBehavior::MergedWithPrevious),
+        SPLIT_MERGED_WITH_NEXT => Ok(SplitBehavior::MergedWithNext),
+        SPLIT_CONTIGUOUS => Ok(SplitBehavior::Contiguous),
+        _ => Err(format!("unknown split behavior: {}", tag)),
+    }
+}
+
+fn deserialize_pre_tokenizer(data: &[u8], off: usize) -> Result<(PreTokenizer, usize), String> {
+    let mut pos = off;
+    let step_count = frozen::get_u32(data, pos)? as usize;
+    pos += 4;
+
+    if step_count == 0 {
+        return Ok((PreTokenizer::None, pos - off));
+    }
+
+    let mut steps = Vec::new();
+    for _ in 0..step_count {
+        let step_tag = frozen::get_u8(data, pos)?;
+        pos += 1;
+        match step_tag {
+            STEP_SPLIT => {
+                let (pattern, len) = frozen::get_str(data, pos)?;
+                pos += len;
+                let regex = fancy_regex::Regex::new(&pattern)
+                    .map_err(|e| format!("invalid pre-tokenizer regex: {e}"))?;
+                let behavior = deserialize_split_behavior(frozen::get_u8(data, pos)?)?;
+                let invert = frozen::get_u8(data, pos + 1)? != 0;
+                pos += 2;
+                steps.push(PreTokenizerStep::Split(SplitPattern {
+                    regex,
+                    behavior,
+                    invert,
+                }));
+            }
+            STEP_BYTE_LEVEL => {
+                let add_prefix_space = frozen::get_u8(data, pos)? != 0;
+                let (pattern, len) = frozen::get_str(data, pos + 1)?;
+                pos += 1 + len;
+                let regex = match pattern.as_str() {
+                    "" => None,
+                    pattern => Some(
+                        fancy_regex::Regex::new(pattern)
+                            .map_err(|e| format!("invalid ByteLevel regex: {e}"))?,
+                    ),
+                };
+                steps.push(PreTokenizerStep::ByteLevel {
+                    table: Box::new(byte_level::byte_to_char_table()),
+                    add_prefix_space,
+                    regex,
+                });
+            }
+            STEP_METASPACE => {
+                let (replacement, len) = frozen::get_str(data, pos)?;
+                pos += len;
+                let mut chars = replacement.chars();
+                let replacement = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => {
+                        return Err(format!(
+                            "Metaspace replacement {:?} is not one character",
+                            replacement
+                        ))
+                    }
+                };
+                let prepend = match frozen::get_u8(data, pos)? {
+                    PREPEND_NEVER => PrependScheme::Never,
+                    PREPEND_FIRST => PrependScheme::First,
+                    PREPEND_ALWAYS => PrependScheme::Always,
+                    other => return Err(format!("unknown Metaspace prepend scheme: {}", other)),
+                };
+                let split = frozen::get_u8(data, pos + 1)? != 0;
+                pos += 2;
+                steps.push(PreTokenizerStep::Metaspace {
+                    replacement,
+                    prepend,
+                    split,
+                });
+            }
+            STEP_DIGITS => {
+                let individual = frozen::get_u8(data, pos)? != 0;
+                pos += 1;
+                steps.push(PreTokenizerStep::Digits { individual });
+            }
+            STEP_PUNCTUATION => {
+                let behavior = deserialize_split_behavior(frozen::get_u8(data, pos)?)?;
+                pos += 1;
+                steps.push(PreTokenizerStep::Punctuation(behavior));
+            }
+            STEP_WHITESPACE => {
+                let regex = fancy_regex::Regex::new(r"\w+|[^\w\s]+").map_err(|e| e.to_string())?;
+                steps.push(PreTokenizerStep::Whitespace(regex));
+            }
+            STEP_WHITESPACE_SPLIT => steps.push(PreTokenizerStep::WhitespaceSplit),
+            STEP_UNICODE_SCRIPTS => steps.push(PreTokenizerStep::UnicodeScripts),
+            _ => return Err(format!("unknown pre-tokenizer step tag: {}", step_tag)),
+        }
+    }
+
+    Ok((PreTokenizer::Sequence(steps), pos - off))
+}
+
+fn deserialize_decoder(data: &[u8], off: usize) -> Result<(Decoder, usize), String> {
+    let tag = frozen::get_u8(data, off)?;
+    let mut pos = off + 1;
+    let dec = match tag {
+        DEC_NONE => Decoder::None,
+        DEC_SEQUENCE => {
+            let count = frozen::get_u32(data, pos)? as usize;
+            pos += 4;
+            let mut decs = Vec::new();
+            for _ in 0..count {
+                let (d, len) = deserialize_decoder(data, pos)?;
+                pos += len;
+                decs.push(d);
+            }
+            Decoder::Sequence(decs)
+        }
+        DEC_BYTE_LEVEL => Decoder::ByteLevel,
+        DEC_BYTE_FALLBACK => Decoder::ByteFallback,
+        DEC_FUSE => Decoder::Fuse,
+        DEC_STRIP => {
+            let (content, len) = frozen::get_str(data, pos)?;
+            pos += len;
+            let start = frozen::get_u32(data, pos)? as usize;
+            let stop = frozen::get_u32(data, pos + 4)? as usize;
+            pos += 8;
+            Decoder::Strip {
+                content,
+                start,
+                stop,
+            }
+        }
+        DEC_REPLACE => {
+            let (pattern, len1) = frozen::get_str(data, pos)?;
+            pos += len1;
+            let (content, len2) = frozen::get_str(data, pos)?;
+            pos += len2;
+            Decoder::Replace { pattern, content }
+        }
+        DEC_METASPACE => {
+            let (replacement, len) = frozen::get_str(data, pos)?;
+            pos += len;
+            let strip_first = frozen::get_u8(data, pos)? != 0;
+            pos += 1;
+            Decoder::Metaspace {
+                replacement,
+                strip_first,
+            }
+        }
+        DEC_WORDPIECE => {
+            let (prefix, len) = frozen::get_str(data, pos)?;
+            pos += len;
+            let cleanup = frozen::get_u8(data, pos)? != 0;
+            pos += 1;
+            Decoder::WordPiece { prefix, cleanup }
+        }
+        _ => return Err(format!("unknown decoder tag: {}", tag)),
+    };
+    Ok((dec, pos - off))
+}
+
+fn read_u32_list(data: &[u8], off: usize) -> Result<(Vec<u32>, usize), String> {
+    let count = frozen::get_u32(data, off)? as usize;
+    let values = (0..count)
+        .map(|i| frozen::get_u32(data, off + 4 + i * 4))
+        .collect::<Result<_, _>>()?;
+    Ok((values, 4 + count * 4))
+}
@@ -0,0 +1,124 @@
+//! Splitting text into chunks of at most N tokens.
+//!
+//! A chunk is cut at the last paragraph break that fits, else the last line
+//! break, sentence end or space, and only then mid-word; breaks in the first
+//! half of the window are passed over so chunks are not needlessly small.
+//! The window comes from the token offsets of the whole text, and every
+//! chunk is then counted on its own, so its count is what the model gives
+//! that text (BOS/EOS included, when the tokenizer adds them).
+
+use crate::Tokenizer;
+
+/// A chunk of the input: bytes `start..end` and their token count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub start: usize,
+    pub end: usize,
+    pub tokens: usize,
+}
+
+/// Split `text` into chunks of at most `max_tokens` tokens, each starting
+/// about `overlap` tokens before the end of the previous one. Chunks cover
+/// the whole text in order; only a `max_tokens` below the tokens the model
+/// adds around every input can leave one over the limit.
+pub fn split_text(
+    tok: &dyn Tokenizer,
+    text: &str,
+    max_tokens: usize,
+    overlap: usize,
+) -> Vec<Chunk> {
+    let max_tokens = max_tokens.max(1);
+    // Token ranges in the text; tokens the model adds have none.
+    let spans: Vec<(usize, usize)> = tok
+        .encode_with_offsets(text)
+        .into_iter()
+        .filter(|t| t.start < t.end)
+        .map(|t| (t.start, t.end))
+        .collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let first = spans.partition_point(|&(_, end)| end <= start);
+        let mut budget = max_tokens;
+        let (end, tokens) = loop {
+            let hard = match spans.get(first + budget - 1) {
+                Some(&(_, end)) if first + budget < spans.len() => char_floor(text, end, start),
+                _ => text.len(),
+            };
+            let end = if hard == text.len() {
+                hard
+            } else {
+                break_before(text, start, hard)
+            };
+            let tokens = tok.count_tokens(&text[start..end]);
+            if tokens <= max_tokens || budget == 1 {
+                break (end, tokens);
+            }
+            // Counted alone, the text took more tokens than its offsets
+            // suggested (added tokens, merges across the cut): shrink.
+            budget -= (tokens - max_tokens).min(budget - 1);
+        };
+        chunks.push(Chunk { start, end, tokens });
+        if end == text.len() {
+            break;
+        }
+        let next = match spans
+            .partition_point(|&(_, e)| e <= end)
+            .checked_sub(overlap)
+        {
+            Some(i) if overlap > 0 => char_floor(text, spans[i].0, start),
+            _ => end,
+        };
+        start = if next > start { next } else { end };
+    }
+    chunks
+}
+
+/// Where to end a chunk that may run from `start` to at most `hard`: just
+/// after the last paragraph break, line break, sentence end or space in the
+/// second half of the window, or `hard` itself.
+fn break_before(text: &str, start: usize, hard: usize) -> usize {
+    let mut lower = start + (hard - start) / 2;
+    while !text.is_char_boundary(lower) {
+        lower += 1;
+    }
+    let window = &text[lower..hard];
+    if let Some(i) = window.rfind("\n\n") {
+        return lower + i + 2;
+    }
+    if let Some(i) = window.rfind('\n') {
+        return lower + i + 1;
+    }
+    let mut prev = None;
+    let (mut sentence, mut space) = (None, None);
+    for (i, c) in window.char_indices() {
+        if c.is_whitespace() {
+            let after = lower + i + c.len_utf8();
+            if matches!(prev, Some('.' | '!' | '?')) {
+                sentence = Some(after);
+            }
+            space = Some(after);
+        }
+        prev = Some(c);
+    }
+    sentence
+        .or(space)
+        .filter(|&end| end > start)
+        .unwrap_or(hard)
+}
+
+/// `i` moved back to a char boundary, but not to or before `start` (where
+/// it moves forward instead).
+fn char_floor(text: &str, mut i: usize, start: usize) -> usize {
+    let original = i;
+    while !text.is_char_boundary(i) {
+        i -= 1;
+    }
+    if i <= start {
+        i = original;
+        while !text.is_char_boundary(i) {
+            i += 1;
+        }
+    }
+    i
+}
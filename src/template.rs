@@ -0,0 +1,2454 @@
+//! Chat templates: the Jinja `chat_template` of a Hugging Face
+//! `tokenizer_config.json`, rendered to the prompt string a model sees.
+//!
+//! Implements the subset of Jinja that model templates use (Llama, Qwen,
+//! Mistral, DeepSeek and the like), with the environment settings of
+//! `transformers`' `apply_chat_template`: `trim_blocks`, `lstrip_blocks`,
+//! loop controls, `raise_exception`, and a `tojson` that writes JSON the
+//! way Python's `json.dumps` does. Macros, `namespace()`, `{% set %}`
+//! blocks and the common filters, tests and `str`/`dict` methods are
+//! supported; anything else is an error naming the construct.
+//!
+//! Undefined variables render as nothing and are falsy, as in Jinja's
+//! default mode; so are attributes of undefined values, which Jinja would
+//! reject.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::frozen;
+use crate::Error;
+
+/// A model's chat template and the special-token strings it refers to.
+/// The source is parsed once, when the template is created; a template
+/// that does not parse reports the error on every render.
+#[derive(Debug, Clone)]
+pub struct ChatTemplate {
+    source: String,
+    bos_token: String,
+    eos_token: String,
+    nodes: Arc<Result<Vec<Node>, String>>,
+}
+
+impl PartialEq for ChatTemplate {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.source, &self.bos_token, &self.eos_token)
+            == (&other.source, &other.bos_token, &other.eos_token)
+    }
+}
+
+impl Eq for ChatTemplate {}
+
+impl ChatTemplate {
+    pub fn new(source: String, bos_token: String, eos_token: String) -> Self {
+        let nodes = Parser::new(&source).and_then(|mut p| p.parse_template());
+        ChatTemplate {
+            source,
+            bos_token,
+            eos_token,
+            nodes: Arc::new(nodes),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn bos_token(&self) -> &str {
+        &self.bos_token
+    }
+
+    pub fn eos_token(&self) -> &str {
+        &self.eos_token
+    }
+
+    /// Render `messages` (HF-style dicts: `role`, `content`, `tool_calls`,
+    /// ...) to the prompt string. `tools` is passed to the template as is;
+    /// with `add_generation_prompt` the template appends the header that
+    /// starts the assistant's reply.
+    pub fn render(
+        &self,
+        messages: &[serde_json::Value],
+        tools: Option<&serde_json::Value>,
+        add_generation_prompt: bool,
+    ) -> Result<String, Error> {
+        let nodes = self
+            .nodes
+            .as_ref()
+            .as_ref()
+            .map_err(|e| Error::ChatTemplate(e.clone()))?;
+        let mut globals = HashMap::new();
+        globals.insert(
+            "messages".to_string(),
+            Value::List(Rc::new(messages.iter().map(Value::from_json).collect())),
+        );
+        globals.insert(
+            "tools".to_string(),
+            tools.map_or(Value::Undefined, Value::from_json),
+        );
+        globals.insert(
+            "add_generation_prompt".to_string(),
+            Value::Bool(add_generation_prompt),
+        );
+        globals.insert(
+            "bos_token".to_string(),
+            Value::from(self.bos_token.as_str()),
+        );
+        globals.insert(
+            "eos_token".to_string(),
+            Value::from(self.eos_token.as_str()),
+        );
+        let mut renderer = Renderer {
+            scopes: vec![globals],
+            macros: HashMap::new(),
+            out: String::new(),
+        };
+        renderer.render_nodes(nodes).map_err(Error::ChatTemplate)?;
+        Ok(renderer.out)
+    }
+}
+
+/// Chat template section layout: the BOS token, EOS token and template
+/// source as length-prefixed strings.
+pub(crate) fn parse_section(section: &[u8]) -> Result<ChatTemplate, String> {
+    let (bos_token, bos_len) = frozen::get_str(section, 0)?;
+    let (eos_token, eos_len) = frozen::get_str(section, bos_len)?;
+    let (source, source_len) = frozen::get_str(section, bos_len + eos_len)?;
+    let len = bos_len + eos_len + source_len;
+    if len != section.len() {
+        return Err(format!("{} trailing bytes", section.len() - len));
+    }
+    Ok(ChatTemplate::new(source, bos_token, eos_token))
+}
+
+/// One-line summary of a chat template section, for `inspect`.
+pub(crate) fn describe_section(section: &[u8]) -> Result<String, String> {
+    let template = parse_section(section)?;
+    Ok(format!(
+        "{} bytes, bos {:?}, eos {:?}",
+        template.source.len(),
+        template.bos_token,
+        template.eos_token
+    ))
+}
+
+// ---------------------------------------------------------------------------
+// Values
+
+/// Dict entries in insertion order.
+type Entries = Vec<(Rc<str>, Value)>;
+/// Keyword arguments of a call.
+type Kwargs = Vec<(String, Value)>;
+
+#[derive(Debug, Clone)]
+enum Value {
+    Undefined,
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    List(Rc<Vec<Value>>),
+    /// The `(key, value)` pairs of `items()`, printed as Python prints them.
+    Tuple(Rc<Vec<Value>>),
+    /// Keys in insertion order, like a Python dict.
+    Map(Rc<Entries>),
+    /// `namespace()`: the one mutable value, so `set ns.x` can escape loops.
+    Namespace(Rc<RefCell<Entries>>),
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl Value {
+    fn from_json(v: &serde_json::Value) -> Value {
+        match v {
+            serde_json::Value::Null => Value::None,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::from(s.as_str()),
+            serde_json::Value::Array(a) => {
+                Value::List(Rc::new(a.iter().map(Value::from_json).collect()))
+            }
+            serde_json::Value::Object(o) => Value::Map(Rc::new(
+                o.iter()
+                    .map(|(k, v)| (k.as_str().into(), Value::from_json(v)))
+                    .collect(),
+            )),
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Undefined | Value::None => false,
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(l) | Value::Tuple(l) => !l.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+            Value::Namespace(_) => true,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Undefined => "undefined",
+            Value::None => "none",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Map(_) => "dict",
+            Value::Namespace(_) => "namespace",
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            Value::Bool(b) => Some(*b as i64 as f64),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Attribute or key lookup; undefined when missing.
+    fn get(&self, key: &str) -> Value {
+        match self {
+            Value::Map(m) => m
+                .iter()
+                .find(|(k, _)| &**k == key)
+                .map_or(Value::Undefined, |(_, v)| v.clone()),
+            Value::Namespace(ns) => ns
+                .borrow()
+                .iter()
+                .find(|(k, _)| &**k == key)
+                .map_or(Value::Undefined, |(_, v)| v.clone()),
+            _ => Value::Undefined,
+        }
+    }
+
+    /// `self[index]`, with Python's negative indices.
+    fn index(&self, index: &Value) -> Value {
+        let at = |len: usize| {
+            let i = match index {
+                Value::Int(i) => *i,
+                Value::Bool(b) => *b as i64,
+                _ => return None,
+            };
+            let i = if i < 0 { i + len as i64 } else { i };
+            (0..len as i64).contains(&i).then_some(i as usize)
+        };
+        match self {
+            Value::List(l) | Value::Tuple(l) => {
+                at(l.len()).map_or(Value::Undefined, |i| l[i].clone())
+            }
+            Value::Str(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                at(chars.len()).map_or(Value::Undefined, |i| Value::from(chars[i].to_string()))
+            }
+            Value::Map(_) | Value::Namespace(_) => match index {
+                Value::Str(k) => self.get(k),
+                _ => Value::Undefined,
+            },
+            _ => Value::Undefined,
+        }
+    }
+
+    /// Items iterated by `for`: list elements, dict keys, string characters.
+    fn iter_items(&self) -> Result<Vec<Value>, String> {
+        match self {
+            Value::List(l) | Value::Tuple(l) => Ok(l.to_vec()),
+            Value::Map(m) => Ok(m.iter().map(|(k, _)| Value::Str(k.clone())).collect()),
+            Value::Str(s) => Ok(s.chars().map(|c| Value::from(c.to_string())).collect()),
+            Value::Undefined | Value::None => Ok(Vec::new()),
+            other => Err(format!("cannot iterate over {}", other.type_name())),
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        match self {
+            Value::List(l) | Value::Tuple(l) => Some(l.len()),
+            Value::Map(m) => Some(m.len()),
+            Value::Str(s) => Some(s.chars().count()),
+            _ => None,
+        }
+    }
+
+    /// `str(value)`, as Jinja prints it.
+    fn to_text(&self) -> String {
+        match self {
+            Value::Undefined => String::new(),
+            Value::Str(s) => s.to_string(),
+            other => other.repr(),
+        }
+    }
+
+    /// Python's `repr`, used inside printed lists and dicts.
+    fn repr(&self) -> String {
+        match self {
+            Value::Undefined => String::new(),
+            Value::None => "None".to_string(),
+            Value::Bool(true) => "True".to_string(),
+            Value::Bool(false) => "False".to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) => python_float(*f),
+            Value::Str(s) => {
+                let quote = if s.contains('\'') && !s.contains('"') {
+                    '"'
+                } else {
+                    '\''
+                };
+                let mut out = String::from(quote);
+                for c in s.chars() {
+                    match c {
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        c if c == quote => {
+                            out.push('\\');
+                            out.push(c);
+                        }
+                        c => out.push(c),
+                    }
+                }
+                out.push(quote);
+                out
+            }
+            Value::List(l) => format!(
+                "[{}]",
+                l.iter().map(Value::repr).collect::<Vec<_>>().join(", ")
+            ),
+            Value::Tuple(t) if t.len() == 1 => format!("({},)", t[0].repr()),
+            Value::Tuple(t) => format!(
+                "({})",
+                t.iter().map(Value::repr).collect::<Vec<_>>().join(", ")
+            ),
+            Value::Map(m) => format!(
+                "{{{}}}",
+                m.iter()
+                    .map(|(k, v)| format!("{}: {}", Value::Str(k.clone()).repr(), v.repr()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Namespace(_) => "<Namespace>".to_string(),
+        }
+    }
+
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Undefined, Value::Undefined) | (Value::None, Value::None) => true,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(k, v)| other.get(k).equals(v) && b.iter().any(|(bk, _)| bk == k))
+            }
+            (Value::Namespace(a), Value::Namespace(b)) => Rc::ptr_eq(a, b),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(x), Some(y)) => x == y,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Python's `repr` of a float: always with a fractional part or exponent.
+fn python_float(f: f64) -> String {
+    if f.is_nan() {
+        "nan".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else if f.fract() == 0.0 && f.abs() < 1e16 {
+        format!("{:.1}", f)
+    } else {
+        format!("{}", f)
+    }
+}
+
+/// `value` as Python's `json.dumps(value, ensure_ascii=False)` writes it,
+/// with `", "` and `": "` separators.
+pub(crate) fn python_json(value: &serde_json::Value) -> String {
+    let mut out = String::new();
+    to_json(&Value::from_json(value), None, &mut out, 0);
+    out
+}
+
+/// `json.dumps(value, ensure_ascii=False, indent=indent)`.
+fn to_json(value: &Value, indent: Option<usize>, out: &mut String, depth: usize) {
+    let newline = |out: &mut String, depth: usize| {
+        if let Some(n) = indent {
+            out.push('\n');
+            out.extend(std::iter::repeat_n(' ', n * depth));
+        }
+    };
+    let item_sep = if indent.is_some() { "," } else { ", " };
+    match value {
+        Value::Undefined | Value::None => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Int(i) => out.push_str(&i.to_string()),
+        Value::Float(f) if f.is_finite() => out.push_str(&python_float(*f)),
+        Value::Float(f) => out.push_str(if f.is_nan() {
+            "NaN"
+        } else if *f > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }),
+        Value::Str(s) => json_string(s, out),
+        Value::List(l) | Value::Tuple(l) if l.is_empty() => out.push_str("[]"),
+        Value::List(l) | Value::Tuple(l) => {
+            out.push('[');
+            for (i, v) in l.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(item_sep);
+                }
+                newline(out, depth + 1);
+                to_json(v, indent, out, depth + 1);
+            }
+            newline(out, depth);
+            out.push(']');
+        }
+        Value::Map(m) if m.is_empty() => out.push_str("{}"),
+        Value::Map(m) => {
+            out.push('{');
+            for (i, (k, v)) in m.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(item_sep);
+                }
+                newline(out, depth + 1);
+                json_string(k, out);
+                out.push_str(": ");
+                to_json(v, indent, out, depth + 1);
+            }
+            newline(out, depth);
+            out.push('}');
+        }
+        Value::Namespace(ns) => to_json(
+            &Value::Map(Rc::new(ns.borrow().clone())),
+            indent,
+            out,
+            depth,
+        ),
+    }
+}
+
+fn json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ---------------------------------------------------------------------------
+// Lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Text(String),
+    /// `{{`
+    VarStart,
+    /// `{%`
+    BlockStart,
+    /// `}}` or `%}`
+    End,
+    Name(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Op(&'static str),
+}
+
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "//", "**", "<", ">", "+", "-", "*", "/", "%", "~", "|", ".", ",", ":",
+    "(", ")", "[", "]", "{", "}", "=",
+];
+
+fn lex(source: &str) -> Result<Vec<Tok>, String> {
+    let mut toks = Vec::new();
+    // Jinja drops a single trailing newline of the template.
+    let mut rest = source.strip_suffix('\n').unwrap_or(source);
+    // Strip leading whitespace of the next text (`-%}`), or only its first
+    // newline (trim_blocks after a block tag).
+    let mut strip_next = false;
+    let mut trim_newline = false;
+    let mut first = true;
+    loop {
+        let next = ["{{", "{%", "{#"].iter().filter_map(|m| rest.find(m)).min();
+        let (mut text, at_line_start) = leading_text(
+            &rest[..next.unwrap_or(rest.len())],
+            strip_next,
+            trim_newline,
+            first,
+        );
+        first = false;
+        let Some(at) = next else {
+            if !text.is_empty() {
+                toks.push(Tok::Text(text));
+            }
+            break;
+        };
+        let tag = &rest[at..at + 2];
+        rest = &rest[at + 2..];
+        let strip_before = rest.starts_with('-');
+        let keep_before = rest.starts_with('+');
+        if strip_before || keep_before {
+            rest = &rest[1..];
+        }
+        if strip_before {
+            text.truncate(text.trim_end().len());
+        } else if tag != "{{" && !keep_before {
+            // lstrip_blocks: drop the indentation before a block or comment
+            // tag that starts its line.
+            let line_start = match text.rfind('\n') {
+                Some(i) => Some(i + 1),
+                None => at_line_start.then_some(0),
+            };
+            if let Some(line_start) = line_start {
+                if text[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+                    text.truncate(line_start);
+                }
+            }
+        }
+        if !text.is_empty() {
+            toks.push(Tok::Text(text));
+        }
+        let strip_after;
+        if tag == "{#" {
+            let end = rest.find("#}").ok_or("unclosed comment")?;
+            strip_after = rest[..end].ends_with('-');
+            rest = &rest[end + 2..];
+        } else {
+            toks.push(if tag == "{{" {
+                Tok::VarStart
+            } else {
+                Tok::BlockStart
+            });
+            let close = if tag == "{{" { "}}" } else { "%}" };
+            let (consumed, strip) = lex_code(rest, close, &mut toks)?;
+            strip_after = strip;
+            rest = &rest[consumed..];
+            toks.push(Tok::End);
+        }
+        strip_next = strip_after;
+        trim_newline = !strip_after && tag != "{{";
+    }
+    Ok(toks)
+}
+
+/// The text before the next tag, after the previous tag's whitespace
+/// control, and whether it starts a line of the source.
+fn leading_text(
+    chunk: &str,
+    strip: bool,
+    trim_newline: bool,
+    at_source_start: bool,
+) -> (String, bool) {
+    let (text, removed) = if strip {
+        let text = chunk.trim_start();
+        (text, &chunk[..chunk.len() - text.len()])
+    } else if trim_newline {
+        let text = chunk
+            .strip_prefix("\r\n")
+            .or_else(|| chunk.strip_prefix('\n'))
+            .unwrap_or(chunk);
+        (text, &chunk[..chunk.len() - text.len()])
+    } else {
+        (chunk, "")
+    };
+    (text.to_string(), at_source_start || removed.ends_with('\n'))
+}
+
+/// Tokenize the inside of a tag up to its `close` marker. Returns the bytes
+/// consumed, including the marker, and whether it was `-`-prefixed.
+fn lex_code(src: &str, close: &str, toks: &mut Vec<Tok>) -> Result<(usize, bool), String> {
+    let bytes = src.as_bytes();
+    let mut i = 0;
+    let mut depth = 0usize;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if depth == 0 {
+            if src[i..].starts_with(close) {
+                return Ok((i + 2, false));
+            }
+            if c == b'-' && src[i + 1..].starts_with(close) {
+                return Ok((i + 3, true));
+            }
+        }
+        if c == b'"' || c == b'\'' {
+            let (s, len) = lex_string(&src[i..])?;
+            toks.push(Tok::Str(s));
+            i += len;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let mut is_float = false;
+            if i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1].is_ascii_digit() {
+                is_float = true;
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let sign = usize::from(matches!(bytes.get(i + 1), Some(b'+' | b'-')));
+                if bytes.get(i + 1 + sign).is_some_and(u8::is_ascii_digit) {
+                    is_float = true;
+                    i += 1 + sign;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let digits = src[start..i].replace('_', "");
+            toks.push(if is_float {
+                Tok::Float(
+                    digits
+                        .parse()
+                        .map_err(|_| format!("bad number {:?}", digits))?,
+                )
+            } else {
+                Tok::Int(
+                    digits
+                        .parse()
+                        .map_err(|_| format!("bad number {:?}", digits))?,
+                )
+            });
+        } else if c == b'_' || c.is_ascii_alphabetic() {
+            let start = i;
+            while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                i += 1;
+            }
+            toks.push(Tok::Name(src[start..i].to_string()));
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| src[i..].starts_with(**op))
+                .ok_or_else(|| {
+                    format!(
+                        "unexpected character {:?}",
+                        src[i..].chars().next().unwrap_or(' ')
+                    )
+                })?;
+            match *op {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            toks.push(Tok::Op(op));
+            i += op.len();
+        }
+    }
+    Err(format!("unclosed tag, expected {:?}", close))
+}
+
+/// A quoted string literal with Python escapes; returns it and its length.
+fn lex_string(src: &str) -> Result<(String, usize), String> {
+    let quote = src.chars().next().unwrap_or('"');
+    let mut out = String::new();
+    let mut chars = src.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((out, i + 1)),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some(c) => {
+                    if !matches!(c, '\\' | '\'' | '"') {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Output(Expr),
+    If(Vec<(Expr, Vec<Node>)>, Vec<Node>),
+    For {
+        targets: Vec<String>,
+        iter: Expr,
+        filter: Option<Expr>,
+        body: Vec<Node>,
+        else_body: Vec<Node>,
+    },
+    Set(Target, Expr),
+    SetBlock(String, Vec<Node>),
+    Macro(Arc<MacroDef>),
+    Break,
+    Continue,
+}
+
+#[derive(Debug)]
+enum Target {
+    Names(Vec<String>),
+    Attr(String, String),
+}
+
+#[derive(Debug)]
+struct MacroDef {
+    name: String,
+    params: Vec<(String, Option<Expr>)>,
+    body: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Const(Literal),
+    Var(String),
+    List(Vec<Expr>),
+    Tuple(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    Attr(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, [Option<Box<Expr>>; 3]),
+    Call(Box<Expr>, Args),
+    Filter(Box<Expr>, String, Args),
+    Test(Box<Expr>, String, Args, bool),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+}
+
+/// A literal of the source. Kept apart from [`Value`], which is not `Send`,
+/// so parsed templates can live in a tokenizer.
+#[derive(Debug)]
+enum Literal {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Literal {
+    fn value(&self) -> Value {
+        match self {
+            Literal::None => Value::None,
+            Literal::Bool(b) => Value::Bool(*b),
+            Literal::Int(i) => Value::Int(*i),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::Str(s) => Value::from(s.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    positional: Vec<Expr>,
+    keyword: Vec<(String, Expr)>,
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, String> {
+        Ok(Parser {
+            toks: lex(source)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Tok, String> {
+        let tok = self
+            .toks
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of template")?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn at_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Op(o)) if *o == op)
+    }
+
+    fn at_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Name(n)) if n == name)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        let found = self.at_op(op);
+        self.pos += found as usize;
+        found
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        let found = self.at_name(name);
+        self.pos += found as usize;
+        found
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), String> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(format!("expected {:?}, found {:?}", op, self.peek()))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), String> {
+        match self.next()? {
+            Tok::End => Ok(()),
+            other => Err(format!("expected end of tag, found {:?}", other)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Tok::Name(n) => Ok(n),
+            other => Err(format!("expected a name, found {:?}", other)),
+        }
+    }
+
+    fn parse_template(&mut self) -> Result<Vec<Node>, String> {
+        let (nodes, end) = self.parse_body(&[])?;
+        match end {
+            None => Ok(nodes),
+            Some(tag) => Err(format!("unexpected {{% {} %}}", tag)),
+        }
+    }
+
+    /// Parse nodes up to one of the `until` block tags, returning it
+    /// (consumed, with its tag still open) or `None` at the end of input.
+    fn parse_body(&mut self, until: &[&str]) -> Result<(Vec<Node>, Option<String>), String> {
+        let mut nodes = Vec::new();
+        while let Some(tok) = self.peek().cloned() {
+            self.pos += 1;
+            match tok {
+                Tok::Text(t) => nodes.push(Node::Text(t)),
+                Tok::VarStart => {
+                    nodes.push(Node::Output(self.parse_expr()?));
+                    self.expect_end()?;
+                }
+                Tok::BlockStart => {
+                    let tag = self.name()?;
+                    if until.contains(&tag.as_str()) {
+                        return Ok((nodes, Some(tag)));
+                    }
+                    if let Some(node) = self.parse_block(&tag)? {
+                        nodes.push(node);
+                    }
+                }
+                other => return Err(format!("unexpected {:?}", other)),
+            }
+        }
+        if until.is_empty() {
+            Ok((nodes, None))
+        } else {
+            Err(format!(
+                "missing {{% {} %}}",
+                until.last().unwrap_or(&"end")
+            ))
+        }
+    }
+
+    fn parse_block(&mut self, tag: &str) -> Result<Option<Node>, String> {
+        let node = match tag {
+            "if" => {
+                let mut branches = Vec::new();
+                let mut cond = self.parse_expr()?;
+                self.expect_end()?;
+                loop {
+                    let (body, end) = self.parse_body(&["elif", "else", "endif"])?;
+                    branches.push((cond, body));
+                    match end.as_deref() {
+                        Some("elif") => {
+                            cond = self.parse_expr()?;
+                            self.expect_end()?;
+                        }
+                        Some("else") => {
+                            self.expect_end()?;
+                            let (else_body, _) = self.parse_body(&["endif"])?;
+                            self.expect_end()?;
+                            break Node::If(branches, else_body);
+                        }
+                        _ => {
+                            self.expect_end()?;
+                            break Node::If(branches, Vec::new());
+                        }
+                    }
+                }
+            }
+            "for" => {
+                let mut targets = vec![self.name()?];
+                while self.eat_op(",") {
+                    targets.push(self.name()?);
+                }
+                if !self.eat_name("in") {
+                    return Err("expected 'in' in for loop".to_string());
+                }
+                let iter = self.parse_or()?;
+                let filter = if self.eat_name("if") {
+                    Some(self.parse_expr()?)
+                } else {
+                    None
+                };
+                self.expect_end()?;
+                let (body, end) = self.parse_body(&["else", "endfor"])?;
+                self.expect_end()?;
+                let else_body = if end.as_deref() == Some("else") {
+                    let (else_body, _) = self.parse_body(&["endfor"])?;
+                    self.expect_end()?;
+                    else_body
+                } else {
+                    Vec::new()
+                };
+                Node::For {
+                    targets,
+                    iter,
+                    filter,
+                    body,
+                    else_body,
+                }
+            }
+            "set" => {
+                let first = self.name()?;
+                let target = if self.eat_op(".") {
+                    Target::Attr(first, self.name()?)
+                } else {
+                    let mut names = vec![first];
+                    while self.eat_op(",") {
+                        names.push(self.name()?);
+                    }
+                    Target::Names(names)
+                };
+                if self.eat_op("=") {
+                    let value = self.parse_tuple()?;
+                    self.expect_end()?;
+                    Node::Set(target, value)
+                } else {
+                    let Target::Names(names) = target else {
+                        return Err("block set needs a plain name".to_string());
+                    };
+                    self.expect_end()?;
+                    let (body, _) = self.parse_body(&["endset"])?;
+                    self.expect_end()?;
+                    Node::SetBlock(names.into_iter().next().unwrap_or_default(), body)
+                }
+            }
+            "macro" => {
+                let name = self.name()?;
+                self.expect_op("(")?;
+                let mut params = Vec::new();
+                while !self.eat_op(")") {
+                    let param = self.name()?;
+                    let default = if self.eat_op("=") {
+                        Some(self.parse_expr()?)
+                    } else {
+                        None
+                    };
+                    params.push((param, default));
+                    if !self.eat_op(",") {
+                        self.expect_op(")")?;
+                        break;
+                    }
+                }
+                self.expect_end()?;
+                let (body, _) = self.parse_body(&["endmacro"])?;
+                self.expect_end()?;
+                Node::Macro(Arc::new(MacroDef { name, params, body }))
+            }
+            "break" | "continue" => {
+                self.expect_end()?;
+                if tag == "break" {
+                    Node::Break
+                } else {
+                    Node::Continue
+                }
+            }
+            // transformers' AssistantTracker marks assistant text for masks;
+            // the tags render nothing.
+            "generation" | "endgeneration" => {
+                self.expect_end()?;
+                return Ok(None);
+            }
+            other => return Err(format!("unsupported tag {{% {} %}}", other)),
+        };
+        Ok(Some(node))
+    }
+
+    /// An expression, or a tuple (as a list) of comma-separated ones.
+    fn parse_tuple(&mut self) -> Result<Expr, String> {
+        let first = self.parse_expr()?;
+        if !self.at_op(",") {
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.eat_op(",") {
+            items.push(self.parse_expr()?);
+        }
+        Ok(Expr::Tuple(items))
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        let value = self.parse_or()?;
+        if !self.eat_name("if") {
+            return Ok(value);
+        }
+        let cond = self.parse_or()?;
+        let otherwise = if self.eat_name("else") {
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+        Ok(Expr::Cond(Box::new(cond), Box::new(value), otherwise))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat_name("or") {
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.eat_name("and") {
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat_name("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_compare()
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_math1()?;
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Op(op @ ("==" | "!=" | "<" | ">" | "<=" | ">="))) => *op,
+                Some(Tok::Name(n)) if n == "in" => "in",
+                Some(Tok::Name(n))
+                    if n == "not"
+                        && matches!(self.toks.get(self.pos + 1), Some(Tok::Name(m)) if m == "in") =>
+                {
+                    self.pos += 1;
+                    "not in"
+                }
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_math1()?));
+        }
+    }
+
+    fn parse_math1(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_concat()?;
+        while let Some(Tok::Op(op @ ("+" | "-"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_concat()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_math2()?;
+        while self.eat_op("~") {
+            left = Expr::Binary("~", Box::new(left), Box::new(self.parse_math2()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_math2(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        while let Some(Tok::Op(op @ ("*" | "/" | "//" | "%"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_unary()?));
+        }
+        Ok(left)
+    }
+
+    /// A signed operand. Filters apply to the signed value: `-1 | abs` is 1.
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let expr = self.parse_signed()?;
+        self.parse_filters(expr)
+    }
+
+    fn parse_signed(&mut self) -> Result<Expr, String> {
+        if self.eat_op("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_signed()?)));
+        }
+        if self.eat_op("+") {
+            return self.parse_signed();
+        }
+        let primary = self.parse_primary()?;
+        self.parse_postfix(primary)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        Ok(match self.next()? {
+            Tok::Str(mut s) => {
+                // Adjacent literals concatenate, as in Python.
+                while let Some(Tok::Str(more)) = self.peek() {
+                    s.push_str(more);
+                    self.pos += 1;
+                }
+                Expr::Const(Literal::Str(s))
+            }
+            Tok::Int(i) => Expr::Const(Literal::Int(i)),
+            Tok::Float(f) => Expr::Const(Literal::Float(f)),
+            Tok::Name(n) => match n.as_str() {
+                "true" | "True" => Expr::Const(Literal::Bool(true)),
+                "false" | "False" => Expr::Const(Literal::Bool(false)),
+                "none" | "None" => Expr::Const(Literal::None),
+                _ => Expr::Var(n),
+            },
+            Tok::Op("(") => {
+                let expr = self.parse_tuple()?;
+                self.expect_op(")")?;
+                expr
+            }
+            Tok::Op("[") => {
+                let mut items = Vec::new();
+                while !self.eat_op("]") {
+                    items.push(self.parse_expr()?);
+                    if !self.eat_op(",") {
+                        self.expect_op("]")?;
+                        break;
+                    }
+                }
+                Expr::List(items)
+            }
+            Tok::Op("{") => {
+                let mut items = Vec::new();
+                while !self.eat_op("}") {
+                    let key = self.parse_expr()?;
+                    self.expect_op(":")?;
+                    items.push((key, self.parse_expr()?));
+                    if !self.eat_op(",") {
+                        self.expect_op("}")?;
+                        break;
+                    }
+                }
+                Expr::Dict(items)
+            }
+            other => return Err(format!("unexpected {:?} in expression", other)),
+        })
+    }
+
+    fn parse_postfix(&mut self, mut expr: Expr) -> Result<Expr, String> {
+        loop {
+            if self.eat_op(".") {
+                let attr = match self.next()? {
+                    Tok::Name(n) => n,
+                    Tok::Int(i) => i.to_string(),
+                    other => return Err(format!("expected an attribute, found {:?}", other)),
+                };
+                expr = Expr::Attr(Box::new(expr), attr);
+            } else if self.eat_op("[") {
+                expr = self.parse_subscript(expr)?;
+            } else if self.eat_op("(") {
+                expr = Expr::Call(Box::new(expr), self.parse_args()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_subscript(&mut self, expr: Expr) -> Result<Expr, String> {
+        let mut parts: [Option<Box<Expr>>; 3] = [None, None, None];
+        let mut part = 0;
+        loop {
+            if self.eat_op("]") {
+                break;
+            }
+            if self.eat_op(":") {
+                part += 1;
+                if part > 2 {
+                    return Err("too many ':' in subscript".to_string());
+                }
+                continue;
+            }
+            parts[part] = Some(Box::new(self.parse_expr()?));
+        }
+        if part == 0 {
+            let index = parts[0].take().ok_or("empty subscript")?;
+            Ok(Expr::Index(Box::new(expr), index))
+        } else {
+            Ok(Expr::Slice(Box::new(expr), parts))
+        }
+    }
+
+    /// Call arguments after the opening parenthesis.
+    fn parse_args(&mut self) -> Result<Args, String> {
+        let mut args = Args::default();
+        while !self.eat_op(")") {
+            let is_keyword = matches!(self.peek(), Some(Tok::Name(_)))
+                && matches!(self.toks.get(self.pos + 1), Some(Tok::Op("=")));
+            if is_keyword {
+                let name = self.name()?;
+                self.pos += 1;
+                args.keyword.push((name, self.parse_expr()?));
+            } else {
+                args.positional.push(self.parse_expr()?);
+            }
+            if !self.eat_op(",") {
+                self.expect_op(")")?;
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    /// `| filter(args)` and `is [not] test args` after an operand.
+    fn parse_filters(&mut self, mut expr: Expr) -> Result<Expr, String> {
+        loop {
+            if self.eat_op("|") {
+                let name = self.name()?;
+                let args = if self.eat_op("(") {
+                    self.parse_args()?
+                } else {
+                    Args::default()
+                };
+                expr = Expr::Filter(Box::new(expr), name, args);
+            } else if self.eat_name("is") {
+                let negated = self.eat_name("not");
+                let name = self.name()?;
+                let args = if self.eat_op("(") {
+                    self.parse_args()?
+                } else if self.starts_test_argument() {
+                    let arg = self.parse_primary()?;
+                    Args {
+                        positional: vec![self.parse_postfix(arg)?],
+                        keyword: Vec::new(),
+                    }
+                } else {
+                    Args::default()
+                };
+                expr = Expr::Test(Box::new(expr), name, args, negated);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Whether a test is followed by a bare argument, as in `is equalto 1`.
+    fn starts_test_argument(&self) -> bool {
+        match self.peek() {
+            Some(Tok::Name(n)) => {
+                !matches!(n.as_str(), "else" | "or" | "and" | "if" | "in" | "not")
+            }
+            Some(Tok::Str(_) | Tok::Int(_) | Tok::Float(_) | Tok::Op("[" | "{")) => true,
+            _ => false,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Renderer
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+struct Renderer {
+    scopes: Vec<HashMap<String, Value>>,
+    macros: HashMap<String, Arc<MacroDef>>,
+    out: String,
+}
+
+impl Renderer {
+    fn lookup(&self, name: &str) -> Value {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(name).cloned())
+            .unwrap_or(Value::Undefined)
+    }
+
+    fn assign(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node]) -> Result<Flow, String> {
+        for node in nodes {
+            match self.render_node(node)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn render_node(&mut self, node: &Node) -> Result<Flow, String> {
+        match node {
+            Node::Text(t) => self.out.push_str(t),
+            Node::Output(expr) => {
+                let value = self.eval(expr)?;
+                self.out.push_str(&value.to_text());
+            }
+            Node::If(branches, else_body) => {
+                for (cond, body) in branches {
+                    if self.eval(cond)?.is_true() {
+                        return self.render_nodes(body);
+                    }
+                }
+                return self.render_nodes(else_body);
+            }
+            Node::For {
+                targets,
+                iter,
+                filter,
+                body,
+                else_body,
+            } => {
+                return self.render_for(targets, iter, filter.as_ref(), body, else_body);
+            }
+            Node::Set(Target::Names(names), expr) => {
+                let value = self.eval(expr)?;
+                if let [name] = names.as_slice() {
+                    self.assign(name, value);
+                } else {
+                    let items = value.iter_items()?;
+                    if items.len() != names.len() {
+                        return Err(format!(
+                            "cannot unpack {} values into {}",
+                            items.len(),
+                            names.len()
+                        ));
+                    }
+                    for (name, item) in names.iter().zip(items) {
+                        self.assign(name, item);
+                    }
+                }
+            }
+            Node::Set(Target::Attr(name, attr), expr) => {
+                let value = self.eval(expr)?;
+                match self.lookup(name) {
+                    Value::Namespace(ns) => {
+                        let mut ns = ns.borrow_mut();
+                        match ns.iter_mut().find(|(k, _)| &**k == attr) {
+                            Some((_, slot)) => *slot = value,
+                            None => ns.push((attr.as_str().into(), value)),
+                        }
+                    }
+                    other => {
+                        return Err(format!("cannot set an attribute on {}", other.type_name()))
+                    }
+                }
+            }
+            Node::SetBlock(name, body) => {
+                let outer = std::mem::take(&mut self.out);
+                let flow = self.render_nodes(body);
+                let captured = std::mem::replace(&mut self.out, outer);
+                flow?;
+                self.assign(name, Value::from(captured));
+            }
+            Node::Macro(def) => {
+                self.macros.insert(def.name.clone(), def.clone());
+            }
+            Node::Break => return Ok(Flow::Break),
+            Node::Continue => return Ok(Flow::Continue),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn render_for(
+        &mut self,
+        targets: &[String],
+        iter: &Expr,
+        filter: Option<&Expr>,
+        body: &[Node],
+        else_body: &[Node],
+    ) -> Result<Flow, String> {
+        let mut items = Vec::new();
+        for item in self.eval(iter)?.iter_items()? {
+            self.scopes.push(HashMap::new());
+            self.bind_targets(targets, &item)?;
+            let keep = match filter {
+                Some(cond) => self.eval(cond).map(|v| v.is_true()),
+                None => Ok(true),
+            };
+            self.scopes.pop();
+            if keep? {
+                items.push(item);
+            }
+        }
+        if items.is_empty() {
+            return self.render_nodes(else_body);
+        }
+        let len = items.len();
+        for (i, item) in items.iter().enumerate() {
+            let loop_info: Vec<(Rc<str>, Value)> = vec![
+                ("index".into(), Value::Int(i as i64 + 1)),
+                ("index0".into(), Value::Int(i as i64)),
+                ("revindex".into(), Value::Int((len - i) as i64)),
+                ("revindex0".into(), Value::Int((len - i - 1) as i64)),
+                ("first".into(), Value::Bool(i == 0)),
+                ("last".into(), Value::Bool(i == len - 1)),
+                ("length".into(), Value::Int(len as i64)),
+                (
+                    "previtem".into(),
+                    if i > 0 {
+                        items[i - 1].clone()
+                    } else {
+                        Value::Undefined
+                    },
+                ),
+                (
+                    "nextitem".into(),
+                    items.get(i + 1).cloned().unwrap_or(Value::Undefined),
+                ),
+            ];
+            self.scopes.push(HashMap::new());
+            self.assign("loop", Value::Map(Rc::new(loop_info)));
+            let flow = self
+                .bind_targets(targets, item)
+                .and_then(|_| self.render_nodes(body));
+            self.scopes.pop();
+            if let Flow::Break = flow? {
+                break;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn bind_targets(&mut self, targets: &[String], item: &Value) -> Result<(), String> {
+        if let [name] = targets {
+            self.assign(name, item.clone());
+            return Ok(());
+        }
+        let parts = item.iter_items()?;
+        if parts.len() != targets.len() {
+            return Err(format!(
+                "cannot unpack {} values into {}",
+                parts.len(),
+                targets.len()
+            ));
+        }
+        for (name, part) in targets.iter().zip(parts) {
+            self.assign(name, part);
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Const(literal) => literal.value(),
+            Expr::Var(name) => self.lookup(name),
+            Expr::List(items) => Value::List(Rc::new(
+                items
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Tuple(items) => Value::Tuple(Rc::new(
+                items
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Dict(items) => {
+                let mut map: Vec<(Rc<str>, Value)> = Vec::new();
+                for (k, v) in items {
+                    let key: Rc<str> = self.eval(k)?.to_text().into();
+                    let value = self.eval(v)?;
+                    map.retain(|(existing, _)| *existing != key);
+                    map.push((key, value));
+                }
+                Value::Map(Rc::new(map))
+            }
+            Expr::Attr(obj, attr) => self.eval(obj)?.get(attr),
+            Expr::Index(obj, index) => {
+                let obj = self.eval(obj)?;
+                obj.index(&self.eval(index)?)
+            }
+            Expr::Slice(obj, parts) => {
+                let obj = self.eval(obj)?;
+                let mut bounds = [None, None, None];
+                for (bound, part) in bounds.iter_mut().zip(parts) {
+                    if let Some(e) = part {
+                        *bound = match self.eval(e)? {
+                            Value::Int(i) => Some(i),
+                            Value::None | Value::Undefined => None,
+                            other => {
+                                return Err(format!(
+                                    "slice index must be an int, not {}",
+                                    other.type_name()
+                                ))
+                            }
+                        };
+                    }
+                }
+                slice(&obj, bounds)?
+            }
+            Expr::Call(callee, args) => self.eval_call(callee, args)?,
+            Expr::Filter(obj, name, args) => {
+                let value = self.eval(obj)?;
+                let (positional, keyword) = self.eval_args(args)?;
+                apply_filter(name, value, &positional, &keyword)?
+            }
+            Expr::Test(obj, name, args, negated) => {
+                let value = self.eval(obj)?;
+                let (positional, _) = self.eval_args(args)?;
+                Value::Bool(apply_test(name, &value, &positional)? != *negated)
+            }
+            Expr::Neg(e) => match self.eval(e)? {
+                Value::Int(i) => Value::Int(-i),
+                Value::Float(f) => Value::Float(-f),
+                other => return Err(format!("cannot negate {}", other.type_name())),
+            },
+            Expr::Not(e) => Value::Bool(!self.eval(e)?.is_true()),
+            Expr::And(a, b) => {
+                let a = self.eval(a)?;
+                if a.is_true() {
+                    self.eval(b)?
+                } else {
+                    a
+                }
+            }
+            Expr::Or(a, b) => {
+                let a = self.eval(a)?;
+                if a.is_true() {
+                    a
+                } else {
+                    self.eval(b)?
+                }
+            }
+            Expr::Binary(op, a, b) => {
+                let a = self.eval(a)?;
+                let b = self.eval(b)?;
+                binary(op, &a, &b)?
+            }
+            Expr::Cond(cond, then, otherwise) => {
+                if self.eval(cond)?.is_true() {
+                    self.eval(then)?
+                } else if let Some(e) = otherwise {
+                    self.eval(e)?
+                } else {
+                    Value::Undefined
+                }
+            }
+        })
+    }
+
+    fn eval_args(&mut self, args: &Args) -> Result<(Vec<Value>, Kwargs), String> {
+        let positional = args
+            .positional
+            .iter()
+            .map(|e| self.eval(e))
+            .collect::<Result<_, _>>()?;
+        let keyword = args
+            .keyword
+            .iter()
+            .map(|(k, e)| Ok((k.clone(), self.eval(e)?)))
+            .collect::<Result<_, String>>()?;
+        Ok((positional, keyword))
+    }
+
+    fn eval_call(&mut self, callee: &Expr, args: &Args) -> Result<Value, String> {
+        let (positional, keyword) = self.eval_args(args)?;
+        match callee {
+            Expr::Attr(obj, method) => {
+                let obj = self.eval(obj)?;
+                call_method(&obj, method, &positional, &keyword)
+            }
+            Expr::Var(name) => {
+                if let Some(def) = self.macros.get(name).cloned() {
+                    return self.call_macro(&def, positional, keyword);
+                }
+                match name.as_str() {
+                    "raise_exception" => {
+                        Err(positional.first().map(Value::to_text).unwrap_or_default())
+                    }
+                    "namespace" | "dict" => {
+                        let mut map: Vec<(Rc<str>, Value)> = Vec::new();
+                        if let Some(Value::Map(init)) = positional.first() {
+                            map.extend(init.iter().cloned());
+                        }
+                        map.extend(keyword.into_iter().map(|(k, v)| (k.into(), v)));
+                        Ok(if name == "dict" {
+                            Value::Map(Rc::new(map))
+                        } else {
+                            Value::Namespace(Rc::new(RefCell::new(map)))
+                        })
+                    }
+                    "range" => {
+                        let ints: Vec<i64> = positional
+                            .iter()
+                            .map(|v| match v {
+                                Value::Int(i) => Ok(*i),
+                                other => {
+                                    Err(format!("range() needs ints, not {}", other.type_name()))
+                                }
+                            })
+                            .collect::<Result<_, _>>()?;
+                        let (start, stop, step) = match ints.as_slice() {
+                            [stop] => (0, *stop, 1),
+                            [start, stop] => (*start, *stop, 1),
+                            [start, stop, step] if *step != 0 => (*start, *stop, *step),
+                            _ => return Err("bad range() arguments".to_string()),
+                        };
+                        let mut items = Vec::new();
+                        let mut i = start;
+                        while (step > 0 && i < stop) || (step < 0 && i > stop) {
+                            items.push(Value::Int(i));
+                            i += step;
+                        }
+                        Ok(Value::List(Rc::new(items)))
+                    }
+                    other => Err(format!("unknown function {}()", other)),
+                }
+            }
+            _ => Err("unsupported call".to_string()),
+        }
+    }
+
+    fn call_macro(
+        &mut self,
+        def: &MacroDef,
+        positional: Vec<Value>,
+        keyword: Vec<(String, Value)>,
+    ) -> Result<Value, String> {
+        let mut scope = HashMap::new();
+        let mut positional = positional.into_iter();
+        for (name, default) in &def.params {
+            let value = match positional.next() {
+                Some(v) => v,
+                None => match keyword.iter().find(|(k, _)| k == name) {
+                    Some((_, v)) => v.clone(),
+                    None => match default {
+                        Some(e) => self.eval(e)?,
+                        None => Value::Undefined,
+                    },
+                },
+            };
+            scope.insert(name.clone(), value);
+        }
+        let outer = std::mem::take(&mut self.out);
+        self.scopes.push(scope);
+        let flow = self.render_nodes(&def.body);
+        self.scopes.pop();
+        let rendered = std::mem::replace(&mut self.out, outer);
+        flow?;
+        Ok(Value::from(rendered))
+    }
+}
+
+fn slice(obj: &Value, [start, stop, step]: [Option<i64>; 3]) -> Result<Value, String> {
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Err("slice step cannot be zero".to_string());
+    }
+    let indices = |len: usize| -> Vec<usize> {
+        let len = len as i64;
+        let clamp = |i: i64, lo: i64, hi: i64| (if i < 0 { i + len } else { i }).clamp(lo, hi);
+        let mut out = Vec::new();
+        if step > 0 {
+            let (mut i, end) = (
+                start.map_or(0, |s| clamp(s, 0, len)),
+                stop.map_or(len, |s| clamp(s, 0, len)),
+            );
+            while i < end {
+                out.push(i as usize);
+                i += step;
+            }
+        } else {
+            let mut i = start.map_or(len - 1, |s| clamp(s, -1, len - 1));
+            let end = stop.map_or(-1, |s| clamp(s, -1, len - 1));
+            while i > end {
+                out.push(i as usize);
+                i += step;
+            }
+        }
+        out
+    };
+    Ok(match obj {
+        Value::List(l) => Value::List(Rc::new(
+            indices(l.len()).into_iter().map(|i| l[i].clone()).collect(),
+        )),
+        Value::Tuple(t) => Value::Tuple(Rc::new(
+            indices(t.len()).into_iter().map(|i| t[i].clone()).collect(),
+        )),
+        Value::Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            Value::from(
+                indices(chars.len())
+                    .into_iter()
+                    .map(|i| chars[i])
+                    .collect::<String>(),
+            )
+        }
+        Value::Undefined | Value::None => Value::Undefined,
+        other => return Err(format!("cannot slice {}", other.type_name())),
+    })
+}
+
+fn binary(op: &str, a: &Value, b: &Value) -> Result<Value, String> {
+    let numeric = || match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some((Some((*x, *y)), *x as f64, *y as f64)),
+        _ => Some((None, a.as_f64()?, b.as_f64()?)),
+    };
+    let mismatch = || {
+        format!(
+            "unsupported operands for {}: {} and {}",
+            op,
+            a.type_name(),
+            b.type_name()
+        )
+    };
+    Ok(match op {
+        "==" => Value::Bool(a.equals(b)),
+        "!=" => Value::Bool(!a.equals(b)),
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = match (a, b) {
+                (Value::Str(x), Value::Str(y)) => x.cmp(y),
+                _ => {
+                    let (_, x, y) = numeric().ok_or_else(mismatch)?;
+                    x.partial_cmp(&y).ok_or_else(mismatch)?
+                }
+            };
+            Value::Bool(match op {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge(),
+            })
+        }
+        "in" | "not in" => {
+            let found = match b {
+                Value::Str(s) => s.contains(a.as_str().ok_or_else(mismatch)?),
+                Value::List(l) | Value::Tuple(l) => l.iter().any(|v| v.equals(a)),
+                Value::Map(_) | Value::Namespace(_) => a
+                    .as_str()
+                    .is_some_and(|k| !matches!(b.get(k), Value::Undefined)),
+                Value::Undefined | Value::None => false,
+                _ => return Err(mismatch()),
+            };
+            Value::Bool(found == (op == "in"))
+        }
+        "~" => Value::from(a.to_text() + &b.to_text()),
+        "+" => match (a, b) {
+            (Value::Str(x), Value::Str(y)) => Value::from(format!("{}{}", x, y)),
+            (Value::List(x), Value::List(y)) => {
+                Value::List(Rc::new(x.iter().chain(y.iter()).cloned().collect()))
+            }
+            (Value::Tuple(x), Value::Tuple(y)) => {
+                Value::Tuple(Rc::new(x.iter().chain(y.iter()).cloned().collect()))
+            }
+            _ => match numeric().ok_or_else(mismatch)? {
+                (Some((x, y)), _, _) => Value::Int(x.wrapping_add(y)),
+                (None, x, y) => Value::Float(x + y),
+            },
+        },
+        "*" => match (a, b) {
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                Value::from(s.repeat((*n).max(0) as usize))
+            }
+            _ => match numeric().ok_or_else(mismatch)? {
+                (Some((x, y)), _, _) => Value::Int(x.wrapping_mul(y)),
+                (None, x, y) => Value::Float(x * y),
+            },
+        },
+        "-" | "/" | "//" | "%" => {
+            let (ints, x, y) = numeric().ok_or_else(mismatch)?;
+            if op != "-" && y == 0.0 {
+                return Err("division by zero".to_string());
+            }
+            match (op, ints) {
+                ("-", Some((x, y))) => Value::Int(x.wrapping_sub(y)),
+                ("-", None) => Value::Float(x - y),
+                ("/", _) => Value::Float(x / y),
+                ("//", Some((x, y))) => {
+                    Value::Int(x.div_euclid(y) - i64::from(y < 0 && x.rem_euclid(y) != 0))
+                }
+                ("//", None) => Value::Float((x / y).floor()),
+                (_, Some((x, y))) => Value::Int(((x % y) + y) % y),
+                (_, None) => Value::Float(((x % y) + y) % y),
+            }
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+fn kwarg<'v>(keyword: &'v [(String, Value)], name: &str) -> Option<&'v Value> {
+    keyword.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+}
+
+fn str_arg<'v>(args: &'v [Value], i: usize, what: &str) -> Result<&'v str, String> {
+    args.get(i)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{} needs a string argument", what))
+}
+
+fn apply_filter(
+    name: &str,
+    value: Value,
+    args: &[Value],
+    keyword: &[(String, Value)],
+) -> Result<Value, String> {
+    let text = || value.to_text();
+    Ok(match name {
+        "trim" => match args.first().and_then(Value::as_str) {
+            Some(chars) => Value::from(text().trim_matches(|c| chars.contains(c)).to_string()),
+            None => Value::from(text().trim().to_string()),
+        },
+        "length" | "count" => Value::Int(
+            value
+                .len()
+                .ok_or_else(|| format!("{} has no length", value.type_name()))? as i64,
+        ),
+        "tojson" => {
+            let indent = match kwarg(keyword, "indent").or(args.first()) {
+                Some(Value::Int(n)) => Some(*n as usize),
+                _ => None,
+            };
+            let mut out = String::new();
+            to_json(&value, indent, &mut out, 0);
+            Value::from(out)
+        }
+        "string" | "safe" => Value::from(text()),
+        "lower" => Value::from(text().to_lowercase()),
+        "upper" => Value::from(text().to_uppercase()),
+        "capitalize" => Value::from(capitalize(&text())),
+        "title" => Value::from(title(&text())),
+        "first" => value
+            .iter_items()?
+            .into_iter()
+            .next()
+            .unwrap_or(Value::Undefined),
+        "last" => value.iter_items()?.pop().unwrap_or(Value::Undefined),
+        "reverse" => match &value {
+            Value::Str(s) => Value::from(s.chars().rev().collect::<String>()),
+            _ => Value::List(Rc::new(value.iter_items()?.into_iter().rev().collect())),
+        },
+        "list" => Value::List(Rc::new(value.iter_items()?)),
+        "join" => {
+            let sep = args.first().map(Value::to_text).unwrap_or_default();
+            let attribute = kwarg(keyword, "attribute").map(Value::to_text);
+            let items: Vec<String> = value
+                .iter_items()?
+                .iter()
+                .map(|v| match &attribute {
+                    Some(a) => v.get(a).to_text(),
+                    None => v.to_text(),
+                })
+                .collect();
+            Value::from(items.join(&sep))
+        }
+        "default" | "d" => {
+            let boolean = args
+                .get(1)
+                .or(kwarg(keyword, "boolean"))
+                .is_some_and(Value::is_true);
+            let missing = if boolean {
+                !value.is_true()
+            } else {
+                matches!(value, Value::Undefined)
+            };
+            if missing {
+                args.first().cloned().unwrap_or_else(|| Value::from(""))
+            } else {
+                value
+            }
+        }
+        "items" => match &value {
+            Value::Map(m) => Value::List(Rc::new(
+                m.iter()
+                    .map(|(k, v)| Value::Tuple(Rc::new(vec![Value::Str(k.clone()), v.clone()])))
+                    .collect(),
+            )),
+            Value::Undefined => Value::List(Rc::new(Vec::new())),
+            other => return Err(format!("items filter on {}", other.type_name())),
+        },
+        "int" => match &value {
+            Value::Int(_) => value,
+            Value::Float(f) => Value::Int(*f as i64),
+            Value::Bool(b) => Value::Int(*b as i64),
+            Value::Str(s) => Value::Int(s.trim().parse().unwrap_or(0)),
+            _ => Value::Int(0),
+        },
+        "float" => Value::Float(match &value {
+            Value::Str(s) => s.trim().parse().unwrap_or(0.0),
+            v => v.as_f64().unwrap_or(0.0),
+        }),
+        "abs" => match value {
+            Value::Int(i) => Value::Int(i.abs()),
+            Value::Float(f) => Value::Float(f.abs()),
+            other => return Err(format!("abs of {}", other.type_name())),
+        },
+        "replace" => {
+            Value::from(text().replace(str_arg(args, 0, "replace")?, str_arg(args, 1, "replace")?))
+        }
+        "unique" => {
+            let mut seen: Vec<Value> = Vec::new();
+            for item in value.iter_items()? {
+                if !seen.iter().any(|s| s.equals(&item)) {
+                    seen.push(item);
+                }
+            }
+            Value::List(Rc::new(seen))
+        }
+        "selectattr" | "rejectattr" => {
+            let attr = str_arg(args, 0, name)?;
+            let mut kept = Vec::new();
+            for item in value.iter_items()? {
+                let field = item.get(attr);
+                let pass = match args.get(1).and_then(Value::as_str) {
+                    Some(test) => apply_test(test, &field, &args[2..])?,
+                    None => field.is_true(),
+                };
+                if pass == (name == "selectattr") {
+                    kept.push(item);
+                }
+            }
+            Value::List(Rc::new(kept))
+        }
+        "map" => {
+            let items = value.iter_items()?;
+            let mapped = if let Some(attr) = kwarg(keyword, "attribute") {
+                let attr = attr.to_text();
+                items.iter().map(|v| v.get(&attr)).collect()
+            } else {
+                let filter = str_arg(args, 0, "map")?;
+                items
+                    .into_iter()
+                    .map(|v| apply_filter(filter, v, &args[1..], &[]))
+                    .collect::<Result<_, _>>()?
+            };
+            Value::List(Rc::new(mapped))
+        }
+        other => return Err(format!("unknown filter {:?}", other)),
+    })
+}
+
+fn apply_test(name: &str, value: &Value, args: &[Value]) -> Result<bool, String> {
+    Ok(match name {
+        "defined" => !matches!(value, Value::Undefined),
+        "undefined" => matches!(value, Value::Undefined),
+        "none" => matches!(value, Value::None),
+        "string" => matches!(value, Value::Str(_)),
+        "number" => matches!(value, Value::Int(_) | Value::Float(_)),
+        "integer" => matches!(value, Value::Int(_)),
+        "float" => matches!(value, Value::Float(_)),
+        "boolean" => matches!(value, Value::Bool(_)),
+        "true" => matches!(value, Value::Bool(true)),
+        "false" => matches!(value, Value::Bool(false)),
+        "mapping" => matches!(value, Value::Map(_) | Value::Namespace(_)),
+        "sequence" | "iterable" => matches!(
+            value,
+            Value::List(_) | Value::Tuple(_) | Value::Str(_) | Value::Map(_)
+        ),
+        "equalto" | "eq" | "==" | "sameas" => args.first().is_some_and(|a| value.equals(a)),
+        "ne" | "!=" => !args.first().is_some_and(|a| value.equals(a)),
+        "in" => args
+            .first()
+            .is_some_and(|a| binary("in", value, a).is_ok_and(|v| v.is_true())),
+        "odd" | "even" => match value {
+            Value::Int(i) => (i % 2 != 0) == (name == "odd"),
+            _ => false,
+        },
+        "divisibleby" => match (value, args.first()) {
+            (Value::Int(i), Some(Value::Int(d))) if *d != 0 => i % d == 0,
+            _ => false,
+        },
+        "lower" => value
+            .as_str()
+            .is_some_and(|s| s.chars().all(|c| !c.is_uppercase())),
+        "upper" => value
+            .as_str()
+            .is_some_and(|s| s.chars().all(|c| !c.is_lowercase())),
+        other => return Err(format!("unknown test {:?}", other)),
+    })
+}
+
+/// Python `str` and `dict` methods.
+fn call_method(
+    obj: &Value,
+    method: &str,
+    args: &[Value],
+    keyword: &[(String, Value)],
+) -> Result<Value, String> {
+    if let Value::Map(m) = obj {
+        return Ok(match method {
+            "items" => Value::List(Rc::new(
+                m.iter()
+                    .map(|(k, v)| Value::Tuple(Rc::new(vec![Value::Str(k.clone()), v.clone()])))
+                    .collect(),
+            )),
+            "keys" => Value::List(Rc::new(
+                m.iter().map(|(k, _)| Value::Str(k.clone())).collect(),
+            )),
+            "values" => Value::List(Rc::new(m.iter().map(|(_, v)| v.clone()).collect())),
+            "get" => {
+                let key = str_arg(args, 0, "get")?;
+                match obj.get(key) {
+                    Value::Undefined => args.get(1).cloned().unwrap_or(Value::None),
+                    v => v,
+                }
+            }
+            other => return Err(format!("unknown dict method {}()", other)),
+        });
+    }
+    let Value::Str(s) = obj else {
+        return match obj {
+            Value::Undefined => Ok(Value::Undefined),
+            other => Err(format!("unknown {} method {}()", other.type_name(), method)),
+        };
+    };
+    let chars = args.first().and_then(Value::as_str);
+    let strip_set = |c: char| chars.map_or(c.is_whitespace(), |set| set.contains(c));
+    Ok(match method {
+        "strip" => Value::from(s.trim_matches(strip_set).to_string()),
+        "lstrip" => Value::from(s.trim_start_matches(strip_set).to_string()),
+        "rstrip" => Value::from(s.trim_end_matches(strip_set).to_string()),
+        "lower" => Value::from(s.to_lowercase()),
+        "upper" => Value::from(s.to_uppercase()),
+        "capitalize" => Value::from(capitalize(s)),
+        "title" => Value::from(title(s)),
+        "startswith" | "endswith" => {
+            let prefixes = match args.first() {
+                Some(Value::List(l) | Value::Tuple(l)) => l
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect(),
+                _ => vec![str_arg(args, 0, method)?.to_string()],
+            };
+            Value::Bool(prefixes.iter().any(|p| {
+                if method == "startswith" {
+                    s.starts_with(p.as_str())
+                } else {
+                    s.ends_with(p.as_str())
+                }
+            }))
+        }
+        "split" | "rsplit" => {
+            let max = match args.get(1).or(kwarg(keyword, "maxsplit")) {
+                Some(Value::Int(n)) if *n >= 0 => Some(*n as usize),
+                _ => None,
+            };
+            let sep = args
+                .first()
+                .or(kwarg(keyword, "sep"))
+                .and_then(Value::as_str);
+            let parts: Vec<String> = match (sep, max, method) {
+                (Some(sep), None, _) => s.split(sep).map(str::to_string).collect(),
+                (Some(sep), Some(n), "split") => s.splitn(n + 1, sep).map(str::to_string).collect(),
+                (Some(sep), Some(n), _) => {
+                    let mut parts: Vec<String> =
+                        s.rsplitn(n + 1, sep).map(str::to_string).collect();
+                    parts.reverse();
+                    parts
+                }
+                (None, _, _) => s.split_whitespace().map(str::to_string).collect(),
+            };
+            Value::List(Rc::new(parts.into_iter().map(Value::from).collect()))
+        }
+        "replace" => {
+            let (from, to) = (str_arg(args, 0, "replace")?, str_arg(args, 1, "replace")?);
+            match args.get(2) {
+                Some(Value::Int(n)) if *n >= 0 => Value::from(s.replacen(from, to, *n as usize)),
+                _ => Value::from(s.replace(from, to)),
+            }
+        }
+        "find" => {
+            let needle = str_arg(args, 0, "find")?;
+            Value::Int(s.find(needle).map_or(-1, |b| s[..b].chars().count() as i64))
+        }
+        "count" => Value::Int(s.matches(str_arg(args, 0, "count")?).count() as i64),
+        "join" => {
+            let items = args
+                .first()
+                .map(Value::iter_items)
+                .transpose()?
+                .unwrap_or_default();
+            Value::from(items.iter().map(Value::to_text).collect::<Vec<_>>().join(s))
+        }
+        other => return Err(format!("unknown string method {}()", other)),
+    })
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn title(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut prev_letter = false;
+    for c in s.chars() {
+        if prev_letter {
+            out.extend(c.to_lowercase());
+        } else {
+            out.extend(c.to_uppercase());
+        }
+        prev_letter = c.is_alphabetic();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Render `source` with `<s>` and `</s>` as the BOS and EOS tokens. The
+    /// expected prompts below are what Jinja2 renders with the settings of
+    /// `apply_chat_template`.
+    fn render(
+        source: &str,
+        messages: serde_json::Value,
+        tools: Option<serde_json::Value>,
+        add_generation_prompt: bool,
+    ) -> Result<String, Error> {
+        let template = ChatTemplate::new(source.to_string(), "<s>".to_string(), "</s>".to_string());
+        template.render(
+            messages.as_array().unwrap(),
+            tools.as_ref(),
+            add_generation_prompt,
+        )
+    }
+
+    fn conversation() -> serde_json::Value {
+        json!([
+            { "role": "system", "content": "  You are terse.  " },
+            { "role": "user", "content": "Hi there!\nHow are you?" },
+            { "role": "assistant", "content": "<think>hmm</think>Fine, thanks." },
+            { "role": "user", "content": "Weather in Paris?" },
+        ])
+    }
+
+    fn tool_conversation() -> serde_json::Value {
+        json!([
+            { "role": "user", "content": "Weather in Paris?" },
+            {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "abc123XYZ",
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "arguments": { "city": "Paris", "units": ["c", 1, 2.5, true, null] },
+                    },
+                }],
+            },
+            { "role": "tool", "tool_call_id": "abc123XYZ", "content": "{\"temp\": 21}" },
+            { "role": "assistant", "content": "It is 21°C." },
+        ])
+    }
+
+    fn tools() -> serde_json::Value {
+        json!([{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Get the \"weather\"",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"],
+                },
+            },
+        }])
+    }
+
+    #[test]
+    fn qwen2_5_conversation() {
+        let source = include_str!("../tests/chat_templates/qwen2.5.jinja");
+        let expected = r##"<|im_start|>system
+  You are terse.  <|im_end|>
+<|im_start|>user
+Hi there!
+How are you?<|im_end|>
+<|im_start|>assistant
+<think>hmm</think>Fine, thanks.<|im_end|>
+<|im_start|>user
+Weather in Paris?<|im_end|>
+<|im_start|>assistant
+"##;
+        assert_eq!(
+            render(source, conversation(), None, true).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn qwen2_5_tool_calls() {
+        let source = include_str!("../tests/chat_templates/qwen2.5.jinja");
+        let expected = r##"<|im_start|>system
+You are Qwen, created by Alibaba Cloud. You are a helpful assistant.
+
+# Tools
+
+You may call one or more functions to assist with the user query.
+
+You are provided with function signatures within <tools></tools> XML tags:
+<tools>
+{"type": "function", "function": {"name": "get_weather", "description": "Get the \"weather\"", "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}}}
+</tools>
+
+For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
+<tool_call>
+{"name": <function-name>, "arguments": <args-json-object>}
+</tool_call><|im_end|>
+<|im_start|>user
+Weather in Paris?<|im_end|>
+<|im_start|>assistant
+<tool_call>
+{"name": "get_weather", "arguments": {"city": "Paris", "units": ["c", 1, 2.5, true, null]}}
+</tool_call><|im_end|>
+<|im_start|>user
+<tool_response>
+{"temp": 21}
+</tool_response><|im_end|>
+<|im_start|>assistant
+It is 21°C.<|im_end|>
+"##;
+        assert_eq!(
+            render(source, tool_conversation(), Some(tools()), false).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn llama3_1_conversation() {
+        let source = include_str!("../tests/chat_templates/llama3.1.jinja");
+        let expected = r##"<s><|start_header_id|>system<|end_header_id|>
+
+Cutting Knowledge Date: December 2023
+Today Date: 26 Jul 2024
+
+You are terse.<|eot_id|><|start_header_id|>user<|end_header_id|>
+
+Hi there!
+How are you?<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+
+<think>hmm</think>Fine, thanks.<|eot_id|><|start_header_id|>user<|end_header_id|>
+
+Weather in Paris?<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+
+"##;
+        assert_eq!(
+            render(source, conversation(), None, true).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn llama3_1_tool_calls() {
+        let source = include_str!("../tests/chat_templates/llama3.1.jinja");
+        let expected = r##"<s><|start_header_id|>system<|end_header_id|>
+
+Environment: ipython
+Cutting Knowledge Date: December 2023
+Today Date: 26 Jul 2024
+
+<|eot_id|><|start_header_id|>user<|end_header_id|>
+
+Given the following functions, please respond with a JSON for a function call with its proper arguments that best answers the given prompt.
+
+Respond in the format {"name": function name, "parameters": dictionary of argument name and its value}.Do not use variables.
+
+{
+    "type": "function",
+    "function": {
+        "name": "get_weather",
+        "description": "Get the \"weather\"",
+        "parameters": {
+            "type": "object",
+            "properties": {
+                "city": {
+                    "type": "string"
+                }
+            },
+            "required": [
+                "city"
+            ]
+        }
+    }
+}
+
+Weather in Paris?<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+
+{"name": "get_weather", "parameters": {"city": "Paris", "units": ["c", 1, 2.5, true, null]}}<|eot_id|><|start_header_id|>ipython<|end_header_id|>
+
+"{\"temp\": 21}"<|eot_id|><|start_header_id|>assistant<|end_header_id|>
+
+It is 21°C.<|eot_id|>"##;
+        assert_eq!(
+            render(source, tool_conversation(), Some(tools()), false).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn mistral_conversation() {
+        let source = include_str!("../tests/chat_templates/mistral.jinja");
+        let expected = r##"<s>[INST] Hi there!
+How are you?[/INST] <think>hmm</think>Fine, thanks.</s>[INST]   You are terse.  
+
+Weather in Paris?[/INST]"##;
+        assert_eq!(
+            render(source, conversation(), None, true).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn mistral_tool_calls() {
+        let source = include_str!("../tests/chat_templates/mistral.jinja");
+        let expected = r##"<s>[AVAILABLE_TOOLS] [{"type": "function", "function": {"name": "get_weather", "description": "Get the "weather"", "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}}}][/AVAILABLE_TOOLS][INST] Weather in Paris?[/INST][TOOL_CALLS] [{"name": "get_weather", "arguments": {"city": "Paris", "units": ["c", 1, 2.5, true, null]}, "id": "abc123XYZ"}]</s>[TOOL_RESULTS] {"content": {"temp": 21}, "call_id": "abc123XYZ"}[/TOOL_RESULTS] It is 21°C.</s>"##;
+        assert_eq!(
+            render(source, tool_conversation(), Some(tools()), false).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn deepseek_r1_conversation() {
+        let source = include_str!("../tests/chat_templates/deepseek-r1.jinja");
+        let expected = r##"<s>  You are terse.  <｜User｜>Hi there!
+How are you?<｜Assistant｜>Fine, thanks.<｜end▁of▁sentence｜><｜User｜>Weather in Paris?<｜Assistant｜><think>
+"##;
+        assert_eq!(
+            render(source, conversation(), None, true).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn deepseek_r1_tool_calls() {
+        let source = include_str!("../tests/chat_templates/deepseek-r1.jinja");
+        let expected = r##"<s><｜User｜>Weather in Paris?<｜Assistant｜><｜tool▁calls▁begin｜><｜tool▁call▁begin｜>function<｜tool▁sep｜>get_weather
+```json
+{"city": "Paris", "units": ["c", 1, 2.5, true, null]}
+```<｜tool▁call▁end｜><｜tool▁calls▁end｜><｜end▁of▁sentence｜><｜tool▁outputs▁begin｜><｜tool▁output▁begin｜>{"temp": 21}<｜tool▁output▁end｜><｜tool▁outputs▁end｜>It is 21°C.<｜end▁of▁sentence｜>"##;
+        assert_eq!(
+            render(source, tool_conversation(), Some(tools()), false).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn items_are_tuples() {
+        let source =
+            "{% set d = {'a': 1} %}{{ d|items|list }} {{ d.items()|first }} {{ ('b', 2) }} \
+            {%- for k, v in d.items() %} {{ k }}={{ v }}{% endfor %}";
+        assert_eq!(
+            render(source, json!([]), None, false).unwrap(),
+            "[('a', 1)] ('a', 1) ('b', 2) a=1"
+        );
+    }
+
+    #[test]
+    fn unknown_filters_are_errors() {
+        let err = render("{{ messages|shuffle }}", json!([]), None, false).unwrap_err();
+        assert_eq!(err.to_string(), "chat template: unknown filter \"shuffle\"");
+    }
+
+    #[test]
+    fn raise_exception_fails_the_render() {
+        let source = "{% if messages[0].role != 'user' %}{{ raise_exception('Conversation must start with a user') }}{% endif %}";
+        let err = render(
+            source,
+            json!([{ "role": "assistant", "content": "x" }]),
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "chat template: Conversation must start with a user"
+        );
+        assert_eq!(
+            render(
+                source,
+                json!([{ "role": "user", "content": "x" }]),
+                None,
+                false
+            )
+            .unwrap(),
+            ""
+        );
+    }
+}
@@ -1,36 +1,115 @@
-/// OpenAI tiktoken-compatible tokenizer for o200k_base encoding.
+/// OpenAI tiktoken-compatible tokenizer (o200k_base, cl100k_base, ...).
 ///
-/// Uses a compile-time frozen hash table (built by build.rs) for rank lookup.
-/// The regex pattern for o200k_base is compiled from a constant.
+/// Uses a frozen hash table (built by build.rs, or at load time from a
+/// `.tiktoken` file) for rank lookup. The blob also carries the encoding's
+/// regex pattern and special tokens.
 ///
-/// Tokenization: regex pre-tokenize → byte-level BPE using rank lookup.
+/// Tokenization: split out special tokens → regex pre-tokenize → byte-level
+/// BPE using rank lookup.
 /// BPE merges use a priority queue + linked-list skip structure for O(n log n).
+use crate::added::{parse_added_tokens, AddedMatcher, AddedToken};
+use crate::convert::ADDED_CHAT;
+use crate::format::{self, Header};
 use crate::frozen;
+use crate::{SpecialTokens, TokenSpan};
+use std::borrow::Cow;
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 
-/// The pre-tokenization regex for o200k_base (from tiktoken's published data).
-const O200K_PAT: &str = concat!(
-    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+",
-    r"('s|'S|'t|'T|'re|'rE|'Re|'RE|'ve|'vE|'Ve|'VE|'m|'M|'ll|'lL|'Ll|'LL|'d|'D)?",
-    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*",
-    r"('s|'S|'t|'T|'re|'rE|'Re|'RE|'ve|'vE|'Ve|'VE|'m|'M|'ll|'lL|'Ll|'LL|'d|'D)?",
-    r"|\p{N}{1,3}",
-    r"| ?[^\s\p{L}\p{N}]+[\r\n/]*",
-    r"|\s*[\r\n]+",
-    r"|\s+(?!\S)",
-    r"|\s+",
-);
-
-pub struct TiktokenTokenizer {
+pub struct TiktokenTokenizer<'a> {
+    encoding: String,
     regex: fancy_regex::Regex,
-    data: &'static [u8],
+    data: Cow<'a, [u8]>,
+    ranks_off: usize,
+    decoder_off: usize,
+    specials: AddedMatcher,
+    /// `specials` plus the ChatML markers.
+    chat_specials: AddedMatcher,
+    special_tokens: SpecialTokens,
+    chat_markers: bool,
 }
 
-impl TiktokenTokenizer {
-    pub fn new(data: &'static [u8]) -> Self {
-        let regex = fancy_regex::Regex::new(O200K_PAT).expect("invalid o200k regex");
-        TiktokenTokenizer { regex, data }
+impl<'a> TiktokenTokenizer<'a> {
+    pub fn from_frozen(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, String> {
+        let data = data.into();
+        let header = Header::parse_kind(&data, format::KIND_TIKTOKEN)?;
+        let config = header.section(format::SEC_CONFIG)?;
+        let (encoding, pattern) = parse_config(&data[config])?;
+        let ranks_off = header
+            .checked_section(format::SEC_VOCAB, frozen::check_frozen_map)?
+            .start;
+        let decoder_off = header
+            .checked_section(format::SEC_ID_TOKENS, frozen::check_string_array)?
+            .start;
+        let specials = header.section(format::SEC_ADDED_TOKENS)?;
+        let specials = parse_added_tokens(&data[specials])
+            .map_err(|e| format!("added-tokens section: {}", e))?;
+        let plain_specials = specials
+            .iter()
+            .filter(|t| t.flags & ADDED_CHAT == 0)
+            .map(|t| AddedToken {
+                id: t.id,
+                content: t.content.clone(),
+                flags: t.flags,
+            })
+            .collect();
+        let regex = fancy_regex::Regex::new(&pattern)
+            .map_err(|e| format!("invalid {} pattern: {e}", encoding))?;
+        Ok(TiktokenTokenizer {
+            encoding,
+            regex,
+            data,
+            ranks_off,
+            decoder_off,
+            specials: AddedMatcher::new(plain_specials),
+            chat_specials: AddedMatcher::new(specials),
+            special_tokens: SpecialTokens::default(),
+            chat_markers: false,
+        })
+    }
+
+    /// Name of the encoding, e.g. `cl100k_base`.
+    pub fn encoding(&self) -> &str {
+        &self.encoding
+    }
+
+    pub fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        self.special_tokens = mode;
+    }
+
+    pub fn set_chat_markers(&mut self, on: bool) {
+        self.chat_markers = on;
+    }
+
+    fn specials(&self) -> &AddedMatcher {
+        if self.chat_markers {
+            &self.chat_specials
+        } else {
+            &self.specials
+        }
+    }
+
+    pub fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        self.specials().find_disallowed(text, &self.special_tokens)
+    }
+
+    /// Split `text` into special tokens, `(start, end, Some(id))`, and the
+    /// ordinary text between them, `(start, end, None)`. Special tokens the
+    /// [`SpecialTokens`] mode does not allow are ordinary text.
+    fn segments(&self, text: &str) -> Vec<(usize, usize, Option<u32>)> {
+        let mut segments = Vec::new();
+        let mut last = 0;
+        for (start, end, id) in self.specials().find(text, &self.special_tokens) {
+            if start > last {
+                segments.push((last, start, None));
+            }
+            segments.push((start, end, Some(id)));
+            last = end;
+        }
+        if last < text.len() {
+            segments.push((last, text.len(), None));
+        }
+        segments
     }
 
     pub fn count_tokens(&self, text: &str) -> usize {
@@ -38,33 +117,98 @@ impl TiktokenTokenizer {
             return 0;
         }
         let mut total = 0;
-        for m in self.regex.find_iter(text) {
-            let m = match m {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-            total += self.bpe_count(m.as_str().as_bytes());
+        for (start, end, special) in self.segments(text) {
+            if special.is_some() {
+                total += 1;
+                continue;
+            }
+            for m in self.regex.find_iter(&text[start..end]) {
+                let m = match m {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+                self.bpe_merge(m.as_str().as_bytes(), |_, _| total += 1);
+            }
         }
         total
     }
 
-    /// Byte-level BPE merge count using a priority queue + linked-list skip
-    /// structure.  O(n log n) instead of the naive O(n²) linear-scan approach.
-    fn bpe_count(&self, piece: &[u8]) -> usize {
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets(text)
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        let mut tokens = Vec::new();
+        for (seg_start, seg_end, special) in self.segments(text) {
+            if let Some(id) = special {
+                tokens.push(TokenSpan {
+                    id,
+                    start: seg_start,
+                    end: seg_end,
+                });
+                continue;
+            }
+            for m in self.regex.find_iter(&text[seg_start..seg_end]) {
+                let m = match m {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+                let piece = m.as_str().as_bytes();
+                let offset = seg_start + m.start();
+                self.bpe_merge(piece, |start, end| {
+                    // Every surviving part is a rank-table key: single bytes are
+                    // all ranked, and merges only produce ranked concatenations.
+                    if let Some(id) =
+                        frozen::frozen_map_get(&self.data[self.ranks_off..], &piece[start..end])
+                    {
+                        tokens.push(TokenSpan {
+                            id,
+                            start: offset + start,
+                            end: offset + end,
+                        });
+                    }
+                });
+            }
+        }
+        tokens
+    }
+
+    pub fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        let decoder = &self.data[self.decoder_off..];
+        let mut out = Vec::new();
+        for &id in ids {
+            out.extend_from_slice(frozen::string_array_get(decoder, id as usize).unwrap_or(&[]));
+        }
+        out
+    }
+
+    /// Byte-level BPE using a priority queue + linked-list skip structure.
+    /// O(n log n) instead of the naive O(n²) linear-scan approach.
+    ///
+    /// Calls `emit(start, end)` with the byte range of each final token of
+    /// `piece`, in order.
+    fn bpe_merge(&self, piece: &[u8], mut emit: impl FnMut(usize, usize)) {
         if piece.is_empty() {
-            return 0;
+            return;
         }
         let n = piece.len();
         if n == 1 {
-            return 1;
+            emit(0, 1);
+            return;
         }
+        let ranks = &self.data[self.ranks_off..];
 
         // Each part is a [start, end) byte range into `piece`.
         let mut parts: Vec<(usize, usize)> = (0..n).map(|i| (i, i + 1)).collect();
 
         // Linked list for O(1) neighbor traversal after merges.
         let mut next: Vec<usize> = (1..=n).collect();
-        let mut prev: Vec<usize> = (0..n).map(|i| if i == 0 { usize::MAX } else { i - 1 }).collect();
+        let mut prev: Vec<usize> = (0..n)
+            .map(|i| if i == 0 { usize::MAX } else { i - 1 })
+            .collect();
         let mut alive = vec![true; n];
 
         // Generation counters to cheaply invalidate stale heap entries.
@@ -76,9 +220,11 @@ impl TiktokenTokenizer {
         // Helper: rank of merging parts[i] with its right neighbor.
         let pair_rank = |i: usize, parts: &[(usize, usize)], next: &[usize]| -> Option<u32> {
             let j = next[i];
-            if j >= n { return None; }
+            if j >= n {
+                return None;
+            }
             frozen::frozen_map_get_concat(
-                self.data,
+                ranks,
                 &piece[parts[i].0..parts[i].1],
                 &piece[parts[j].0..parts[j].1],
             )
@@ -91,23 +237,27 @@ impl TiktokenTokenizer {
             }
         }
 
-        let mut count = n;
-
         while let Some(Reverse((rank, i, g))) = heap.pop() {
-            if !alive[i] || gen[i] != g { continue; }
+            if !alive[i] || gen[i] != g {
+                continue;
+            }
             let j = next[i];
-            if j >= n || !alive[j] { continue; }
+            if j >= n || !alive[j] {
+                continue;
+            }
 
             // Verify the rank is still current (parts[i] may have grown).
             let current_rank = match frozen::frozen_map_get_concat(
-                self.data,
+                ranks,
                 &piece[parts[i].0..parts[i].1],
                 &piece[parts[j].0..parts[j].1],
             ) {
                 Some(r) => r,
                 None => continue,
             };
-            if current_rank != rank { continue; }
+            if current_rank != rank {
+                continue;
+            }
 
             // Merge: extend i to cover j's byte range, remove j from the list.
             parts[i].1 = parts[j].1;
@@ -115,8 +265,9 @@ impl TiktokenTokenizer {
             alive[j] = false;
             let k = next[j];
             next[i] = k;
-            if k < n { prev[k] = i; }
-            count -= 1;
+            if k < n {
+                prev[k] = i;
+            }
 
             // Re-evaluate the pair (prev[i], i) — left neighbor changed.
             if prev[i] != usize::MAX && alive[prev[i]] {
@@ -133,6 +284,34 @@ impl TiktokenTokenizer {
             }
         }
 
-        count
+        // Part 0 is never merged away (merges keep the left index), so the
+        // surviving parts are reachable from it in order.
+        let mut i = 0;
+        while i < n {
+            emit(parts[i].0, parts[i].1);
+            i = next[i];
+        }
+    }
+}
+
+/// Config section: `[encoding name][pattern]`, both length-prefixed.
+fn parse_config(config: &[u8]) -> Result<(String, String), String> {
+    let (encoding, len) = frozen::get_str(config, 0)?;
+    let (pattern, pattern_len) = frozen::get_str(config, len)?;
+    if len + pattern_len != config.len() {
+        return Err(format!(
+            "config section has {} unread bytes",
+            config.len() - len - pattern_len
+        ));
     }
+    Ok((encoding, pattern))
+}
+
+/// Labelled, human-readable lines for a config section (`tokencount inspect`).
+pub(crate) fn describe_config(config: &[u8]) -> Result<Vec<(&'static str, String)>, String> {
+    let (encoding, pattern) = parse_config(config)?;
+    Ok(vec![
+        ("encoding", encoding),
+        ("pattern", format!("/{}/", pattern)),
+    ])
 }
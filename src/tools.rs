@@ -0,0 +1,328 @@
+//! Token cost of tool (function) definitions sent with a chat request.
+//!
+//! Providers turn the JSON Schema of each tool into text in the prompt,
+//! so a tool costs the tokens of that text. The serializations are not
+//! published; each is rendered the way it is usually reconstructed:
+//!
+//! - OpenAI, after `openai-chat-tokens`: a TypeScript `namespace functions`
+//!   in the system message, one `type name = (_: {...}) => any;` per tool
+//!   with its description and each property's as `//` comments.
+//! - Anthropic, as its tool-use prompt: one
+//!   `<function>{"description": ..., "name": ..., "parameters": ...}</function>`
+//!   line per tool inside `<functions>`. The fixed instructions around
+//!   them are not counted.
+//!
+//! Models with a chat template render the tools themselves; there a tool
+//! costs what removing it would save, and whatever the template adds for
+//! having tools at all is the overhead.
+
+use serde_json::{json, Value};
+
+use crate::chat::ChatFormat;
+use crate::template::{self, ChatTemplate};
+use crate::{Error, Tokenizer};
+
+const OPENAI_HEADER: &str = "# Tools\n\n## functions\n\nnamespace functions {\n\n";
+const OPENAI_FOOTER: &str = "} // namespace functions";
+const ANTHROPIC_HEADER: &str = "<functions>\n";
+const ANTHROPIC_FOOTER: &str = "</functions>";
+
+/// Tokens of one tool definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCount {
+    pub name: String,
+    /// The definition as the provider serializes it.
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// Tokens of a list of tool definitions, tool by tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolsCount {
+    pub tools: Vec<ToolCount>,
+    /// Tokens of the wrapper around the definitions, when there are any.
+    pub overhead: usize,
+}
+
+impl ToolsCount {
+    pub fn total(&self) -> usize {
+        self.tools.iter().map(|t| t.tokens).sum::<usize>() + self.overhead
+    }
+}
+
+/// A tool definition, whichever API it was written for.
+struct Tool {
+    name: String,
+    description: Option<String>,
+    parameters: Value,
+}
+
+/// Count tool definitions: a request body with a `tools` (or OpenAI's
+/// legacy `functions`) array, the bare array, or a single tool.
+pub fn count_tools(
+    tok: &dyn Tokenizer,
+    format: ChatFormat,
+    payload: &Value,
+) -> Result<ToolsCount, Error> {
+    let tools = parse_tools(format, payload)?;
+    let (header, footer) = match format {
+        ChatFormat::OpenAi => (OPENAI_HEADER, OPENAI_FOOTER),
+        ChatFormat::Anthropic => (ANTHROPIC_HEADER, ANTHROPIC_FOOTER),
+    };
+    let texts: Vec<String> = tools
+        .iter()
+        .map(|tool| match format {
+            ChatFormat::OpenAi => openai_definition(tool),
+            ChatFormat::Anthropic => anthropic_definition(tool),
+        })
+        .collect();
+    if let Some(template) = tok.chat_template() {
+        return count_templated(tok, template, &tools, texts);
+    }
+    let tools = tools
+        .into_iter()
+        .zip(texts)
+        .map(|(tool, text)| ToolCount {
+            name: tool.name,
+            tokens: tok.count_tokens(&text),
+            text,
+        })
+        .collect::<Vec<_>>();
+    let overhead = if tools.is_empty() {
+        0
+    } else {
+        tok.count_tokens(header) + tok.count_tokens(footer)
+    };
+    Ok(ToolsCount { tools, overhead })
+}
+
+/// Count tools through `template`, rendered with a one-message conversation
+/// and the generation prompt. Each tool is charged the tokens the prompt
+/// loses without it.
+fn count_templated(
+    tok: &dyn Tokenizer,
+    template: &ChatTemplate,
+    tools: &[Tool],
+    texts: Vec<String>,
+) -> Result<ToolsCount, Error> {
+    let messages = [json!({ "role": "user", "content": "" })];
+    let hf: Vec<Value> = tools.iter().map(hf_tool).collect();
+    let count = |tools: Vec<Value>| -> Result<usize, Error> {
+        let tools = (!tools.is_empty()).then_some(Value::Array(tools));
+        Ok(tok.count_tokens(&template.render(&messages, tools.as_ref(), true)?))
+    };
+    let full = count(hf.clone())?;
+    let without_any = count(Vec::new())?;
+    let mut counts = Vec::new();
+    for ((i, tool), text) in tools.iter().enumerate().zip(texts) {
+        let mut others = hf.clone();
+        others.remove(i);
+        let tokens = full.saturating_sub(count(others)?);
+        counts.push(ToolCount {
+            name: tool.name.clone(),
+            text,
+            tokens,
+        });
+    }
+    let tools_total: usize = counts.iter().map(|t| t.tokens).sum();
+    let overhead = full.saturating_sub(without_any).saturating_sub(tools_total);
+    Ok(ToolsCount {
+        tools: counts,
+        overhead,
+    })
+}
+
+/// The tools of a request body as Hugging Face chat templates take them,
+/// `None` when it has none.
+pub(crate) fn template_tools(format: ChatFormat, body: &Value) -> Result<Option<Value>, Error> {
+    let has_tools = body
+        .get("tools")
+        .or_else(|| body.get("functions"))
+        .is_some();
+    if !body.is_object() || !has_tools {
+        return Ok(None);
+    }
+    let tools = parse_tools(format, body)?;
+    Ok(Some(Value::Array(tools.iter().map(hf_tool).collect())))
+}
+
+/// `{"type": "function", "function": {"name", "description", "parameters"}}`.
+fn hf_tool(tool: &Tool) -> Value {
+    let mut function = json!({ "name": tool.name });
+    if let Some(description) = &tool.description {
+        function["description"] = Value::from(description.as_str());
+    }
+    function["parameters"] = tool.parameters.clone();
+    json!({ "type": "function", "function": function })
+}
+
+fn parse_tools(format: ChatFormat, payload: &Value) -> Result<Vec<Tool>, Error> {
+    let single;
+    let items = match payload {
+        Value::Array(items) => items.as_slice(),
+        Value::Object(body) => match body.get("tools").or_else(|| body.get("functions")) {
+            Some(Value::Array(items)) => items.as_slice(),
+            Some(_) => return Err(invalid("tools must be an array")),
+            None => {
+                single = [payload.clone()];
+                &single[..]
+            }
+        },
+        _ => return Err(invalid("expected a JSON object or array")),
+    };
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let tool = match format {
+                ChatFormat::OpenAi => parse_openai_tool(item),
+                ChatFormat::Anthropic => parse_anthropic_tool(item),
+            };
+            tool.map_err(|e| invalid(format!("tool {}: {}", i, e)))
+        })
+        .collect()
+}
+
+/// `{"type": "function", "function": {...}}`, or the bare function of the
+/// legacy `functions` array.
+fn parse_openai_tool(item: &Value) -> Result<Tool, String> {
+    let function = match item.get("type").and_then(Value::as_str) {
+        Some("function") => item
+            .get("function")
+            .ok_or("function tool without a function")?,
+        Some(other) => return Err(format!("unsupported tool type {:?}", other)),
+        None => item,
+    };
+    parse_tool(function, "parameters")
+}
+
+/// `{"name", "description", "input_schema"}`; server tools, which have a
+/// `type` and no schema, are not supported.
+fn parse_anthropic_tool(item: &Value) -> Result<Tool, String> {
+    match item.get("type").and_then(Value::as_str) {
+        None | Some("custom") => parse_tool(item, "input_schema"),
+        Some(other) => Err(format!("unsupported tool type {:?}", other)),
+    }
+}
+
+fn parse_tool(function: &Value, schema_key: &str) -> Result<Tool, String> {
+    let name = function
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or("tool without a string \"name\"")?;
+    Ok(Tool {
+        name: name.to_string(),
+        description: function
+            .get("description")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        parameters: function
+            .get(schema_key)
+            .cloned()
+            .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+    })
+}
+
+/// One function of OpenAI's TypeScript namespace, with its trailing blank line.
+fn openai_definition(tool: &Tool) -> String {
+    let mut lines = Vec::new();
+    if let Some(description) = &tool.description {
+        lines.push(format!("// {}", description));
+    }
+    let has_properties = tool
+        .parameters
+        .get("properties")
+        .and_then(Value::as_object)
+        .is_some_and(|p| !p.is_empty());
+    if has_properties {
+        lines.push(format!("type {} = (_: {{", tool.name));
+        lines.push(typescript_properties(&tool.parameters, 0));
+        lines.push("}) => any;".to_string());
+    } else {
+        lines.push(format!("type {} = () => any;", tool.name));
+    }
+    lines.join("\n") + "\n\n"
+}
+
+/// An object schema's properties as TypeScript members, optional unless
+/// `required`. Descriptions are kept for the top two levels.
+fn typescript_properties(schema: &Value, indent: usize) -> String {
+    let required = |name: &str| {
+        schema
+            .get("required")
+            .and_then(Value::as_array)
+            .is_some_and(|r| r.iter().any(|v| v.as_str() == Some(name)))
+    };
+    let mut lines = Vec::new();
+    for (name, property) in schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(description) = property.get("description").and_then(Value::as_str) {
+            if indent < 2 {
+                lines.push(format!("// {}", description));
+            }
+        }
+        let optional = if required(name) { "" } else { "?" };
+        lines.push(format!(
+            "{}{}: {},",
+            name,
+            optional,
+            typescript_type(property, indent)
+        ));
+    }
+    let pad = " ".repeat(indent);
+    lines
+        .iter()
+        .map(|line| format!("{}{}", pad, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn typescript_type(schema: &Value, indent: usize) -> String {
+    let enum_values = |quote: bool| {
+        schema.get("enum").and_then(Value::as_array).map(|values| {
+            values
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) if quote => format!("\"{}\"", s),
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" | ")
+        })
+    };
+    match schema.get("type").and_then(Value::as_str) {
+        Some("string") => enum_values(true).unwrap_or_else(|| "string".to_string()),
+        Some("number" | "integer") => enum_values(false).unwrap_or_else(|| "number".to_string()),
+        Some("boolean") => "boolean".to_string(),
+        Some("null") => "null".to_string(),
+        Some("object") => format!("{{\n{}\n}}", typescript_properties(schema, indent + 2)),
+        Some("array") => match schema.get("items") {
+            Some(items) => format!("{}[]", typescript_type(items, indent)),
+            None => "any[]".to_string(),
+        },
+        _ => "any".to_string(),
+    }
+}
+
+/// One `<function>` line of Anthropic's tool-use prompt.
+fn anthropic_definition(tool: &Tool) -> String {
+    let mut function = json!({});
+    if let Some(description) = &tool.description {
+        function["description"] = Value::from(description.as_str());
+    }
+    function["name"] = Value::from(tool.name.as_str());
+    function["parameters"] = tool.parameters.clone();
+    format!(
+        "<function>{}</function>\n",
+        template::python_json(&function)
+    )
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidChat(reason.into())
+}
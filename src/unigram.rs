@@ -0,0 +1,317 @@
+//! Unigram (SentencePiece) model from a Hugging Face `tokenizer.json`.
+//!
+//! Each pre-tokenized chunk is segmented by Viterbi search over the pieces
+//! that match at each character boundary, maximizing the sum of their
+//! log-probability scores, as in HF's `Unigram::encode_optimized`.
+//! Characters no piece covers become unknown steps, scored below every
+//! piece; consecutive ones are fused into a single token.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::claude::{check_trie, TrieArrays};
+use crate::format::{self, Header};
+use crate::frozen;
+use crate::pipeline::{Piece, Pipeline};
+use crate::template::ChatTemplate;
+use crate::{SpecialTokens, TokenSpan};
+
+/// Score of an unknown step below the lowest piece score, as in HF.
+const UNK_PENALTY: f64 = 10.0;
+/// Lattice node ID of an unknown step, and the scores section's `unk_id`
+/// when the model has none.
+const UNKNOWN: u32 = u32::MAX;
+
+pub struct UnigramTokenizer<'a> {
+    data: Cow<'a, [u8]>,
+    pipeline: Pipeline,
+    trie: Range<usize>,
+    scores_off: usize,
+    piece_count: usize,
+    unk_id: Option<u32>,
+    unk_score: f64,
+    /// IDs of the `<0xNN>` pieces, for byte fallback.
+    byte_ids: [Option<u32>; 256],
+    id_tokens_off: usize,
+}
+
+/// Best path ending at a byte offset of the chunk.
+#[derive(Clone, Copy)]
+struct Node {
+    score: f64,
+    /// Where the last piece on the path starts; `usize::MAX` if unreached.
+    start: usize,
+    id: u32,
+}
+
+impl<'a> UnigramTokenizer<'a> {
+    pub fn from_frozen(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, String> {
+        let data = data.into();
+        let header = Header::parse_kind(&data, format::KIND_HF_UNIGRAM)?;
+        let pipeline = Pipeline::from_frozen(&header, &data)?;
+        let trie = header.checked_section(format::SEC_TRIE, check_trie)?;
+        let scores_off = header
+            .checked_section(format::SEC_SCORES, check_scores)?
+            .start;
+        let id_tokens_off = header
+            .checked_section(format::SEC_ID_TOKENS, frozen::check_string_array)?
+            .start;
+
+        let unk_id = Some(frozen::read_u32(&data, scores_off)).filter(|&id| id != UNKNOWN);
+        let piece_count = frozen::read_u32(&data, scores_off + 4) as usize;
+        let min_score = f64::from_bits(frozen::read_u64(&data, scores_off + 8));
+
+        let mut byte_ids = [None; 256];
+        if pipeline.byte_fallback {
+            let arrays = TrieArrays::new(&data[trie.clone()]);
+            for (b, slot) in byte_ids.iter_mut().enumerate() {
+                let piece = format!("<0x{:02X}>", b);
+                arrays.prefixes(piece.as_bytes(), 0, |len, id| {
+                    if len == piece.len() {
+                        *slot = Some(id);
+                    }
+                });
+            }
+        }
+
+        Ok(UnigramTokenizer {
+            data,
+            pipeline,
+            trie,
+            scores_off,
+            piece_count,
+            unk_id,
+            unk_score: min_score - UNK_PENALTY,
+            byte_ids,
+            id_tokens_off,
+        })
+    }
+
+    pub fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        self.pipeline.set_special_tokens(mode);
+    }
+
+    pub fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        self.pipeline.find_special_token(text)
+    }
+
+    pub fn set_add_special_tokens(&mut self, add: bool) {
+        self.pipeline.set_add_special_tokens(add);
+    }
+
+    pub fn chat_template(&self) -> Option<&ChatTemplate> {
+        self.pipeline.chat_template()
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        self.pipeline.post_count(false) + self.count_text(text)
+    }
+
+    pub fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        self.pipeline.post_count(true) + self.count_text(a) + self.count_text(b)
+    }
+
+    /// Tokens of `text` alone, without the post-processor's.
+    fn count_text(&self, text: &str) -> usize {
+        let mut total = 0;
+        for piece in self.pipeline.pre_tokenize(text, false) {
+            match piece {
+                Piece::Added(_) => total += 1,
+                Piece::Text(chunk) => self.segment(&chunk.text, &mut |_, _, _| total += 1),
+            }
+        }
+        total
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets_impl(text, false)
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        self.encode_with_offsets_impl(text, true)
+    }
+
+    pub fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        self.pipeline.encode_pair(
+            |spans| self.encode_text(a, false, spans),
+            |spans| self.encode_text(b, false, spans),
+        )
+    }
+
+    fn encode_with_offsets_impl(&self, text: &str, track_offsets: bool) -> Vec<TokenSpan> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.pipeline.encode_single(text.len(), |spans| {
+            self.encode_text(text, track_offsets, spans)
+        })
+    }
+
+    /// Encode `text` alone into `spans`, without the post-processor's tokens.
+    fn encode_text(&self, text: &str, track_offsets: bool, spans: &mut Vec<TokenSpan>) {
+        for piece in self.pipeline.pre_tokenize(text, track_offsets) {
+            let chunk = match piece {
+                Piece::Added(span) => {
+                    spans.push(span);
+                    continue;
+                }
+                Piece::Text(chunk) => chunk,
+            };
+            self.segment(&chunk.text, &mut |id, s, e| {
+                let (start, end) = chunk.original_range(s, e);
+                spans.push(TokenSpan { id, start, end });
+            });
+        }
+    }
+
+    pub fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        let id_tokens = &self.data[self.id_tokens_off..];
+        let tokens = ids
+            .iter()
+            .filter_map(|&id| frozen::string_array_get(id_tokens, id as usize))
+            .map(|t| t.to_vec())
+            .collect();
+        self.pipeline.decode(tokens)
+    }
+
+    fn score(&self, id: u32) -> Option<f64> {
+        let id = id as usize;
+        (id < self.piece_count)
+            .then(|| f64::from_bits(frozen::read_u64(&self.data, self.scores_off + 16 + id * 8)))
+    }
+
+    fn is_unk(&self, id: u32) -> bool {
+        id == UNKNOWN || Some(id) == self.unk_id
+    }
+
+    /// Segment one pre-tokenized chunk, calling `emit` with the ID of each
+    /// token and the byte range of `chunk` it covers, in order.
+    fn segment<F: FnMut(u32, usize, usize)>(&self, chunk: &str, emit: &mut F) {
+        let trie = TrieArrays::new(&self.data[self.trie.clone()]);
+        let bytes = chunk.as_bytes();
+        let unreached = Node {
+            score: 0.0,
+            start: usize::MAX,
+            id: UNKNOWN,
+        };
+        let mut lattice = vec![unreached; bytes.len() + 1];
+
+        for (pos, ch) in chunk.char_indices() {
+            let base = lattice[pos].score;
+            let char_len = ch.len_utf8();
+            let mut covered = false;
+            // Ties keep the earlier (shorter) piece, like HF.
+            trie.prefixes(bytes, pos, |len, id| {
+                let Some(score) = self.score(id) else { return };
+                let node = &mut lattice[pos + len];
+                let candidate = base + score;
+                if node.start == usize::MAX || candidate > node.score {
+                    *node = Node {
+                        score: candidate,
+                        start: pos,
+                        id,
+                    };
+                }
+                covered |= len == char_len;
+            });
+            if !covered {
+                let node = &mut lattice[pos + char_len];
+                let candidate = base + self.unk_score;
+                if node.start == usize::MAX || candidate > node.score {
+                    *node = Node {
+                        score: candidate,
+                        start: pos,
+                        id: UNKNOWN,
+                    };
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut end = bytes.len();
+        while end > 0 {
+            let node = lattice[end];
+            path.push((node.id, node.start, end));
+            end = node.start;
+        }
+        path.reverse();
+
+        let mut i = 0;
+        while i < path.len() {
+            let (id, start, mut end) = path[i];
+            i += 1;
+            if !self.is_unk(id) {
+                emit(id, start, end);
+                continue;
+            }
+            let fused = i < path.len() && self.is_unk(path[i].0);
+            while i < path.len() && self.is_unk(path[i].0) {
+                end = path[i].2;
+                i += 1;
+            }
+            if id != UNKNOWN && !fused {
+                // The unk piece itself, matched in the text.
+                emit(id, start, end);
+            } else {
+                self.emit_unknown(&bytes[start..end], start, end, emit);
+            }
+        }
+    }
+
+    /// Emit a run of unknown text as `<0xNN>` byte pieces when byte fallback
+    /// covers all its bytes, else as the unk token; without one it is dropped.
+    fn emit_unknown<F: FnMut(u32, usize, usize)>(
+        &self,
+        text: &[u8],
+        start: usize,
+        end: usize,
+        emit: &mut F,
+    ) {
+        if self.pipeline.byte_fallback && text.iter().all(|&b| self.byte_ids[b as usize].is_some())
+        {
+            for id in text.iter().filter_map(|&b| self.byte_ids[b as usize]) {
+                emit(id, start, end);
+            }
+            return;
+        }
+        if let Some(unk) = self.unk_id {
+            emit(unk, start, end);
+        }
+    }
+}
+
+/// Scores section layout: `[unk_id][count][min score]` then `count` f64s.
+pub(crate) fn check_scores(scores: &[u8]) -> Result<(), String> {
+    let count = frozen::get_u32(scores, 4)? as usize;
+    if count.checked_mul(8).and_then(|n| n.checked_add(16)) != Some(scores.len()) {
+        return Err(format!(
+            "malformed scores: {} pieces in {} bytes",
+            count,
+            scores.len()
+        ));
+    }
+    Ok(())
+}
+
+/// One-line summary of a scores section, for `inspect`.
+pub(crate) fn describe_scores(scores: &[u8]) -> Result<String, String> {
+    check_scores(scores)?;
+    let unk_id = frozen::read_u32(scores, 0);
+    let count = frozen::read_u32(scores, 4);
+    let min_score = f64::from_bits(frozen::read_u64(scores, 8));
+    let unk = if unk_id == UNKNOWN {
+        "no unk".to_string()
+    } else {
+        format!("unk {}", unk_id)
+    };
+    Ok(format!(
+        "{} pieces, min score {:.4}, {}",
+        count, min_score, unk
+    ))
+}
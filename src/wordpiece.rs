@@ -0,0 +1,212 @@
+//! WordPiece model from a Hugging Face `tokenizer.json`, as used by BERT
+//! and its embedding-model descendants.
+//!
+//! Each pre-tokenized word is split greedily into the longest vocab pieces
+//! from the left, every piece after the first carrying the continuation
+//! prefix (`##`). Words longer than `max_input_chars_per_word` characters,
+//! or with a remainder no piece matches, become a single unk token.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::claude::{check_trie, TrieArrays};
+use crate::format::{self, Header};
+use crate::frozen;
+use crate::pipeline::{Piece, Pipeline};
+use crate::template::ChatTemplate;
+use crate::{SpecialTokens, TokenSpan};
+
+/// The parameters section's `unk_id` when the unk token is not in the vocab.
+const NO_UNK: u32 = u32::MAX;
+
+pub struct WordPieceTokenizer<'a> {
+    data: Cow<'a, [u8]>,
+    pipeline: Pipeline,
+    trie: Range<usize>,
+    unk_id: Option<u32>,
+    max_chars: usize,
+    prefix: String,
+    id_tokens_off: usize,
+}
+
+impl<'a> WordPieceTokenizer<'a> {
+    pub fn from_frozen(data: impl Into<Cow<'a, [u8]>>) -> Result<Self, String> {
+        let data = data.into();
+        let header = Header::parse_kind(&data, format::KIND_HF_WORDPIECE)?;
+        let pipeline = Pipeline::from_frozen(&header, &data)?;
+        let trie = header.checked_section(format::SEC_TRIE, check_trie)?;
+        let params = header.section(format::SEC_WORDPIECE)?;
+        let (unk_id, max_chars, prefix) = parse_params(&data[params]).map_err(|e| {
+            format!(
+                "{} section: {}",
+                format::section_name(format::SEC_WORDPIECE),
+                e
+            )
+        })?;
+        let id_tokens_off = header
+            .checked_section(format::SEC_ID_TOKENS, frozen::check_string_array)?
+            .start;
+
+        Ok(WordPieceTokenizer {
+            data,
+            pipeline,
+            trie,
+            unk_id,
+            max_chars,
+            prefix,
+            id_tokens_off,
+        })
+    }
+
+    pub fn set_special_tokens(&mut self, mode: SpecialTokens) {
+        self.pipeline.set_special_tokens(mode);
+    }
+
+    pub fn find_special_token(&self, text: &str) -> Option<(usize, usize)> {
+        self.pipeline.find_special_token(text)
+    }
+
+    pub fn set_add_special_tokens(&mut self, add: bool) {
+        self.pipeline.set_add_special_tokens(add);
+    }
+
+    pub fn chat_template(&self) -> Option<&ChatTemplate> {
+        self.pipeline.chat_template()
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        self.pipeline.post_count(false) + self.count_text(text)
+    }
+
+    pub fn count_pair_tokens(&self, a: &str, b: &str) -> usize {
+        self.pipeline.post_count(true) + self.count_text(a) + self.count_text(b)
+    }
+
+    /// Tokens of `text` alone, without the post-processor's.
+    fn count_text(&self, text: &str) -> usize {
+        let mut total = 0;
+        let mut pieces = Vec::new();
+        for piece in self.pipeline.pre_tokenize(text, false) {
+            match piece {
+                Piece::Added(_) => total += 1,
+                Piece::Text(chunk) => {
+                    self.word_pieces(&chunk.text, &mut pieces);
+                    total += pieces.len();
+                }
+            }
+        }
+        total
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets_impl(text, false)
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        self.encode_with_offsets_impl(text, true)
+    }
+
+    pub fn encode_pair(&self, a: &str, b: &str) -> Vec<u32> {
+        self.pipeline.encode_pair(
+            |spans| self.encode_text(a, false, spans),
+            |spans| self.encode_text(b, false, spans),
+        )
+    }
+
+    fn encode_with_offsets_impl(&self, text: &str, track_offsets: bool) -> Vec<TokenSpan> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.pipeline.encode_single(text.len(), |spans| {
+            self.encode_text(text, track_offsets, spans)
+        })
+    }
+
+    /// Encode `text` alone into `spans`, without the post-processor's tokens.
+    fn encode_text(&self, text: &str, track_offsets: bool, spans: &mut Vec<TokenSpan>) {
+        let mut pieces = Vec::new();
+        for piece in self.pipeline.pre_tokenize(text, track_offsets) {
+            let chunk = match piece {
+                Piece::Added(span) => {
+                    spans.push(span);
+                    continue;
+                }
+                Piece::Text(chunk) => chunk,
+            };
+            self.word_pieces(&chunk.text, &mut pieces);
+            spans.extend(pieces.iter().map(|&(id, s, e)| {
+                let (start, end) = chunk.original_range(s, e);
+                TokenSpan { id, start, end }
+            }));
+        }
+    }
+
+    pub fn decode_bytes(&self, ids: &[u32]) -> Vec<u8> {
+        let id_tokens = &self.data[self.id_tokens_off..];
+        let tokens = ids
+            .iter()
+            .filter_map(|&id| frozen::string_array_get(id_tokens, id as usize))
+            .map(|t| t.to_vec())
+            .collect();
+        self.pipeline.decode(tokens)
+    }
+
+    /// Split one word into `(id, start, end)` pieces, replacing the contents
+    /// of `out`. A word that cannot be split is the unk token, or nothing
+    /// when the vocab has none.
+    fn word_pieces(&self, word: &str, out: &mut Vec<(u32, usize, usize)>) {
+        out.clear();
+        if word.chars().count() <= self.max_chars {
+            let trie = TrieArrays::new(&self.data[self.trie.clone()]);
+            let bytes = word.as_bytes();
+            let mut start = 0;
+            while start < bytes.len() {
+                let prefix = if start == 0 {
+                    &b""[..]
+                } else {
+                    self.prefix.as_bytes()
+                };
+                match trie.longest_match(prefix, bytes, start) {
+                    Some((len, id)) => {
+                        out.push((id, start, start + len));
+                        start += len;
+                    }
+                    None => break,
+                }
+            }
+            if start == bytes.len() {
+                return;
+            }
+            out.clear();
+        }
+        if let Some(unk) = self.unk_id {
+            out.push((unk, 0, word.len()));
+        }
+    }
+}
+
+fn parse_params(params: &[u8]) -> Result<(Option<u32>, usize, String), String> {
+    let unk_id = Some(frozen::get_u32(params, 0)?).filter(|&id| id != NO_UNK);
+    let max_chars = frozen::get_u32(params, 4)? as usize;
+    let (prefix, len) = frozen::get_str(params, 8)?;
+    if 8 + len != params.len() {
+        return Err(format!("{} trailing bytes", params.len() - 8 - len));
+    }
+    Ok((unk_id, max_chars, prefix))
+}
+
+/// One-line summary of a parameters section, for `inspect`.
+pub(crate) fn describe_params(params: &[u8]) -> Result<String, String> {
+    let (unk_id, max_chars, prefix) = parse_params(params)?;
+    let unk = unk_id.map_or("no unk".to_string(), |id| format!("unk {}", id));
+    Ok(format!(
+        "prefix {:?}, max {} chars per word, {}",
+        prefix, max_chars, unk
+    ))
+}
@@ -1,25 +1,33 @@
 #!/usr/bin/env node
 // Integration tests for the tokencount CLI
-// Run: npm run test:cli  (after npm run build:cli)
+// Run: npm run test:cli  (after npm run build:cli, and cargo build --release
+// for the Rust CLI tests; TOKENCOUNT_BIN overrides the binary's path)
 
 import { execSync } from "node:child_process";
 import fs from "node:fs";
+import os from "node:os";
 import path from "node:path";
 import { fileURLToPath } from "node:url";
 
 const __filename = fileURLToPath(import.meta.url);
 const ROOT = path.resolve(path.dirname(__filename), "..");
 const CLI = path.join(ROOT, "dist", "tokencount.mjs");
+const BIN = process.env.TOKENCOUNT_BIN || path.join(ROOT, "target", "release", "tokencount");
 
 let passed = 0;
 let failed = 0;
 
-function run(args, { input, expectExit = 0 } = {}) {
+function run(args, options) {
+  return exec(`node "${CLI}"`, args, options);
+}
+
+function exec(command, args, { input, expectExit = 0, cwd = ROOT, env } = {}) {
   try {
-    const result = execSync(`node "${CLI}" ${args}`, {
-      cwd: ROOT,
+    const result = execSync(`${command} ${args}`, {
+      cwd,
       encoding: "utf8",
       input,
+      env,
       timeout: 30000,
       stdio: ["pipe", "pipe", "pipe"],
     });
@@ -204,5 +212,628 @@ test("--share respects TOKEN_COUNT_URL env var", () => {
   assert(result.includes("http://localhost:8000/?b="), `expected custom base URL, got: ${result.trim()}`);
 });
 
+// ---------------------------------------------------------------------------
+// Rust CLI (src/main.rs)
+//
+// Models other than claude come from small generated files in
+// TOKEN_COUNT_MODELS, so the binary must be built without embedding real
+// ones: the byte-level models count one token per UTF-8 byte.
+
+const rustCli = fs.existsSync(BIN);
+const FIXTURES = rustCli ? fs.mkdtempSync(path.join(os.tmpdir(), "tokencount-cli-")) : "";
+const MODELS = path.join(FIXTURES, "models");
+
+function rustTest(name, fn) {
+  if (rustCli) test(name, fn);
+}
+
+function runBin(args, { env, ...options } = {}) {
+  return exec(`"${BIN}"`, args, {
+    cwd: FIXTURES,
+    env: { ...process.env, TOKEN_COUNT_MODELS: MODELS, ...env },
+    ...options,
+  });
+}
+
+/** First count of a text report line ("      11 a.txt"). */
+function count(out) {
+  return parseInt(out.trim().split(/\s+/)[0], 10);
+}
+
+function writeFixture(name, content) {
+  const file = path.join(FIXTURES, name);
+  fs.mkdirSync(path.dirname(file), { recursive: true });
+  const data = typeof content === "string" || Buffer.isBuffer(content) ? content : JSON.stringify(content);
+  fs.writeFileSync(file, data);
+  return name;
+}
+
+// GPT-2's byte-to-unicode table: printable stand-ins for the 256 bytes.
+const BYTE_VOCAB = {};
+for (let b = 0, n = 0; b < 256; b++) {
+  const printable = (b >= 0x21 && b <= 0x7e) || (b >= 0xa1 && b <= 0xac) || (b >= 0xae && b <= 0xff);
+  BYTE_VOCAB[String.fromCodePoint(printable ? b : 256 + n++)] = b;
+}
+
+/** A tokenizer.json with `model` and any other top-level keys in `rest`. */
+function tokenizerJson({ added = [], ...rest }) {
+  return {
+    added_tokens: added.map(([id, content]) => ({
+      id,
+      content,
+      special: true,
+      single_word: false,
+      lstrip: false,
+      rstrip: false,
+      normalized: false,
+    })),
+    ...rest,
+  };
+}
+
+/** Byte-level BPE without merges: one token per byte. */
+function byteLevel(added = []) {
+  return tokenizerJson({
+    added: added.map((content, i) => [256 + i, content]),
+    pre_tokenizer: { type: "ByteLevel", add_prefix_space: false, trim_offsets: true, use_regex: false },
+    decoder: { type: "ByteLevel", add_prefix_space: false, trim_offsets: true, use_regex: false },
+    model: { type: "BPE", vocab: BYTE_VOCAB, merges: [] },
+  });
+}
+
+/**
+ * BPE over whole words: "hello", "world" and the other vocab entries are
+ * one token each, any other letter is one token, anything else is [UNK].
+ */
+function wordModel(pipeline = {}, model = {}) {
+  const vocab = { "[UNK]": 0 };
+  for (const token of ["hello", "world", "fi", "123", ..."abcdefghijklmnopqrstuvwxyz0123456789"]) {
+    vocab[token] = Object.keys(vocab).length;
+  }
+  return tokenizerJson({
+    pre_tokenizer: { type: "WhitespaceSplit" },
+    ...pipeline,
+    model: { type: "BPE", vocab, merges: [], ignore_merges: true, unk_token: "[UNK]", ...model },
+  });
+}
+
+if (rustCli) {
+  const ranks = Array.from({ length: 256 }, (_, b) => `${Buffer.from([b]).toString("base64")} ${b}\n`);
+  for (const encoding of ["o200k_base", "cl100k_base", "p50k_base", "r50k_base"]) {
+    writeFixture(`models/${encoding}.tiktoken`, ranks.join(""));
+  }
+  for (const model of ["gemini", "deepseek", "llama", "mistral", "grok", "minimax"]) {
+    writeFixture(`models/${model}/tokenizer.json`, byteLevel());
+  }
+  // Qwen gets ChatML special tokens and a chat template.
+  writeFixture("models/qwen/tokenizer.json", byteLevel(["<|im_start|>", "<|im_end|>"]));
+  writeFixture("models/qwen/tokenizer_config.json", {
+    chat_template:
+      "{% for m in messages %}<|im_start|>{{ m.role }}\n{{ m.content }}<|im_end|>\n{% endfor %}" +
+      "{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}",
+  });
+  writeFixture("hello.txt", "hello world");
+  writeFixture("accent.txt", "h\u00e9llo\n");
+  console.log("\nRust CLI integration tests\n");
+} else {
+  console.log(`\nSkipping Rust CLI tests: ${BIN} not found (run cargo build --release)`);
+}
+
+rustTest("counts files and stdin", () => {
+  assert(count(runBin("hello.txt")) === 2, "hello.txt should be 2 claude tokens");
+  assert(count(runBin("", { input: "hello world" })) === 2, "stdin should count the same");
+});
+
+rustTest("-m: unknown model lists the available ones", () => {
+  const out = runBin("-m gpt5 hello.txt", { expectExit: 1 });
+  assert(out.includes("unknown model 'gpt5'"), `unexpected error: ${out}`);
+  assert(out.includes("Available: claude"), "should list the models");
+});
+
+rustTest("claude: bytes outside the vocabulary are one token each", () => {
+  writeFixture("private.txt", "a\u{10ffff}a");
+  assert(count(runBin("private.txt")) === 6, "a, the four bytes of U+10FFFF, a");
+});
+
+rustTest("decode: token IDs from arguments", () => {
+  assert(runBin("decode -m openai 104 105") === "hi", "should decode 104 105 to 'hi'");
+});
+
+rustTest("decode: every integer on stdin", () => {
+  const out = runBin("decode -m qwen", { input: "[104, 101, 121]\n" });
+  assert(out === "hey", `expected 'hey', got '${out}'`);
+});
+
+rustTest("decode: --model-file", () => {
+  const model = writeFixture("byte/tokenizer.json", byteLevel());
+  assert(runBin(`decode --model-file ${model} 111 107`) === "ok", "should decode with the model file");
+});
+
+rustTest("decode: invalid token ID exits with error", () => {
+  const out = runBin("decode -m openai 12x", { expectExit: 1 });
+  assert(out.includes("invalid token ID: 12x"), "should name the invalid ID");
+});
+
+rustTest("-t: marks token boundaries", () => {
+  assert(runBin("-t -m openai hello.txt").trimEnd() === "h|e|l|l|o| |w|o|r|l|d", "byte-level tokens");
+  assert(runBin("-t hello.txt").trimEnd() === "hello| world", "claude tokens");
+});
+
+rustTest("-t: tokens inside one character are shown together", () => {
+  const out = runBin("-t -m openai accent.txt");
+  assert(out.startsWith("h|é|l|l|o|"), `expected 'h|é|l|l|o|...', got '${out}'`);
+});
+
+rustTest("-t -a: one section per model", () => {
+  const out = runBin("-t -a", { input: "hi" });
+  assert(out.includes("==> stdin (claude) <==\nhi\n"), "should have a claude section");
+  assert(out.includes("==> stdin (qwen) <==\nh|i"), "should have a qwen section");
+});
+
+rustTest("-f json: files and total", () => {
+  const report = JSON.parse(runBin("-f json -m openai hello.txt accent.txt"));
+  assert(report.version === 1, "should have a version");
+  assert(report.files.length === 2, "should list both files");
+  const [hello, accent] = report.files;
+  assert(hello.path === "hello.txt" && hello.tokens === 11, "hello.txt should be 11 tokens");
+  assert(accent.bytes === 7 && accent.chars === 6 && accent.lines === 1, "accent.txt: 7 bytes, 6 chars, 1 line");
+  assert(report.total.path === null && report.total.tokens === 18, "total should be 18 tokens");
+});
+
+rustTest("-f ndjson -a: a tokens map per line", () => {
+  const lines = runBin("-f ndjson -a hello.txt").trim().split("\n").map(JSON.parse);
+  assert(lines.length === 2, "should have a file line and a total line");
+  assert(lines[0].type === "file" && lines[1].type === "total", "file, then total");
+  assert(lines[0].tokens.claude === 2 && lines[0].tokens.qwen === 11, "tokens should map model to count");
+});
+
+rustTest("-f csv: header, quoting and total row", () => {
+  fs.copyFileSync(path.join(FIXTURES, "hello.txt"), path.join(FIXTURES, "a,b.txt"));
+  const rows = runBin('-f csv -m openai "a,b.txt" accent.txt').trim().split("\n");
+  assert(rows[0] === "type,path,bytes,chars,lines,model,tokens", `unexpected header: ${rows[0]}`);
+  assert(rows[1] === 'file,"a,b.txt",11,11,0,openai,11', `unexpected row: ${rows[1]}`);
+  assert(rows[3] === "total,,18,17,1,openai,18", `unexpected total: ${rows[3]}`);
+});
+
+rustTest("-f csv -a: one column per model", () => {
+  const [header] = runBin("-f csv -a hello.txt").split("\n");
+  assert(header.startsWith("type,path,bytes,chars,lines,claude,openai,"), `unexpected header: ${header}`);
+});
+
+rustTest("-f tsv: tab-separated", () => {
+  const rows = runBin("-f tsv -m openai hello.txt").trim().split("\n");
+  assert(rows[1] === "file\thello.txt\t11\t11\t0\topenai\t11", `unexpected row: ${rows[1]}`);
+});
+
+rustTest("-f: unknown format exits with error", () => {
+  const out = runBin("-f xml hello.txt", { expectExit: 1 });
+  assert(out.includes("unknown format 'xml'"), "should name the format");
+});
+
+rustTest("--max-tokens: over budget exits 2", () => {
+  const out = runBin("--max-tokens 10 -m openai hello.txt accent.txt", { expectExit: 2 });
+  assert(out.includes("total: 18 tokens exceeds --max-tokens 10 (openai)"), `unexpected error: ${out}`);
+});
+
+rustTest("--max-tokens: under budget exits 0", () => {
+  assert(count(runBin("--max-tokens 11 -m openai hello.txt")) === 11, "should still print the count");
+});
+
+rustTest("--max-tokens: model=N limits only that model", () => {
+  const out = runBin("-a --max-tokens claude=1 --max-tokens openai=100 hello.txt", { expectExit: 2 });
+  assert(out.includes("(claude)"), "claude should be over its limit");
+  assert(!out.includes("(openai)"), "openai should be under its limit");
+});
+
+rustTest("--max-tokens-per-file: names the file", () => {
+  const out = runBin("--max-tokens-per-file 10 -m openai hello.txt accent.txt", { expectExit: 2 });
+  assert(out.includes("hello.txt: 11 tokens exceeds --max-tokens-per-file 10"), `unexpected error: ${out}`);
+  assert(!out.includes("accent.txt"), "accent.txt is under the limit");
+});
+
+rustTest("--max-tokens: applies to json output", () => {
+  runBin("-f json --max-tokens 5 -m openai hello.txt", { expectExit: 2 });
+});
+
+rustTest("--max-tokens: invalid limits exit 1", () => {
+  assert(runBin("--max-tokens x hello.txt", { expectExit: 1 }).includes("expects N or model=N"), "bad N");
+  const out = runBin("--max-tokens openai=5 hello.txt", { expectExit: 1 });
+  assert(out.includes("not a selected model"), "limit for a model that is not counted");
+});
+
+rustTest("TOKEN_COUNT_MODELS: -a loads every model at runtime", () => {
+  const lines = runBin("-a hello.txt").trim().split("\n");
+  assert(lines.length === 13, `expected 13 models, got ${lines.length}`);
+  assert(count(runBin("-m qwen hello.txt")) === 11, "qwen should count bytes");
+});
+
+rustTest("TOKEN_COUNT_MODELS: missing model exits with error", () => {
+  const env = { TOKEN_COUNT_MODELS: path.join(FIXTURES, "none") };
+  const out = runBin("-m openai hello.txt", { expectExit: 1, env });
+  assert(out.includes("openai model not embedded"), `unexpected error: ${out}`);
+  assert(count(runBin("hello.txt", { env })) === 2, "claude is always embedded");
+});
+
+rustTest("--model-file: tokenizer.json and .tiktoken", () => {
+  const env = { TOKEN_COUNT_MODELS: "" };
+  for (const model of ["models/qwen/tokenizer.json", "models/cl100k_base.tiktoken"]) {
+    assert(count(runBin(`--model-file ${model} hello.txt`, { env })) === 11, `${model} should count bytes`);
+  }
+  const out = runBin("--model-file missing.json hello.txt", { expectExit: 1 });
+  assert(out.includes("cannot read missing.json"), `unexpected error: ${out}`);
+});
+
+rustTest("frozen files: truncated or foreign files are errors", () => {
+  runBin("freeze models/qwen/tokenizer.json qwen.frozen");
+  const frozen = fs.readFileSync(path.join(FIXTURES, "qwen.frozen"));
+  writeFixture("truncated.frozen", frozen.subarray(0, 100));
+  let out = runBin("inspect truncated.frozen", { expectExit: 1 });
+  assert(out.includes("file truncated?") && !out.includes("panicked"), `unexpected error: ${out}`);
+  out = runBin("--model-file truncated.frozen hello.txt", { expectExit: 1 });
+  assert(out.includes("invalid truncated.frozen model"), `unexpected error: ${out}`);
+  writeFixture("foreign.frozen", "hello\n");
+  out = runBin("inspect foreign.frozen", { expectExit: 1 });
+  assert(out.includes("not a frozen model (bad magic)"), `unexpected error: ${out}`);
+});
+
+rustTest("freeze: names the model after its directory", () => {
+  const out = runBin("freeze models/qwen/tokenizer.json qwen.frozen");
+  assert(out.includes("qwen.frozen: hf-bpe model 'qwen'"), `unexpected output: ${out}`);
+  const info = runBin("inspect qwen.frozen");
+  assert(/^name +qwen$/m.test(info), "inspect should show the name");
+  assert(/^added-tokens +2 tokens/m.test(info), "inspect should show the added tokens");
+  assert(/^chat-template +\d+ bytes/m.test(info), "the tokenizer_config.json template should be kept");
+});
+
+rustTest("freeze: a rank file's encoding comes from its name", () => {
+  runBin("freeze models/p50k_base.tiktoken p50k.frozen");
+  assert(/^encoding +p50k_base$/m.test(runBin("inspect p50k.frozen")), "p50k_base from the file stem");
+  runBin("freeze --name custom models/p50k_base.tiktoken custom.frozen");
+  const info = runBin("inspect custom.frozen");
+  assert(/^name +custom$/m.test(info), "--name should be recorded");
+  assert(/^encoding +o200k_base$/m.test(info), "other names default to o200k_base");
+});
+
+rustTest("freeze: usage errors", () => {
+  const out = runBin("freeze hello.txt", { expectExit: 1 });
+  assert(out.includes("freeze expects an input and an output path"), `unexpected error: ${out}`);
+  assert(runBin("freeze --help").startsWith("Usage: tokencount freeze"), "--help should print the usage");
+});
+
+rustTest("--model-file: a frozen file counts like its source", () => {
+  writeFixture("special.txt", "<|im_start|>user\nhéllo<|im_end|>\n");
+  for (const [source, frozen] of [
+    ["models/qwen/tokenizer.json", "qwen.frozen"],
+    ["models/cl100k_base.tiktoken", "cl100k.frozen"],
+  ]) {
+    runBin(`freeze ${source} ${frozen}`);
+    for (const file of ["hello.txt", "accent.txt", "special.txt"]) {
+      const expected = runBin(`--model-file ${source} ${file}`);
+      assert(runBin(`--model-file ${frozen} ${file}`) === expected, `${frozen} should count ${file} like ${source}`);
+    }
+  }
+  assert(runBin("decode --model-file qwen.frozen 104 105") === "hi", "a frozen file should decode");
+});
+
+rustTest("--special: added tokens of a tokenizer.json", () => {
+  writeFixture("im_start.txt", "<|im_start|>hi");
+  assert(count(runBin("-m qwen im_start.txt")) === 3, "allow: <|im_start|> is one token");
+  assert(count(runBin("-m qwen --special plain im_start.txt")) === 14, "plain: counted as text");
+  const out = runBin("-m qwen --special error im_start.txt", { expectExit: 1 });
+  assert(out.includes("special token '<|im_start|>' for qwen at byte 0"), `unexpected error: ${out}`);
+  assert(runBin("-m qwen --special bogus im_start.txt", { expectExit: 1 }).includes("unknown --special mode"), "bad mode");
+});
+
+rustTest("--special: o200k special tokens", () => {
+  writeFixture("endoftext.txt", "<|endoftext|>");
+  assert(count(runBin("-m openai endoftext.txt")) === 1, "allow: <|endoftext|> is one token");
+  assert(count(runBin("-m openai --special plain endoftext.txt")) === 13, "plain: counted as text");
+  runBin("-m openai --special error endoftext.txt", { expectExit: 1 });
+  writeFixture("markers.txt", "<|fim_prefix|><|endofprompt|><|im_start|>");
+  let out = runBin("-t -m openai markers.txt").trimEnd();
+  assert(out.startsWith("<|||f|i|m"), `<|fim_prefix|> is not special: ${out}`);
+  assert(out.includes("|<|endofprompt|>|<|||i|m"), `only <|endofprompt|> is special: ${out}`);
+  out = runBin("-t -m openai --chat-markers markers.txt").trimEnd();
+  assert(out.endsWith("|<|endofprompt|>|<|im_start|>"), `--chat-markers: <|im_start|> is special: ${out}`);
+});
+
+rustTest("--allowed-special: only the listed special tokens", () => {
+  writeFixture("two.txt", "<|endoftext|><|endofprompt|>");
+  assert(count(runBin("-m openai --allowed-special '<|endoftext|>' two.txt")) === 1 + 15, "the other is text");
+  assert(count(runBin("-m openai --special error --allowed-special '<|endoftext|>' endoftext.txt")) === 1, "allowed");
+  const out = runBin("-m openai --special error --allowed-special '<|endoftext|>' two.txt", { expectExit: 1 });
+  assert(out.includes("special token '<|endofprompt|>' for openai at byte 13"), `unexpected error: ${out}`);
+});
+
+rustTest("-m: each OpenAI encoding has its own special tokens", () => {
+  writeFixture("specials.txt", "<|fim_prefix|><|start|><|endoftext|>");
+  const tokens = (model) => count(runBin(`-m ${model} specials.txt`));
+  // 14 + 9 bytes of text, or 1 token each when special.
+  assert(tokens("cl100k") === 1 + 9 + 1, "cl100k: <|fim_prefix|> and <|endoftext|>");
+  assert(tokens("o200k-harmony") === 14 + 1 + 1, "o200k-harmony: <|start|> and <|endoftext|>");
+  assert(tokens("p50k") === 14 + 9 + 1, "p50k: <|endoftext|> only");
+  assert(tokens("r50k") === 14 + 9 + 1, "r50k: <|endoftext|> only");
+});
+
+rustTest("normalizers: Lowercase, NFKC and NFD + StripAccents", () => {
+  writeFixture("normalize.txt", "HELLO ﬁ héllo world");
+  const tokens = (normalizer) => {
+    writeFixture("normalizer/tokenizer.json", wordModel({ normalizer }));
+    return runBin("-t --model-file normalizer/tokenizer.json normalize.txt").trimEnd();
+  };
+  assert(tokens(undefined) === "H|E|L|L|O |ﬁ |h|é|l|l|o |world", "no normalizer");
+  assert(tokens({ type: "Lowercase" }).startsWith("HELLO |"), "Lowercase makes HELLO a word");
+  assert(tokens({ type: "NFKC" }).includes("O |ﬁ |"), "NFKC maps the ligature to fi");
+  const stripped = tokens({ type: "Sequence", normalizers: [{ type: "NFD" }, { type: "StripAccents" }] });
+  assert(stripped.includes("|héllo |"), "NFD + StripAccents makes héllo hello");
+});
+
+rustTest("pre-tokenizers: Digits, Whitespace, Punctuation and UnicodeScripts", () => {
+  const tokens = (preTokenizer, text) => {
+    const pre_tokenizer = { type: "Sequence", pretokenizers: [{ type: "WhitespaceSplit" }, preTokenizer] };
+    writeFixture("pre/tokenizer.json", wordModel({ pre_tokenizer }));
+    return runBin("-t --model-file pre/tokenizer.json", { input: text }).trimEnd();
+  };
+  assert(tokens({ type: "Digits", individual_digits: false }, "hello123") === "hello|123", "digit runs");
+  assert(tokens({ type: "Digits", individual_digits: true }, "hello123") === "hello|1|2|3", "single digits");
+  assert(tokens({ type: "Whitespace" }, "hello-world") === "hello|-|world", "word/non-word runs");
+  const punctuation = { type: "Punctuation", behavior: "Isolated" };
+  assert(tokens(punctuation, "hello,world") === "hello|,|world", "punctuation");
+  assert(tokens({ type: "UnicodeScripts" }, "helloпр") === "hello|п|р", "script changes");
+});
+
+rustTest("pre-tokenizers: Split behaviors", () => {
+  const tokens = (behavior) => {
+    const pre_tokenizer = { type: "Split", pattern: { String: "-" }, behavior, invert: false };
+    writeFixture("split/tokenizer.json", wordModel({ pre_tokenizer }));
+    return runBin("-t --model-file split/tokenizer.json", { input: "hello--world" }).trimEnd();
+  };
+  assert(tokens("Removed") === "hello--|world", "Removed drops the dashes");
+  assert(tokens("Isolated") === "hello|-|-|world", "Isolated keeps each dash");
+  assert(tokens("MergedWithPrevious") === "h|e|l|l|o|-|-|world", "hello- is not a word");
+  assert(tokens("MergedWithNext") === "hello|-|-|w|o|r|l|d", "-world is not a word");
+});
+
+rustTest("pre-tokenizers: ByteLevel add_prefix_space", () => {
+  const model = byteLevel();
+  model.pre_tokenizer.add_prefix_space = true;
+  writeFixture("prefix/tokenizer.json", model);
+  assert(count(runBin("--model-file prefix/tokenizer.json hello.txt")) === 12, "a space is added");
+  assert(count(runBin("--model-file prefix/tokenizer.json", { input: " hello world" })) === 12, "but not doubled");
+});
+
+rustTest("Unigram: Viterbi pieces with Metaspace", () => {
+  const metaspace = { type: "Metaspace", replacement: "▁", prepend_scheme: "always", split: true };
+  const vocab = [["<unk>", 0], ["▁", -1], ["▁he", -2], ["llo", -2.5], ["h", -3], ["e", -3], ["l", -3], ["o", -3]];
+  const model = { type: "Unigram", unk_id: 0, vocab, byte_fallback: false };
+  writeFixture("unigram/tokenizer.json", tokenizerJson({ pre_tokenizer: metaspace, decoder: metaspace, model }));
+  const out = runBin("-t --model-file unigram/tokenizer.json", { input: "hello hello" }).trimEnd();
+  assert(out === "he|llo| he|llo", `expected 'he|llo| he|llo', got '${out}'`);
+  assert(count(runBin("--model-file unigram/tokenizer.json", { input: "hex" })) === 2, "x is <unk>");
+  assert(runBin("decode --model-file unigram/tokenizer.json 2 3 1 2 3") === "hello  hello", "decode");
+});
+
+rustTest("WordPiece: greedy subwords, [UNK] words and [CLS]/[SEP]", () => {
+  const vocab = { "[CLS]": 0, "[SEP]": 1, "[UNK]": 2, hel: 3, "##lo": 4, h: 5, "##e": 6 };
+  const tokenizer = tokenizerJson({
+    added: [[0, "[CLS]"], [1, "[SEP]"]],
+    normalizer: { type: "BertNormalizer", clean_text: true, handle_chinese_chars: true, lowercase: true },
+    pre_tokenizer: { type: "BertPreTokenizer" },
+    post_processor: { type: "BertProcessing", sep: ["[SEP]", 1], cls: ["[CLS]", 0] },
+    decoder: { type: "WordPiece", prefix: "##", cleanup: true },
+    model: { type: "WordPiece", unk_token: "[UNK]", continuing_subword_prefix: "##", vocab },
+  });
+  writeFixture("wordpiece/tokenizer.json", tokenizer);
+  const model = "--model-file wordpiece/tokenizer.json";
+  assert(runBin(`-t ${model}`, { input: "Hello hexyz" }).trimEnd() === "Hel|lo |hexyz", "hel ##lo [UNK]");
+  assert(count(runBin(model, { input: "Hello hexyz" })) === 5, "[CLS] and [SEP] are counted");
+  assert(count(runBin(`--no-bos-eos ${model}`, { input: "Hello hexyz" })) === 3, "--no-bos-eos drops them");
+  assert(runBin(`decode ${model} 3 4 5 6`) === "hello he", "## pieces join the word before them");
+});
+
+rustTest("BPE: ignore_merges, affixes and unk_token", () => {
+  const tokens = (vocab, merges, options) => {
+    const model = { type: "BPE", vocab, merges, unk_token: "[UNK]", ...options };
+    writeFixture("bpe/tokenizer.json", tokenizerJson({ pre_tokenizer: { type: "WhitespaceSplit" }, model }));
+    return runBin("-t --model-file bpe/tokenizer.json", { input: "hello hex" }).trimEnd();
+  };
+  const vocab = { "[UNK]": 0, h: 1, e: 2, l: 3, o: 4, he: 5, ll: 6, hello: 7 };
+  assert(tokens(vocab, ["h e", "l l"], {}) === "he|ll|o |he|x", "merges stop at he + ll + o");
+  assert(tokens(vocab, ["h e", "l l"], { ignore_merges: true }) === "hello |he|x", "hello is in the vocab");
+  const suffixed = { "[UNK]": 0, h: 1, e: 2, l: 3, "o</w>": 4, he: 5, ll: 6, "llo</w>": 7 };
+  const suffix = tokens(suffixed, ["h e", "l l", "ll o</w>"], { end_of_word_suffix: "</w>" });
+  assert(suffix === "he|llo |he|x", "end_of_word_suffix marks the last piece");
+  const prefixed = { "[UNK]": 0, h: 1, "##e": 2, "##l": 3, "##o": 4, he: 5, "##ll": 6 };
+  const prefix = tokens(prefixed, ["h ##e", "##l ##l"], { continuing_subword_prefix: "##" });
+  assert(prefix === "he|ll|o |he|x", "continuing_subword_prefix marks the other pieces");
+});
+
+rustTest("--no-bos-eos: TemplateProcessing tokens", () => {
+  const model = byteLevel(["<s>"]);
+  model.post_processor = {
+    type: "TemplateProcessing",
+    single: [{ SpecialToken: { id: "<s>", type_id: 0 } }, { Sequence: { id: "A", type_id: 0 } }],
+    pair: [{ Sequence: { id: "A", type_id: 0 } }, { Sequence: { id: "B", type_id: 1 } }],
+    special_tokens: { "<s>": { id: "<s>", ids: [256], tokens: ["<s>"] } },
+  };
+  writeFixture("bos/tokenizer.json", model);
+  assert(count(runBin("--model-file bos/tokenizer.json hello.txt")) === 12, "<s> is counted");
+  assert(count(runBin("--no-bos-eos --model-file bos/tokenizer.json hello.txt")) === 11, "--no-bos-eos drops <s>");
+  const out = runBin("-t --model-file bos/tokenizer.json hello.txt").trimEnd();
+  assert(out === "h|e|l|l|o| |w|o|r|l|d", `-t shows only the input's tokens: ${out}`);
+});
+
+rustTest("--chat: per-message counts with framing", () => {
+  const ping = { type: "function", function: { name: "ping", description: "Check", parameters: {} } };
+  writeFixture("chat.json", {
+    messages: [
+      { role: "system", content: "be brief" },
+      { role: "user", content: "hi" },
+    ],
+    tools: [ping],
+  });
+  const lines = runBin("--chat openai -m openai chat.json").trim().split("\n").map((l) => l.trim());
+  assert(lines[0] === "17 [0] system (+9 framing)", `unexpected line: ${lines[0]}`);
+  assert(lines[1] === "9 [1] user (+7 framing)", `unexpected line: ${lines[1]}`);
+  assert(lines[2] === "3 reply priming", `unexpected line: ${lines[2]}`);
+  assert(/^\d+ tool definitions$/.test(lines[3]), "the request's tools should be counted");
+  const total = 17 + 9 + 3 + parseInt(lines[3], 10);
+  assert(lines[4] === `${total} total`, `unexpected total: ${lines[4]}`);
+});
+
+rustTest("--chat: json lists the messages", () => {
+  const [file] = JSON.parse(runBin("--chat openai -m openai -f json chat.json")).files;
+  assert(file.messages.length === 2, "should list both messages");
+  assert(file.messages[1].role === "user" && file.messages[1].tokens === 9, "user message should be 9 tokens");
+  const all = JSON.parse(runBin("--chat openai -a -f json chat.json")).files[0];
+  assert(all.messages.qwen.length === 2, "-a should map each model to its messages");
+});
+
+rustTest("--chat: anthropic system and content blocks", () => {
+  writeFixture("messages.json", {
+    system: "be brief",
+    messages: [{ role: "user", content: [{ type: "text", text: "hi" }] }],
+  });
+  const out = runBin("--chat anthropic -m openai messages.json");
+  assert(/^ +8 \[0\] system \(\+0 framing\)$/m.test(out), `unexpected output: ${out}`);
+  assert(/^ +\d+ \[1\] user \(\+\d+ framing\)$/m.test(out), `unexpected output: ${out}`);
+});
+
+rustTest("--chat: a model's chat template", () => {
+  const lines = runBin("--chat openai -m qwen chat.json").trim().split("\n").map((l) => l.trim());
+  // <|im_start|> + "system\nbe brief" + <|im_end|> + "\n"
+  assert(lines[0] === "18 [0] system (+10 framing)", `unexpected line: ${lines[0]}`);
+  assert(lines[1] === "10 [1] user (+8 framing)", `unexpected line: ${lines[1]}`);
+  assert(lines[2] === "11 reply priming", `<|im_start|>assistant\\n: ${lines[2]}`);
+  assert(lines[3] === "39 total", `the template does not render tools: ${lines[3]}`);
+});
+
+rustTest("--chat: invalid payloads exit with error", () => {
+  const out = runBin("--chat openai", { input: '{"messages": 5}', expectExit: 1 });
+  assert(out.includes('invalid chat payload: expected a "messages" array'), `unexpected error: ${out}`);
+  assert(runBin("--chat bogus chat.json", { expectExit: 1 }).includes("unknown --chat format"), "bad format");
+});
+
+rustTest("--chat: tools rendered by the chat template", () => {
+  writeFixture("qwen2.5/tokenizer.json", byteLevel(["<|im_start|>", "<|im_end|>"]));
+  const template = fs.readFileSync(path.join(ROOT, "tests", "chat_templates", "qwen2.5.jinja"), "utf8");
+  writeFixture("qwen2.5/tokenizer_config.json", { chat_template: template });
+  const out = runBin("--chat openai --model-file qwen2.5/tokenizer.json chat.json");
+  const tools = out.match(/^ +(\d+) tool definitions$/m);
+  assert(tools && parseInt(tools[1], 10) > 0, `the template's tool section should be counted: ${out}`);
+});
+
+rustTest("--tools: one row per tool, then the overhead", () => {
+  const city = { type: "object", properties: { city: { type: "string" } }, required: ["city"] };
+  writeFixture("tools.json", {
+    tools: [
+      { type: "function", function: { name: "ping", description: "Check", parameters: {} } },
+      { type: "function", function: { name: "get_weather", parameters: city } },
+    ],
+  });
+  const lines = runBin("--tools openai -m openai tools.json").trim().split("\n").map((l) => l.trim());
+  // "// Check\ntype ping = () => any;\n\n"
+  assert(lines[0] === "33 tools.json: ping", `unexpected line: ${lines[0]}`);
+  assert(lines[1] === "51 tools.json: get_weather", `unexpected line: ${lines[1]}`);
+  // The namespace functions { ... } wrapper.
+  assert(lines[2] === "70 tools.json: (overhead)", `unexpected line: ${lines[2]}`);
+  assert(lines[3] === "154 total", `unexpected line: ${lines[3]}`);
+});
+
+rustTest("--tools: anthropic definitions", () => {
+  const ping = { name: "ping", description: "Check", input_schema: { type: "object", properties: {} } };
+  const out = runBin("--tools anthropic -m openai", { input: JSON.stringify([ping]) });
+  // <function>{"description": "Check", "name": "ping", "parameters": {...}}</function>
+  assert(/^ +114 ping$/m.test(out), `unexpected output: ${out}`);
+  assert(/^ +24 \(overhead\)$/m.test(out), `unexpected output: ${out}`);
+  const error = runBin("--tools anthropic -m openai tools.json", { expectExit: 1 });
+  assert(error.includes('tool 0: unsupported tool type "function"'), `unexpected error: ${error}`);
+});
+
+rustTest("--tools -a: every model's rows", () => {
+  const out = runBin("--tools openai -a tools.json");
+  assert(/^ +33 tools.json: ping \(openai\)$/m.test(out), "openai row");
+  assert(/^ +\d+ tools.json: get_weather \(claude\)$/m.test(out), "claude row");
+});
+
+rustTest("dataset: nearest-rank percentiles and the largest records", () => {
+  const records = Array.from({ length: 10 }, (_, i) => JSON.stringify({ text: "x".repeat(10 * (i + 1)) }));
+  writeFixture("data.jsonl", records.join("\n") + "\n");
+  const out = runBin("dataset --field text -m openai data.jsonl");
+  for (const [value, label] of [["10", "records"], ["550", "total"], ["55.0", "mean"], ["50", "median"]]) {
+    assert(new RegExp(`^ +${value} ${label}$`, "m").test(out), `expected ${value} ${label}: ${out}`);
+  }
+  assert(/^ +100 p95$/m.test(out) && /^ +100 p99$/m.test(out), "p95 and p99 are the largest record");
+  assert(out.includes("Largest:\n     100 data.jsonl:10\n      90 data.jsonl:9\n"), "largest first");
+});
+
+rustTest("dataset: --max-tokens lists the records over it", () => {
+  const report = JSON.parse(runBin("dataset --field text -m openai --max-tokens 50 -f json data.jsonl"));
+  const [stats] = report.models;
+  assert(stats.model === "openai" && stats.max_tokens === 50, "the limit should be reported");
+  assert(stats.over === 5, `expected 5 records over 50, got ${stats.over}`);
+  assert(stats.offenders.length === 5, "only the records over the limit are offenders");
+  assert(stats.offenders[0].record === "data.jsonl:10" && stats.offenders[0].tokens === 100, "largest first");
+});
+
+rustTest("dataset: chat records count like --chat", () => {
+  const chat = { messages: [{ role: "user", content: "hi" }] };
+  writeFixture("chats.jsonl", JSON.stringify({ id: 1, chat }) + "\n");
+  writeFixture("chat1.json", chat);
+  const out = runBin("dataset --field /chat -m openai chats.jsonl");
+  const expected = count(runBin("--chat openai -m openai chat1.json").trim().split("\n").pop());
+  assert(new RegExp(`^ +${expected} total$`, "m").test(out), `expected ${expected} tokens: ${out}`);
+  const error = runBin("dataset -m openai data.jsonl", { expectExit: 1 });
+  assert(error.includes("data.jsonl:1: invalid chat payload"), `unexpected error: ${error}`);
+});
+
+rustTest("split: contiguous chunks within the limit", () => {
+  const paragraph = "The quick brown fox. ".repeat(6).trimEnd() + "\n\n";
+  const text = paragraph.repeat(4);
+  writeFixture("long.txt", text);
+  const chunks = runBin("split --max-tokens 100 -m openai long.txt").trim().split("\n").map(JSON.parse);
+  assert(chunks.length > 1, "should need more than one chunk");
+  let end = 0;
+  for (const chunk of chunks) {
+    assert(chunk.start === end, `chunk ${chunk.chunk} should start at ${end}, got ${chunk.start}`);
+    assert(chunk.tokens <= 100 && chunk.tokens === chunk.end - chunk.start, `chunk ${chunk.chunk} count`);
+    assert(chunk.text === text.slice(chunk.start, chunk.end), `chunk ${chunk.chunk} text`);
+    assert(chunk.end === text.length || /[ \n]$/.test(chunk.text), `chunk ${chunk.chunk} ends mid-word`);
+    end = chunk.end;
+  }
+  assert(end === text.length, "chunks should cover the file");
+});
+
+rustTest("split: --overlap starts chunks before the previous end", () => {
+  const out = runBin("split --max-tokens 100 --overlap 20 -m openai long.txt");
+  const chunks = out.trim().split("\n").map(JSON.parse);
+  for (let i = 1; i < chunks.length; i++) {
+    const [prev, chunk] = [chunks[i - 1], chunks[i]];
+    assert(chunk.start < prev.end && chunk.start > prev.start, `chunk ${chunk.chunk} should overlap`);
+    assert(chunk.tokens <= 100, `chunk ${chunk.chunk} is over the limit`);
+  }
+  const size = fs.statSync(path.join(FIXTURES, "long.txt")).size;
+  assert(chunks[chunks.length - 1].end === size, "chunks should cover the file");
+});
+
+rustTest("split: -o writes the chunks as files", () => {
+  const out = runBin("split --max-tokens 100 -m openai -o chunks long.txt");
+  const files = fs.readdirSync(path.join(FIXTURES, "chunks")).sort();
+  assert(files[0] === "long-0001.txt", `unexpected file name: ${files[0]}`);
+  assert(out.trim().split("\n").length === files.length, "should list every file");
+  const read = (file) => fs.readFileSync(path.join(FIXTURES, file), "utf8");
+  const joined = files.map((f) => read(path.join("chunks", f))).join("");
+  assert(joined === read("long.txt"), "chunks should rebuild the file");
+});
+
+rustTest("split: invalid limits exit with error", () => {
+  let out = runBin("split -m openai long.txt", { expectExit: 1 });
+  assert(out.includes("split requires --max-tokens with a positive N"), `unexpected error: ${out}`);
+  out = runBin("split --max-tokens 10 --overlap 10 -m openai long.txt", { expectExit: 1 });
+  assert(out.includes("--overlap must be less than --max-tokens"), `unexpected error: ${out}`);
+});
+
+if (rustCli) fs.rmSync(FIXTURES, { recursive: true, force: true });
+
 console.log(`\n${passed} passed, ${failed} failed`);
 if (failed > 0) process.exit(1);